@@ -0,0 +1,135 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::BTreeSet;
+use serde::Serialize;
+use crate::common::{MessageType, MessageKind};
+use super::{Sighting, Percentiles};
+
+/// The message carries the block header of the level
+pub fn carries(ty: &MessageType) -> bool {
+    matches!(
+        ty,
+        MessageType::P2p(MessageKind::CurrentHead)
+            | MessageType::P2p(MessageKind::CurrentBranch)
+            | MessageType::P2p(MessageKind::BlockHeader)
+    )
+}
+
+/// The peer announced the block by `current_head` or `current_branch`
+#[derive(Serialize)]
+pub struct Announcement {
+    pub peer: String,
+    pub message_id: u64,
+    /// nanoseconds since epoch
+    pub timestamp: u64,
+    /// milliseconds since the first announcement
+    pub delay: u64,
+}
+
+/// How the block propagated through the node
+#[derive(Serialize)]
+pub struct BlockPropagation {
+    pub hash: String,
+    /// the first announcement of each peer, in order of time
+    pub announcements: Vec<Announcement>,
+    /// nanoseconds since epoch, when the node sent `get_block_headers` first time
+    pub header_requested: Option<u64>,
+    /// nanoseconds since epoch, when the first peer sent `block_header`
+    pub header_received: Option<u64>,
+    pub header_peer: Option<String>,
+    /// milliseconds from `header_requested` until `header_received`
+    pub header_latency: Option<u64>,
+}
+
+impl BlockPropagation {
+    /// The `sightings` are in order of the message id, that is the order of time
+    pub fn new(hash: String, sightings: &[Sighting]) -> Self {
+        let is =
+            |s: &Sighting, kind: MessageKind| matches!(&s.ty, MessageType::P2p(k) if *k == kind);
+
+        let mut announcements = Vec::<Announcement>::new();
+        let mut first = None;
+        for s in sightings {
+            let announce = is(s, MessageKind::CurrentHead) || is(s, MessageKind::CurrentBranch);
+            if !announce || !s.incoming || announcements.iter().any(|a| a.peer == s.peer) {
+                continue;
+            }
+            let first = *first.get_or_insert(s.timestamp);
+            announcements.push(Announcement {
+                peer: s.peer.clone(),
+                message_id: s.message_id,
+                timestamp: s.timestamp * 1_000_000,
+                delay: s.timestamp.saturating_sub(first),
+            });
+        }
+        let requested = sightings
+            .iter()
+            .find(|s| !s.incoming && is(s, MessageKind::GetBlockHeaders));
+        let received = sightings
+            .iter()
+            .find(|s| s.incoming && is(s, MessageKind::BlockHeader));
+        let header_latency = match (requested, received) {
+            (Some(requested), Some(received)) if requested.message_id < received.message_id => {
+                Some(received.timestamp.saturating_sub(requested.timestamp))
+            },
+            _ => None,
+        };
+
+        BlockPropagation {
+            hash,
+            announcements,
+            header_requested: requested.map(|s| s.timestamp * 1_000_000),
+            header_received: received.map(|s| s.timestamp * 1_000_000),
+            header_peer: received.map(|s| s.peer.clone()),
+            header_latency,
+        }
+    }
+}
+
+/// The blocks of the level, there might be several on different branches
+#[derive(Serialize)]
+pub struct BlockReport {
+    pub level: u32,
+    pub blocks: Vec<BlockPropagation>,
+}
+
+/// The propagation of the blocks of the levels in the time window
+#[derive(Serialize)]
+pub struct BlocksSummary {
+    pub levels: usize,
+    pub first_level: Option<u32>,
+    pub last_level: Option<u32>,
+    pub blocks: usize,
+    /// the delay of each announcement since the first announcement of the block
+    pub announce_delay: Percentiles,
+    /// the number of peers announced the block
+    pub announcers: Percentiles,
+    pub header_latency: Percentiles,
+}
+
+impl BlocksSummary {
+    pub fn new(reports: &[BlockReport]) -> Self {
+        let levels = reports.iter().map(|r| r.level).collect::<BTreeSet<_>>();
+        let blocks = reports.iter().flat_map(|r| &r.blocks);
+        BlocksSummary {
+            levels: levels.len(),
+            first_level: levels.iter().next().cloned(),
+            last_level: levels.iter().next_back().cloned(),
+            blocks: blocks.clone().count(),
+            announce_delay: Percentiles::new(
+                blocks
+                    .clone()
+                    .flat_map(|b| b.announcements.iter().skip(1).map(|a| a.delay))
+                    .collect(),
+            ),
+            announcers: Percentiles::new(
+                blocks
+                    .clone()
+                    .map(|b| b.announcements.len() as u64)
+                    .collect(),
+            ),
+            header_latency: Percentiles::new(blocks.filter_map(|b| b.header_latency).collect()),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryFrom;
+use tezos_messages::p2p::{
+    binary_message::BinaryRead,
+    encoding::{ack::AckMessage, limits::MESSAGE_MAX_SIZE, metadata::MetadataMessage},
+};
+use crate::{
+    common::{MessageType, MessageKind},
+    tables::{
+        connection::{Comments, ConformanceState, Violations},
+        message,
+    },
+};
+
+/// The maximal length of the p2p message allowed by the encoding,
+/// the message of larger length is a violation
+pub const MAX_MESSAGE_SIZE: u64 = MESSAGE_MAX_SIZE as u64;
+
+fn violations(comments: &mut Comments, incoming: bool) -> &mut Violations {
+    if incoming {
+        &mut comments.incoming_violations
+    } else {
+        &mut comments.outgoing_violations
+    }
+}
+
+/// The bytes start like the p2p message, the length is plausible and the tag is known
+fn p2p_header(plain: &[u8]) -> bool {
+    if plain.len() < 6 {
+        return false;
+    }
+    let len = u32::from_be_bytes(<[u8; 4]>::try_from(&plain[..4]).unwrap());
+    let tag = u16::from_be_bytes(<[u8; 2]>::try_from(&plain[4..6]).unwrap());
+    (len as u64) <= MAX_MESSAGE_SIZE && MessageKind::from_tag(tag).valid_tag()
+}
+
+/// Check the message against the messages before it on the connection,
+/// the `state` is what the sides sent before, the message is added to it,
+/// the `plain` is the decrypted bytes of the message, `None` if they are not available,
+/// it is needed to tell `ack` from `nack`
+pub fn check_message(
+    comments: &mut Comments,
+    state: &mut ConformanceState,
+    message: &message::Item,
+    plain: Option<&[u8]>,
+) {
+    let incoming = message.sender.incoming();
+    let acked = if incoming {
+        &mut state.incoming_ack
+    } else {
+        &mut state.outgoing_ack
+    };
+
+    let mut found = 0;
+    if state.disconnect {
+        found |= Violations::AFTER_DISCONNECT;
+    }
+    match (&message.ty, plain) {
+        (MessageType::Ack, Some(plain)) => match AckMessage::from_bytes(plain) {
+            Ok(AckMessage::Ack) => *acked = true,
+            Ok(_) => {
+                found |= Violations::NACK;
+                state.nack = true;
+            },
+            // the side skipped the `ack`, the p2p message is in its place
+            Err(_) if p2p_header(plain) => found |= Violations::BEFORE_ACK,
+            Err(_) => (),
+        },
+        // cannot tell, assume the `ack`
+        (MessageType::Ack, None) => *acked = true,
+        (MessageType::P2p(kind), _) => {
+            if !kind.valid_tag() {
+                found |= Violations::UNKNOWN_KIND;
+            }
+            if !*acked {
+                found |= Violations::BEFORE_ACK;
+            }
+            if state.nack {
+                found |= Violations::AFTER_NACK;
+            }
+            if let MessageKind::Disconnect = kind {
+                state.disconnect = true;
+            }
+        },
+        _ => (),
+    }
+    if message.size > MAX_MESSAGE_SIZE {
+        found |= Violations::TOO_BIG;
+    }
+
+    violations(comments, incoming).insert(found);
+}
+
+/// The chunk after the handshake is too short for p2p message,
+/// if it is the metadata or the `ack`, the side repeats the handshake
+pub fn check_stray_chunk(comments: &mut Comments, incoming: bool, plain: &[u8]) {
+    if plain.is_empty() {
+        return;
+    }
+    if MetadataMessage::from_bytes(plain).is_ok() || AckMessage::from_bytes(plain).is_ok() {
+        violations(comments, incoming).insert(Violations::DUPLICATE_HANDSHAKE);
+    }
+}
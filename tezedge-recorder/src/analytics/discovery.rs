@@ -0,0 +1,252 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+};
+use serde::Serialize;
+use crate::common::{MessageType, MessageKind};
+
+/// The message carries the addresses of the peers
+pub fn carries(ty: &MessageType) -> bool {
+    matches!(
+        ty,
+        MessageType::P2p(MessageKind::Advertise)
+            | MessageType::P2p(MessageKind::SwapRequest)
+            | MessageType::P2p(MessageKind::SwapAck)
+    )
+}
+
+/// The ipv4 address mapped to ipv6 is converted back to ipv4,
+/// so the advertised address and the address of the connection are comparable
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V6(v6) => match v6.to_ipv4() {
+            Some(v4) if v4.to_ipv6_mapped() == v6 => SocketAddr::new(v4.into(), addr.port()),
+            _ => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+/// The node cannot connect to the address from the internet
+pub fn unroutable(addr: &SocketAddr) -> bool {
+    let ip = match addr.ip() {
+        IpAddr::V4(v4) => {
+            v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+        },
+        IpAddr::V6(v6) => v6.is_loopback() || v6.is_unspecified() || v6.is_multicast(),
+    };
+    ip || addr.port() == 0
+}
+
+/// The peer advertised the addresses to the node
+pub struct Advertisement {
+    /// the peer id, or the remote address if the peer is unknown
+    pub advertiser: String,
+    pub kind: MessageKind,
+    /// milliseconds since epoch
+    pub timestamp: u64,
+    pub points: Vec<SocketAddr>,
+}
+
+/// The connection the node initiated
+pub struct Dial {
+    pub addr: SocketAddr,
+    /// nanoseconds since epoch
+    pub timestamp: u64,
+    pub peer: String,
+}
+
+/// The peer advertised the address
+#[derive(Serialize)]
+pub struct Edge {
+    pub advertiser: String,
+    pub address: SocketAddr,
+    pub kind: MessageKind,
+    pub count: u64,
+    /// nanoseconds since epoch
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Serialize)]
+pub struct Address {
+    pub address: SocketAddr,
+    /// the number of distinct peers advertised the address
+    pub advertisers: usize,
+    /// nanoseconds since epoch
+    pub first_advertised: u64,
+    /// when the node connected to the address first time after it was advertised,
+    /// nanoseconds since epoch, and the peer it turned out to be
+    pub connected: Option<u64>,
+    pub peer: Option<String>,
+    pub unroutable: bool,
+}
+
+#[derive(Serialize)]
+pub struct Advertiser {
+    pub peer: String,
+    /// the number of messages
+    pub advertisements: u64,
+    /// the number of distinct addresses
+    pub addresses: usize,
+    /// the number of the addresses the node connected to
+    pub connected: usize,
+    /// the number of the addresses the node cannot connect to
+    pub unroutable: usize,
+}
+
+/// Which peer advertised which addresses, and which of them the node connected to
+#[derive(Serialize)]
+pub struct DiscoveryGraph {
+    pub advertisers: Vec<Advertiser>,
+    pub addresses: Vec<Address>,
+    pub edges: Vec<Edge>,
+}
+
+impl DiscoveryGraph {
+    /// The `advertisements` and the `dials` are in order of time
+    pub fn new(advertisements: &[Advertisement], dials: &[Dial]) -> Self {
+        let mut edges = BTreeMap::<(String, SocketAddr, u8), Edge>::new();
+        let mut addresses = BTreeMap::<SocketAddr, (BTreeSet<String>, u64)>::new();
+        let mut advertisers = BTreeMap::<String, u64>::new();
+        for a in advertisements {
+            let timestamp = a.timestamp * 1_000_000;
+            *advertisers.entry(a.advertiser.clone()).or_default() += 1;
+            for addr in a.points.iter().cloned().map(canonical) {
+                let tag = MessageType::P2p(a.kind.clone()).into_int();
+                let edge = edges
+                    .entry((a.advertiser.clone(), addr, tag))
+                    .or_insert_with(|| Edge {
+                        advertiser: a.advertiser.clone(),
+                        address: addr,
+                        kind: a.kind.clone(),
+                        count: 0,
+                        first_seen: timestamp,
+                        last_seen: timestamp,
+                    });
+                edge.count += 1;
+                edge.last_seen = timestamp;
+                let (peers, _) = addresses
+                    .entry(addr)
+                    .or_insert_with(|| (BTreeSet::new(), timestamp));
+                peers.insert(a.advertiser.clone());
+            }
+        }
+
+        let mut dials_by_addr = HashMap::<SocketAddr, Vec<&Dial>>::new();
+        for dial in dials {
+            dials_by_addr
+                .entry(canonical(dial.addr))
+                .or_default()
+                .push(dial);
+        }
+        let addresses = addresses
+            .into_iter()
+            .map(|(address, (peers, first_advertised))| {
+                let dial = dials_by_addr
+                    .get(&address)
+                    .and_then(|d| d.iter().find(|d| d.timestamp >= first_advertised));
+                Address {
+                    address,
+                    advertisers: peers.len(),
+                    first_advertised,
+                    connected: dial.map(|d| d.timestamp),
+                    peer: dial.map(|d| d.peer.clone()),
+                    unroutable: unroutable(&address),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let edges = edges.into_iter().map(|(_, edge)| edge).collect::<Vec<_>>();
+        let by_addr = addresses
+            .iter()
+            .map(|a| (a.address, a))
+            .collect::<HashMap<_, _>>();
+        let mut own = BTreeMap::<&str, BTreeSet<SocketAddr>>::new();
+        for e in &edges {
+            own.entry(&e.advertiser).or_default().insert(e.address);
+        }
+        let mut advertisers = advertisers
+            .into_iter()
+            .map(|(peer, advertisements)| {
+                let own = own
+                    .get(peer.as_str())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|addr| by_addr.get(addr))
+                    .collect::<Vec<_>>();
+                Advertiser {
+                    advertisements,
+                    addresses: own.len(),
+                    connected: own.iter().filter(|a| a.connected.is_some()).count(),
+                    unroutable: own.iter().filter(|a| a.unroutable).count(),
+                    peer,
+                }
+            })
+            .collect::<Vec<_>>();
+        // the peer advertised most addresses goes first
+        advertisers.sort_by(|a, b| b.addresses.cmp(&a.addresses));
+
+        DiscoveryGraph {
+            advertisers,
+            addresses,
+            edges,
+        }
+    }
+
+    /// Render the graph in Graphviz DOT language, the advertisers are boxes,
+    /// the addresses the node connected to are green, the unroutable addresses are gray
+    pub fn dot(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('"', "\\\""));
+
+        let mut dot = String::from("digraph discovery {\n    rankdir=LR;\n");
+        for a in &self.advertisers {
+            let label = format!(
+                "{}\\n{} addresses, {} connected",
+                a.peer, a.addresses, a.connected
+            );
+            let _ = writeln!(
+                dot,
+                "    {} [shape=box, label={}];",
+                quote(&a.peer),
+                quote(&label)
+            );
+        }
+        for a in &self.addresses {
+            let style = if a.connected.is_some() {
+                ", style=filled, fillcolor=palegreen"
+            } else if a.unroutable {
+                ", style=filled, fillcolor=gray"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                dot,
+                "    {} [shape=ellipse{}];",
+                quote(&a.address.to_string()),
+                style
+            );
+        }
+        for e in &self.edges {
+            let label = format!("{:?} x{}", e.kind, e.count);
+            let _ = writeln!(
+                dot,
+                "    {} -> {} [label={}];",
+                quote(&e.advertiser),
+                quote(&e.address.to_string()),
+                quote(&label)
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
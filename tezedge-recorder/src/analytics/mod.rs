@@ -0,0 +1,50 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+pub mod operation;
+pub mod block;
+pub mod pairing;
+pub mod discovery;
+pub mod conformance;
+
+use serde::Serialize;
+use crate::common::MessageType;
+
+/// The message which refers to the operation or the block
+pub struct Sighting {
+    pub message_id: u64,
+    pub ty: MessageType,
+    /// milliseconds since epoch
+    pub timestamp: u64,
+    pub incoming: bool,
+    /// the peer id, or the remote address if the peer is unknown
+    pub peer: String,
+}
+
+/// The distribution of the values, in milliseconds
+#[derive(Serialize, Default)]
+pub struct Percentiles {
+    pub count: usize,
+    pub p50: Option<u64>,
+    pub p90: Option<u64>,
+    pub p99: Option<u64>,
+    pub max: Option<u64>,
+}
+
+impl Percentiles {
+    pub fn new(mut values: Vec<u64>) -> Self {
+        values.sort_unstable();
+        // nearest rank
+        let rank = |p: usize| {
+            let rank = (values.len() * p + 99) / 100;
+            values.get(rank.max(1) - 1).cloned()
+        };
+        Percentiles {
+            count: values.len(),
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: values.last().cloned(),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::BTreeSet;
+use serde::Serialize;
+use crate::common::{MessageType, MessageKind};
+use super::Sighting;
+
+/// The operation is carried by the message, not only requested
+pub fn carries(ty: &MessageType) -> bool {
+    matches!(
+        ty,
+        MessageType::P2p(MessageKind::Operation)
+            | MessageType::P2p(MessageKind::OperationsForBlocks)
+            | MessageType::P2p(MessageKind::CurrentHead)
+    )
+}
+
+/// How the operation propagated through the node
+#[derive(Serialize)]
+pub struct OperationReport {
+    pub hash: String,
+    /// nanoseconds since epoch, when the first peer sent the operation to the node
+    pub first_seen: Option<u64>,
+    pub first_seen_peer: Option<String>,
+    pub first_seen_message: Option<u64>,
+    /// the number of distinct peers which sent the operation to the node
+    pub relayed_by: usize,
+    /// nanoseconds since epoch, when the node sent the operation to any peer first time
+    pub advertised: Option<u64>,
+    /// milliseconds from `first_seen` until `advertised`, `None` if the node
+    /// did not send the operation, or sent it before any peer
+    pub advertise_delay: Option<u64>,
+    /// the number of messages which carry the operation, in both directions
+    pub messages: usize,
+}
+
+impl OperationReport {
+    /// The `sightings` are in order of the message id, that is the order of time
+    pub fn new(hash: String, sightings: &[Sighting]) -> Self {
+        let first = sightings.iter().find(|s| s.incoming);
+        let advertised = sightings.iter().find(|s| !s.incoming);
+        let relayed_by = sightings
+            .iter()
+            .filter(|s| s.incoming)
+            .map(|s| s.peer.as_str())
+            .collect::<BTreeSet<_>>()
+            .len();
+        let advertise_delay = match (first, advertised) {
+            (Some(first), Some(advertised)) if first.message_id < advertised.message_id => {
+                Some(advertised.timestamp.saturating_sub(first.timestamp))
+            },
+            _ => None,
+        };
+
+        OperationReport {
+            hash,
+            first_seen: first.map(|s| s.timestamp * 1_000_000),
+            first_seen_peer: first.map(|s| s.peer.clone()),
+            first_seen_message: first.map(|s| s.message_id),
+            relayed_by,
+            advertised: advertised.map(|s| s.timestamp * 1_000_000),
+            advertise_delay,
+            messages: sightings.len(),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use serde::Serialize;
+use crate::{
+    common::{MessageType, MessageKind},
+    tables::{connection, message, request},
+};
+use super::Percentiles;
+
+/// The kind of the response to the request, `None` if the message is not a request
+pub fn response_kind(kind: &MessageKind) -> Option<MessageKind> {
+    match kind {
+        MessageKind::GetCurrentBranch => Some(MessageKind::CurrentBranch),
+        MessageKind::GetBlockHeaders => Some(MessageKind::BlockHeader),
+        MessageKind::GetOperations => Some(MessageKind::Operation),
+        MessageKind::GetOperationsForBlocks => Some(MessageKind::OperationsForBlocks),
+        MessageKind::GetProtocols => Some(MessageKind::Protocol),
+        _ => None,
+    }
+}
+
+/// The blocks or the operations requested by the message, a request record for each of them,
+/// `None` if the message is not decoded, or its responses carry no hash
+fn requested(kind: &MessageKind, item: &message::Item) -> Vec<Option<[u8; 32]>> {
+    let hashes: Vec<Option<[u8; 32]>> = match kind {
+        MessageKind::GetBlockHeaders | MessageKind::GetOperationsForBlocks => {
+            item.refs.blocks.iter().cloned().map(Some).collect()
+        },
+        MessageKind::GetOperations => item.refs.operations.iter().cloned().map(Some).collect(),
+        _ => vec![],
+    };
+    if hashes.is_empty() {
+        vec![None]
+    } else {
+        hashes
+    }
+}
+
+struct Pending {
+    key: request::Key,
+    value: request::Value,
+    /// the requested block or operation, `None` if the response is matched only by its kind
+    hash: Option<[u8; 32]>,
+}
+
+/// Matches the responses to the requests on each connection,
+/// the request of several blocks or operations is answered by a response for each of them
+#[derive(Default)]
+pub struct Pairing {
+    pending: HashMap<(u64, u32), VecDeque<Pending>>,
+}
+
+impl Pairing {
+    /// The oldest request is considered unanswered if the connection has more pending requests
+    pub const MAX_PENDING: usize = 0x400;
+
+    /// Returns the records to store: the new requests, the answered request,
+    /// and the requests dropped because too many requests are pending
+    pub fn message(
+        &mut self,
+        index: u64,
+        item: &message::Item,
+    ) -> Vec<(request::Key, request::Value)> {
+        let kind = match &item.ty {
+            MessageType::P2p(kind) => kind,
+            _ => return vec![],
+        };
+        let cn_id = item.cn_id();
+        let incoming = item.sender.incoming();
+        let pending = self.pending.entry((cn_id.ts, cn_id.ts_nanos)).or_default();
+
+        let mut records = vec![];
+        if response_kind(kind).is_some() {
+            for (part, hash) in requested(kind, item).into_iter().enumerate() {
+                let key = request::Key {
+                    index,
+                    part: part as u32,
+                };
+                let value = request::Value::new(&cn_id, kind.clone(), incoming, item.timestamp);
+                records.push((key.clone(), value.clone()));
+                pending.push_back(Pending { key, value, hash });
+                if pending.len() > Self::MAX_PENDING {
+                    if let Some(mut dropped) = pending.pop_front() {
+                        dropped.value.unanswered = true;
+                        records.push((dropped.key, dropped.value));
+                    }
+                }
+            }
+        } else {
+            // the response carries the block or the operation first
+            let hash = match kind {
+                MessageKind::BlockHeader | MessageKind::OperationsForBlocks => {
+                    item.refs.blocks.first().cloned()
+                },
+                MessageKind::Operation => item.refs.operations.first().cloned(),
+                _ => None,
+            };
+            let position = pending.iter().position(|p| {
+                p.value.incoming != incoming
+                    && response_kind(&p.value.kind).as_ref() == Some(kind)
+                    && p.hash.map_or(true, |h| Some(h) == hash)
+            });
+            if let Some(mut answered) = position.and_then(|position| pending.remove(position)) {
+                answered.value.response = Some(index);
+                answered.value.rtt = Some(item.timestamp.saturating_sub(answered.value.timestamp));
+                records.push((answered.key, answered.value));
+            }
+        }
+
+        if pending.is_empty() {
+            self.pending.remove(&(cn_id.ts, cn_id.ts_nanos));
+        }
+        records
+    }
+
+    /// Put back the stored request which is waiting for the response,
+    /// the `item` is the request message, the requests are restored in order of their keys
+    pub fn restore(&mut self, key: request::Key, value: request::Value, item: &message::Item) {
+        let hash = match &item.ty {
+            MessageType::P2p(kind) => requested(kind, item)
+                .get(key.part as usize)
+                .cloned()
+                .flatten(),
+            _ => return,
+        };
+        let cn_id = value.cn_id();
+        self.pending
+            .entry((cn_id.ts, cn_id.ts_nanos))
+            .or_default()
+            .push_back(Pending { key, value, hash });
+    }
+
+    /// The connection is closed, returns its pending requests marked unanswered
+    pub fn close(&mut self, cn_id: &connection::Key) -> Vec<(request::Key, request::Value)> {
+        self.pending
+            .remove(&(cn_id.ts, cn_id.ts_nanos))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|mut p| {
+                p.value.unanswered = true;
+                (p.key, p.value)
+            })
+            .collect()
+    }
+}
+
+/// The requests of one kind and how fast they were answered
+#[derive(Serialize, Default)]
+pub struct Latency {
+    pub requests: u64,
+    pub answered: u64,
+    pub unanswered: u64,
+    /// the round trip time of the answered requests
+    pub rtt: Percentiles,
+}
+
+#[derive(Serialize)]
+pub struct KindLatency {
+    pub kind: MessageKind,
+    #[serde(flatten)]
+    pub latency: Latency,
+}
+
+#[derive(Serialize)]
+pub struct PeerLatency {
+    /// the peer id, or the remote address if the peer is unknown
+    pub peer: String,
+    #[serde(flatten)]
+    pub total: Latency,
+    pub kinds: Vec<KindLatency>,
+}
+
+impl PeerLatency {
+    /// Group the requests by the peer and by the kind, the peer with most requests goes first
+    pub fn new<I>(requests: I) -> Vec<Self>
+    where
+        I: IntoIterator<Item = (String, request::Value)>,
+    {
+        #[derive(Default)]
+        struct Acc {
+            requests: u64,
+            unanswered: u64,
+            rtt: Vec<u64>,
+        }
+
+        impl Acc {
+            fn add(&mut self, value: &request::Value) {
+                self.requests += 1;
+                if value.unanswered {
+                    self.unanswered += 1;
+                }
+                self.rtt.extend(value.rtt);
+            }
+
+            fn latency(self) -> Latency {
+                Latency {
+                    requests: self.requests,
+                    answered: self.rtt.len() as u64,
+                    unanswered: self.unanswered,
+                    rtt: Percentiles::new(self.rtt),
+                }
+            }
+        }
+
+        let mut peers = BTreeMap::<String, (Acc, BTreeMap<u8, Acc>)>::new();
+        for (peer, value) in requests {
+            let (total, kinds) = peers.entry(peer).or_default();
+            total.add(&value);
+            let ty = MessageType::P2p(value.kind.clone()).into_int();
+            kinds.entry(ty).or_default().add(&value);
+        }
+
+        let mut vec = peers
+            .into_iter()
+            .map(|(peer, (total, kinds))| PeerLatency {
+                peer,
+                total: total.latency(),
+                kinds: kinds
+                    .into_iter()
+                    .filter_map(|(ty, acc)| match MessageType::from_int(ty) {
+                        MessageType::P2p(kind) => Some(KindLatency {
+                            kind,
+                            latency: acc.latency(),
+                        }),
+                        _ => None,
+                    })
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        vec.sort_by(|a, b| b.total.requests.cmp(&a.total.requests));
+        vec
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{path::PathBuf, process};
+use structopt::StructOpt;
+use tezedge_recorder::{
+    database::{DatabaseNew, rocks::Db},
+    tables::chunk::ChunkPayload,
+};
+
+/// Verify the database of the recorder, report dangling index entries,
+/// messages with missing chunks, the full text search index discrepancy
+/// and the column families which need migration
+#[derive(StructOpt)]
+struct Args {
+    /// the `db` path from the recorder config
+    path: PathBuf,
+    /// migrate the database, rebuild secondary indexes, the full text search index
+    /// and the tables derived from the messages, the recorder must be stopped
+    #[structopt(long)]
+    rebuild: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let args = Args::from_args();
+
+    let report = if args.rebuild {
+        let log_full_text_index = args.path.join("tantivy").exists();
+        let db = <Db as DatabaseNew>::open(
+            &args.path,
+            log_full_text_index,
+            None,
+            None,
+            ChunkPayload::default(),
+        )?;
+        db.rebuild_indexes()?;
+        db.check()?
+    } else {
+        Db::open_read_only(&args.path)?.check()?
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.is_consistent() {
+        process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,170 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+};
+use structopt::StructOpt;
+use tezedge_recorder::{
+    database::{DatabaseNew, DatabaseFetch, ExportFilter, rocks::Db},
+    export::{self, anonymize::Anonymizer},
+    tables::chunk::ChunkPayload,
+};
+
+/// Export the data recorded by the recorder
+#[derive(StructOpt)]
+enum Args {
+    /// Export connections as pcapng file, the chunks become tcp segments,
+    /// the decrypted bytes and the message type are in the packet comments
+    Pcapng {
+        #[structopt(flatten)]
+        selection: Selection,
+        #[structopt(flatten)]
+        anonymize: Anonymize,
+        /// the `p2p.port` from the recorder config, the port of the node's side
+        #[structopt(long)]
+        port: u16,
+        /// the output file
+        #[structopt(short, long, default_value = "recorder.pcapng")]
+        output: PathBuf,
+    },
+    /// Export connections with their chunks and messages,
+    /// and the node logs of the time window as an archive
+    Archive {
+        #[structopt(flatten)]
+        selection: Selection,
+        #[structopt(flatten)]
+        anonymize: Anonymize,
+        /// the output file
+        #[structopt(short, long, default_value = "recorder.tar")]
+        output: PathBuf,
+    },
+    /// Load the archive into the database, the recorder using this database must be stopped
+    Import {
+        /// the archive
+        archive: PathBuf,
+        /// the `db` path from the recorder config
+        path: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+struct Selection {
+    /// the `db` path from the recorder config
+    path: PathBuf,
+    /// the connection id, for example 1617005682.953928051
+    #[structopt(long)]
+    cn: Option<String>,
+    /// export connections opened since this time, in seconds
+    #[structopt(long)]
+    from: Option<u64>,
+    /// export connections opened before this time, in seconds
+    #[structopt(long)]
+    to: Option<u64>,
+    /// maximal number of connections
+    #[structopt(long)]
+    limit: Option<u64>,
+}
+
+impl Selection {
+    fn split(self) -> (PathBuf, ExportFilter) {
+        let Selection {
+            path,
+            cn,
+            from,
+            to,
+            limit,
+        } = self;
+        (
+            path,
+            ExportFilter {
+                cn,
+                from,
+                to,
+                limit,
+            },
+        )
+    }
+}
+
+#[derive(StructOpt)]
+struct Anonymize {
+    /// replace the addresses, the ports and the public keys of the peers by pseudonyms
+    #[structopt(long)]
+    anonymize: bool,
+    /// 32 bytes in hex, the exports made with the same secret share the pseudonyms,
+    /// implies `--anonymize`
+    #[structopt(long)]
+    anonymize_secret: Option<String>,
+    /// another word to replace in the logs, the syslog host names are replaced anyway
+    #[structopt(long)]
+    hostname: Vec<String>,
+}
+
+impl Anonymize {
+    fn build(self) -> anyhow::Result<Option<Anonymizer>> {
+        let mut anonymizer = match self.anonymize_secret {
+            Some(secret) => {
+                let secret = <[u8; 32]>::try_from(hex::decode(secret)?.as_slice())
+                    .map_err(|_| anyhow::anyhow!("the secret must be 32 bytes"))?;
+                Anonymizer::new(secret)
+            },
+            None if self.anonymize => Anonymizer::random(),
+            None => return Ok(None),
+        };
+        for hostname in &self.hostname {
+            anonymizer.hostname(hostname);
+        }
+        Ok(Some(anonymizer))
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    match Args::from_args() {
+        Args::Pcapng {
+            selection,
+            anonymize,
+            port,
+            output,
+        } => {
+            let (path, filter) = selection.split();
+            let db = Db::open_read_only(&path)?;
+            let mut connections = db.fetch_connection_chunks(&filter)?;
+            if let Some(mut anonymizer) = anonymize.build()? {
+                connections
+                    .iter_mut()
+                    .for_each(|c| anonymizer.connection_chunks(c));
+            }
+            let file = BufWriter::new(File::create(&output)?);
+            export::pcapng::write(file, &connections, port)?;
+            log::info!("exported {} connections to {:?}", connections.len(), output);
+        },
+        Args::Archive {
+            selection,
+            anonymize,
+            output,
+        } => {
+            let (path, filter) = selection.split();
+            let db = Db::open_read_only(&path)?;
+            let mut anonymizer = anonymize.build()?;
+            let file = BufWriter::new(File::create(&output)?);
+            let manifest = export::archive::write(file, &db, &filter, anonymizer.as_mut())?;
+            println!("{}", serde_json::to_string_pretty(&manifest)?);
+        },
+        Args::Import { archive, path } => {
+            let db = <Db as DatabaseNew>::open(&path, true, None, None, ChunkPayload::default())?;
+            let file = BufReader::new(File::open(&archive)?);
+            let manifest = export::archive::read(file, &db)?;
+            println!("{}", serde_json::to_string_pretty(&manifest)?);
+        },
+    }
+
+    Ok(())
+}
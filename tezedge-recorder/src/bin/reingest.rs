@@ -0,0 +1,68 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    fs, io,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{Ordering, AtomicBool},
+    },
+};
+use structopt::StructOpt;
+use tezedge_recorder::{
+    System,
+    capture::{CaptureFile, PcapCapture},
+    database::rocks::Db,
+    main_loop,
+};
+
+/// Process the events from the capture file written by the recorder
+/// with `capture_file` option, or the traffic from pcap/pcapng file,
+/// and store the result into the database
+#[derive(StructOpt)]
+struct Args {
+    /// the capture file, or pcap/pcapng file
+    capture: PathBuf,
+    /// the address of the node's host in the pcap file, may be repeated,
+    /// every connection initiated from it is processed as outgoing connection of the node,
+    /// if not specified, only incoming connections to the node's p2p port are processed
+    #[structopt(long)]
+    local_ip: Vec<IpAddr>,
+    /// the recorder config, the `db` of the nodes should point to fresh directories
+    #[structopt(long, default_value = "config.toml")]
+    config: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let args = Args::from_args();
+
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let running = running.clone();
+        ctrlc::set_handler(move || running.store(false, Ordering::Relaxed))?;
+    }
+
+    let mut system = System::<Db>::from_toml(&fs::read_to_string(&args.config)?)?;
+    system.run_dbs(running.clone());
+
+    match CaptureFile::open(&args.capture) {
+        Ok(source) => main_loop::run_with_source(source, &mut system, running.clone())?,
+        Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+            let source = PcapCapture::open(&args.capture, args.local_ip)?;
+            main_loop::run_with_source(source, &mut system, running.clone())?
+        },
+        Err(error) => return Err(error.into()),
+    }
+    log::info!("capture file {:?} is processed", args.capture);
+
+    running.store(false, Ordering::Relaxed);
+    system.join();
+
+    Ok(())
+}
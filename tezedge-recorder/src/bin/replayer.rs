@@ -9,7 +9,10 @@ use std::{
 use tezedge_recorder::{
     common::MessageCategory,
     database::{DatabaseNew, DatabaseFetch, rocks::Db, MessagesFilter},
-    tables::message::{MessageFrontend, TezosMessage},
+    tables::{
+        chunk::ChunkPayload,
+        message::{MessageFrontend, TezosMessage},
+    },
 };
 use pseudonode::{ChunkBuffer, Message, handshake};
 use crypto::{
@@ -178,7 +181,14 @@ fn main() {
         .with_max_level(tracing::Level::INFO)
         .init();
 
-    let db = Db::open("/volume/debugger_db/tezedge", false, None, None).unwrap();
+    let db = Db::open(
+        "/volume/debugger_db/tezedge",
+        false,
+        None,
+        None,
+        ChunkPayload::Both,
+    )
+    .unwrap();
 
     let mut filter = MessagesFilter::default();
     filter.cursor = Some(0);
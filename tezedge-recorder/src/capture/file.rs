@@ -0,0 +1,386 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    convert::TryFrom,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::atomic::AtomicBool,
+};
+use bpf_recorder::{SnifferEvent, EventId, SocketId};
+use super::CaptureSource;
+
+/// The file starts with the magic, followed by the records:
+/// * record layout: `[length(4)][tag(1)][pid(4)][fd(4)][ts(8)][body]`,
+/// the `length` is the length of the rest of the record,
+/// the `ts` is the time since epoch in nanoseconds when the event was read,
+/// all integers are little endian
+/// * address layout: `[family(1)][ip(4 or 16)][port(2)]`
+const MAGIC: &[u8; 8] = b"TZRCAP01";
+
+const TAG_DATA: u8 = 0;
+const TAG_CONNECT: u8 = 1;
+const TAG_BIND: u8 = 2;
+const TAG_LISTEN: u8 = 3;
+const TAG_ACCEPT: u8 = 4;
+const TAG_CLOSE: u8 = 5;
+const TAG_GET_FD: u8 = 6;
+const TAG_DEBUG: u8 = 7;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn encode_address(v: &mut Vec<u8>, address: &SocketAddr) {
+    match address.ip() {
+        IpAddr::V4(ip) => {
+            v.push(4);
+            v.extend_from_slice(&ip.octets());
+        },
+        IpAddr::V6(ip) => {
+            v.push(6);
+            v.extend_from_slice(&ip.octets());
+        },
+    }
+    v.extend_from_slice(&address.port().to_le_bytes());
+}
+
+fn decode_address(b: &[u8]) -> io::Result<SocketAddr> {
+    let e = || invalid("bad address");
+    let (ip, rest) = match b.first() {
+        Some(4) if b.len() >= 7 => {
+            let ip = <[u8; 4]>::try_from(&b[1..5]).unwrap();
+            (IpAddr::V4(ip.into()), &b[5..])
+        },
+        Some(6) if b.len() >= 19 => {
+            let ip = <[u8; 16]>::try_from(&b[1..17]).unwrap();
+            (IpAddr::V6(ip.into()), &b[17..])
+        },
+        _ => return Err(e()),
+    };
+    let port = u16::from_le_bytes(<[u8; 2]>::try_from(&rest[..2]).map_err(|_| e())?);
+    Ok(SocketAddr::new(ip, port))
+}
+
+fn encode(event: &SnifferEvent, ts: u64) -> Vec<u8> {
+    let mut v = Vec::with_capacity(64);
+    let mut header = |tag: u8, id: &EventId| {
+        v.push(tag);
+        v.extend_from_slice(&id.socket_id.pid.to_le_bytes());
+        v.extend_from_slice(&id.socket_id.fd.to_le_bytes());
+        v.extend_from_slice(&ts.to_le_bytes());
+    };
+    match event {
+        SnifferEvent::Data {
+            id,
+            data,
+            net,
+            incoming,
+        } => {
+            header(TAG_DATA, id);
+            v.push(*net as u8);
+            v.push(*incoming as u8);
+            v.extend_from_slice(data);
+        },
+        SnifferEvent::Connect { id, address } => {
+            header(TAG_CONNECT, id);
+            encode_address(&mut v, address);
+        },
+        SnifferEvent::Bind { id, address } => {
+            header(TAG_BIND, id);
+            encode_address(&mut v, address);
+        },
+        SnifferEvent::Listen { id } => header(TAG_LISTEN, id),
+        SnifferEvent::Accept {
+            id,
+            listen_on_fd,
+            address,
+        } => {
+            header(TAG_ACCEPT, id);
+            v.extend_from_slice(&listen_on_fd.to_le_bytes());
+            encode_address(&mut v, address);
+        },
+        SnifferEvent::Close { id } => header(TAG_CLOSE, id),
+        SnifferEvent::GetFd { id } => header(TAG_GET_FD, id),
+        SnifferEvent::Debug { id, msg } => {
+            header(TAG_DEBUG, id);
+            v.extend_from_slice(msg.as_bytes());
+        },
+    }
+    v
+}
+
+fn event_id(event: &SnifferEvent) -> &EventId {
+    match event {
+        SnifferEvent::Data { id, .. } => id,
+        SnifferEvent::Connect { id, .. } => id,
+        SnifferEvent::Bind { id, .. } => id,
+        SnifferEvent::Listen { id } => id,
+        SnifferEvent::Accept { id, .. } => id,
+        SnifferEvent::Close { id } => id,
+        SnifferEvent::GetFd { id } => id,
+        SnifferEvent::Debug { id, .. } => id,
+    }
+}
+
+fn decode(b: &[u8]) -> io::Result<SnifferEvent> {
+    if b.len() < 17 {
+        return Err(invalid("record is too short"));
+    }
+    let pid = u32::from_le_bytes(<[u8; 4]>::try_from(&b[1..5]).unwrap());
+    let fd = u32::from_le_bytes(<[u8; 4]>::try_from(&b[5..9]).unwrap());
+    let ts = u64::from_le_bytes(<[u8; 8]>::try_from(&b[9..17]).unwrap());
+    let id = EventId::new(SocketId { pid, fd }, 0, ts);
+    let body = &b[17..];
+    match b[0] {
+        TAG_DATA => {
+            if body.len() < 2 {
+                return Err(invalid("data record is too short"));
+            }
+            Ok(SnifferEvent::Data {
+                id,
+                data: body[2..].to_vec(),
+                net: body[0] != 0,
+                incoming: body[1] != 0,
+            })
+        },
+        TAG_CONNECT => Ok(SnifferEvent::Connect {
+            id,
+            address: decode_address(body)?,
+        }),
+        TAG_BIND => Ok(SnifferEvent::Bind {
+            id,
+            address: decode_address(body)?,
+        }),
+        TAG_LISTEN => Ok(SnifferEvent::Listen { id }),
+        TAG_ACCEPT => {
+            if body.len() < 4 {
+                return Err(invalid("accept record is too short"));
+            }
+            Ok(SnifferEvent::Accept {
+                id,
+                listen_on_fd: u32::from_le_bytes(<[u8; 4]>::try_from(&body[..4]).unwrap()),
+                address: decode_address(&body[4..])?,
+            })
+        },
+        TAG_CLOSE => Ok(SnifferEvent::Close { id }),
+        TAG_GET_FD => Ok(SnifferEvent::GetFd { id }),
+        TAG_DEBUG => Ok(SnifferEvent::Debug {
+            id,
+            msg: String::from_utf8_lossy(body).into_owned(),
+        }),
+        _ => Err(invalid("unknown tag")),
+    }
+}
+
+/// The length of the capture file up to the end of the last complete record,
+/// zero if even the magic is incomplete
+fn complete_length(file: &mut File, length: u64) -> io::Result<u64> {
+    let mut magic = [0; 8];
+    let magic_length = (length as usize).min(MAGIC.len());
+    file.read_exact(&mut magic[..magic_length])?;
+    if magic[..magic_length] != MAGIC[..magic_length] {
+        return Err(invalid("not a capture file"));
+    }
+    if magic_length < MAGIC.len() {
+        return Ok(0);
+    }
+
+    // the bodies are skipped, only the lengths are read
+    let mut end = MAGIC.len() as u64;
+    let mut record_length = [0; 4];
+    while end + 4 <= length {
+        file.seek(SeekFrom::Start(end))?;
+        file.read_exact(&mut record_length)?;
+        let next = end + 4 + u32::from_le_bytes(record_length) as u64;
+        if next > length {
+            break;
+        }
+        end = next;
+    }
+    Ok(end)
+}
+
+/// Appends every event to the capture file
+pub struct CaptureWriter {
+    file: BufWriter<File>,
+}
+
+impl CaptureWriter {
+    /// Appends to the existing file, the incomplete record left by the recorder
+    /// killed in the middle of writing is cut off
+    pub fn create<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(path)?;
+        let length = file.metadata()?.len();
+        let end = if length == 0 {
+            0
+        } else {
+            complete_length(&mut file, length)?
+        };
+        if end < length {
+            log::warn!(
+                "capture file is truncated, cut off {} bytes of incomplete record",
+                length - end,
+            );
+            file.set_len(end)?;
+        }
+        file.seek(SeekFrom::Start(end))?;
+        if end == 0 {
+            file.write_all(MAGIC)?;
+        }
+        Ok(CaptureWriter {
+            file: BufWriter::new(file),
+        })
+    }
+
+    /// The `ts` is the time since epoch in nanoseconds
+    pub fn write(&mut self, event: &SnifferEvent, ts: u64) -> io::Result<()> {
+        let record = encode(event, ts);
+        self.file.write_all(&(record.len() as u32).to_le_bytes())?;
+        self.file.write_all(&record)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Wraps the source and writes every event it yields to the capture file
+pub struct Recording<S> {
+    inner: S,
+    writer: CaptureWriter,
+}
+
+impl<S> Recording<S> {
+    pub fn new(inner: S, writer: CaptureWriter) -> Self {
+        Recording { inner, writer }
+    }
+}
+
+impl<S> CaptureSource for Recording<S>
+where
+    S: CaptureSource,
+{
+    fn read_events(&mut self, running: &AtomicBool) -> io::Result<Vec<SnifferEvent>> {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let events = self.inner.read_events(running)?;
+        // the kernel module stamps the events with monotonic time, it is useless in a file
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let wall_clock = self.inner.wall_clock();
+        for event in &events {
+            let ts = if wall_clock {
+                event_id(event).ts_finish()
+            } else {
+                now
+            };
+            if let Err(error) = self.writer.write(event, ts) {
+                log::error!("cannot write capture file: {}", error);
+            }
+        }
+        if let Err(error) = self.writer.flush() {
+            log::error!("cannot write capture file: {}", error);
+        }
+        Ok(events)
+    }
+
+    fn watch_port(&mut self, port: u16) -> io::Result<()> {
+        self.inner.watch_port(port)
+    }
+
+    fn ignore_connection(&mut self, socket_id: SocketId) -> io::Result<()> {
+        self.inner.ignore_connection(socket_id)
+    }
+
+    fn wall_clock(&self) -> bool {
+        self.inner.wall_clock()
+    }
+}
+
+/// Yields the events from the capture file in the order they were written
+pub struct CaptureFile {
+    file: BufReader<File>,
+}
+
+impl CaptureFile {
+    const BATCH: usize = 64;
+
+    pub fn open<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a capture file"));
+        }
+        Ok(CaptureFile { file })
+    }
+
+    fn read_event(&mut self) -> io::Result<Option<SnifferEvent>> {
+        let mut length = [0; 4];
+        match self.file.read_exact(&mut length) {
+            Ok(()) => (),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error),
+        }
+        let mut record = vec![0; u32::from_le_bytes(length) as usize];
+        match self.file.read_exact(&mut record) {
+            Ok(()) => decode(&record).map(Some),
+            // the recorder was killed in the middle of writing
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                log::warn!("capture file is truncated");
+                Ok(None)
+            },
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl CaptureSource for CaptureFile {
+    fn read_events(&mut self, running: &AtomicBool) -> io::Result<Vec<SnifferEvent>> {
+        let _ = running;
+        let mut events = Vec::with_capacity(Self::BATCH);
+        while events.len() < Self::BATCH {
+            match self.read_event()? {
+                Some(event) => events.push(event),
+                None => break,
+            }
+        }
+        if events.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "capture file is over",
+            ))
+        } else {
+            Ok(events)
+        }
+    }
+
+    fn watch_port(&mut self, port: u16) -> io::Result<()> {
+        let _ = port;
+        Ok(())
+    }
+
+    fn ignore_connection(&mut self, socket_id: SocketId) -> io::Result<()> {
+        let _ = socket_id;
+        Ok(())
+    }
+
+    fn wall_clock(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::Duration,
+};
+use bpf_recorder::{BpfModuleClient, Command, SnifferEvent, SocketId};
+use bpf_ring_buffer::RingBufferSync;
+
+mod file;
+pub use self::file::{CaptureWriter, Recording, CaptureFile};
+
+mod pcap;
+pub use self::pcap::PcapCapture;
+
+/// The source of the events the recorder processes
+pub trait CaptureSource {
+    /// Block until some events are available,
+    /// return empty vector if `running` became false,
+    /// return `UnexpectedEof` error if the source is exhausted
+    fn read_events(&mut self, running: &AtomicBool) -> io::Result<Vec<SnifferEvent>>;
+
+    /// Start delivering events of the connections on the port
+    fn watch_port(&mut self, port: u16) -> io::Result<()>;
+
+    /// The connection is not interesting,
+    /// the source may stop delivering its events
+    fn ignore_connection(&mut self, socket_id: SocketId) -> io::Result<()>;
+
+    /// The timestamp of the event is the time since epoch in nanoseconds,
+    /// otherwise the event happens when it is processed
+    fn wall_clock(&self) -> bool {
+        false
+    }
+}
+
+/// Events intercepted by the bpf-recorder kernel module
+pub struct BpfCapture {
+    client: BpfModuleClient,
+    rb: RingBufferSync,
+}
+
+impl BpfCapture {
+    pub fn connect<P>(sniffer_path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let (client, rb) = BpfModuleClient::new_sync(sniffer_path)?;
+        Ok(BpfCapture { client, rb })
+    }
+}
+
+impl CaptureSource for BpfCapture {
+    fn read_events(&mut self, running: &AtomicBool) -> io::Result<Vec<SnifferEvent>> {
+        self.rb
+            .read_blocking::<SnifferEvent>(running)
+            .map(|events| events.into_iter().collect())
+    }
+
+    fn watch_port(&mut self, port: u16) -> io::Result<()> {
+        self.client.send_command(Command::WatchPort { port })
+    }
+
+    fn ignore_connection(&mut self, socket_id: SocketId) -> io::Result<()> {
+        let SocketId { pid, fd } = socket_id;
+        self.client
+            .send_command(Command::IgnoreConnection { pid, fd })
+    }
+}
+
+/// Events produced in the same process, for example, by a test,
+/// requires neither root nor the kernel module
+pub struct ChannelCapture {
+    rx: mpsc::Receiver<SnifferEvent>,
+}
+
+impl ChannelCapture {
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new() -> (Self, mpsc::Sender<SnifferEvent>) {
+        let (tx, rx) = mpsc::channel();
+        (ChannelCapture { rx }, tx)
+    }
+}
+
+impl CaptureSource for ChannelCapture {
+    fn read_events(&mut self, running: &AtomicBool) -> io::Result<Vec<SnifferEvent>> {
+        while running.load(Ordering::Relaxed) {
+            match self.rx.recv_timeout(Self::POLL_INTERVAL) {
+                Ok(event) => {
+                    let mut events = vec![event];
+                    events.extend(self.rx.try_iter());
+                    return Ok(events);
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "capture channel is disconnected",
+                    ))
+                },
+            }
+        }
+        Ok(vec![])
+    }
+
+    fn watch_port(&mut self, port: u16) -> io::Result<()> {
+        let _ = port;
+        Ok(())
+    }
+
+    fn ignore_connection(&mut self, socket_id: SocketId) -> io::Result<()> {
+        let _ = socket_id;
+        Ok(())
+    }
+}
@@ -0,0 +1,670 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryFrom,
+    fs::File,
+    io::{self, BufReader, Read},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::atomic::AtomicBool,
+};
+use bpf_recorder::{SnifferEvent, EventId, SocketId};
+use super::CaptureSource;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn u16_at(b: &[u8], pos: usize, big_endian: bool) -> u16 {
+    let v = <[u8; 2]>::try_from(&b[pos..(pos + 2)]).unwrap();
+    if big_endian {
+        u16::from_be_bytes(v)
+    } else {
+        u16::from_le_bytes(v)
+    }
+}
+
+fn u32_at(b: &[u8], pos: usize, big_endian: bool) -> u32 {
+    let v = <[u8; 4]>::try_from(&b[pos..(pos + 4)]).unwrap();
+    if big_endian {
+        u32::from_be_bytes(v)
+    } else {
+        u32::from_le_bytes(v)
+    }
+}
+
+/// The packet as it is stored in the file
+struct Packet {
+    timestamp_nanos: u64,
+    link_type: u32,
+    data: Vec<u8>,
+}
+
+struct Interface {
+    link_type: u32,
+    // how many nanoseconds in the unit of the timestamp
+    nanos_per_unit: f64,
+}
+
+enum Format {
+    Pcap {
+        big_endian: bool,
+        nanos: bool,
+        link_type: u32,
+    },
+    PcapNg {
+        big_endian: bool,
+        interfaces: Vec<Interface>,
+    },
+}
+
+/// Reads classic pcap and pcapng files
+struct PacketReader {
+    file: BufReader<File>,
+    format: Format,
+}
+
+impl PacketReader {
+    const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+    const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+    const PCAPNG_SHB: u32 = 0x0a0d0d0a;
+    const PCAPNG_BYTE_ORDER: u32 = 0x1a2b3c4d;
+    /// The default snapshot length of tcpdump, the longer packet means the file is corrupted
+    const MAX_PACKET_LENGTH: usize = 0x40000;
+    /// The packet with the headers and the options of its block
+    const MAX_BLOCK_LENGTH: usize = Self::MAX_PACKET_LENGTH + 0x1000;
+
+    fn open<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0; 4];
+        file.read_exact(&mut magic)?;
+        let (le, be) = (u32::from_le_bytes(magic), u32::from_be_bytes(magic));
+        let format = if le == Self::PCAPNG_SHB {
+            let big_endian = Self::read_section_header(&mut file)?;
+            Format::PcapNg {
+                big_endian,
+                interfaces: vec![],
+            }
+        } else if [le, be]
+            .iter()
+            .any(|m| *m == Self::PCAP_MAGIC || *m == Self::PCAP_MAGIC_NANOS)
+        {
+            let big_endian = be == Self::PCAP_MAGIC || be == Self::PCAP_MAGIC_NANOS;
+            let mut header = [0; 20];
+            file.read_exact(&mut header)?;
+            Format::Pcap {
+                big_endian,
+                nanos: le == Self::PCAP_MAGIC_NANOS || be == Self::PCAP_MAGIC_NANOS,
+                link_type: u32_at(&header, 16, big_endian) & 0xffff,
+            }
+        } else {
+            return Err(invalid("neither pcap nor pcapng file"));
+        };
+
+        Ok(PacketReader { file, format })
+    }
+
+    /// Read the rest of the section header block, whose type is already read,
+    /// return whether the section is big endian
+    fn read_section_header(file: &mut BufReader<File>) -> io::Result<bool> {
+        let mut head = [0; 8];
+        file.read_exact(&mut head)?;
+        let big_endian = match u32_at(&head, 4, false) {
+            Self::PCAPNG_BYTE_ORDER => false,
+            _ if u32_at(&head, 4, true) == Self::PCAPNG_BYTE_ORDER => true,
+            _ => return Err(invalid("bad pcapng byte order magic")),
+        };
+        let total_length = u32_at(&head, 0, big_endian) as usize;
+        if total_length < 12 {
+            return Err(invalid("bad pcapng block length"));
+        }
+        // skip the rest of the block
+        io::copy(
+            &mut file.by_ref().take((total_length - 12) as u64),
+            &mut io::sink(),
+        )?;
+        Ok(big_endian)
+    }
+
+    /// Read exactly `buf.len()` bytes, or nothing if the file is over
+    fn read_or_eof(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        match self.file.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        match &self.format {
+            &Format::Pcap {
+                big_endian,
+                nanos,
+                link_type,
+            } => {
+                let mut header = [0; 16];
+                if !self.read_or_eof(&mut header)? {
+                    return Ok(None);
+                }
+                let seconds = u32_at(&header, 0, big_endian) as u64;
+                let fraction = u32_at(&header, 4, big_endian) as u64;
+                let length = u32_at(&header, 8, big_endian) as usize;
+                if length > Self::MAX_PACKET_LENGTH {
+                    return Err(invalid("pcap packet is too long"));
+                }
+                let mut data = vec![0; length];
+                if !self.read_or_eof(&mut data)? {
+                    return Ok(None);
+                }
+                let timestamp_nanos =
+                    seconds * 1_000_000_000 + if nanos { fraction } else { fraction * 1_000 };
+                Ok(Some(Packet {
+                    timestamp_nanos,
+                    link_type,
+                    data,
+                }))
+            },
+            Format::PcapNg { .. } => self.next_packet_ng(),
+        }
+    }
+
+    fn next_packet_ng(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            let big_endian = match &self.format {
+                Format::PcapNg { big_endian, .. } => *big_endian,
+                _ => unreachable!(),
+            };
+            let mut head = [0; 8];
+            if !self.read_or_eof(&mut head[..4])? {
+                return Ok(None);
+            }
+            if u32_at(&head, 0, false) == Self::PCAPNG_SHB {
+                // new section, the byte order and interfaces might change
+                let big_endian = Self::read_section_header(&mut self.file)?;
+                self.format = Format::PcapNg {
+                    big_endian,
+                    interfaces: vec![],
+                };
+                continue;
+            }
+            if !self.read_or_eof(&mut head[4..])? {
+                return Ok(None);
+            }
+            let block_type = u32_at(&head, 0, big_endian);
+            let total_length = u32_at(&head, 4, big_endian) as usize;
+            if total_length < 12 || total_length > Self::MAX_BLOCK_LENGTH {
+                return Err(invalid("bad pcapng block length"));
+            }
+            let mut body = vec![0; total_length - 8];
+            if !self.read_or_eof(&mut body)? {
+                return Ok(None);
+            }
+            let body = &body[..(body.len() - 4)];
+            let interfaces = match &mut self.format {
+                Format::PcapNg { interfaces, .. } => interfaces,
+                _ => unreachable!(),
+            };
+            match block_type {
+                // interface description block
+                1 if body.len() >= 8 => {
+                    let link_type = u16_at(body, 0, big_endian) as u32;
+                    let mut nanos_per_unit = 1_000.0;
+                    let mut options = &body[8..];
+                    while options.len() >= 4 {
+                        let code = u16_at(options, 0, big_endian);
+                        let length = u16_at(options, 2, big_endian) as usize;
+                        let padded = (length + 3) & !3;
+                        if options.len() < 4 + padded {
+                            break;
+                        }
+                        // if_tsresol
+                        if code == 9 && length == 1 {
+                            let v = options[4];
+                            let units_per_second = if v & 0x80 == 0 {
+                                10.0f64.powi((v & 0x7f) as i32)
+                            } else {
+                                2.0f64.powi((v & 0x7f) as i32)
+                            };
+                            nanos_per_unit = 1e9 / units_per_second;
+                        }
+                        if code == 0 {
+                            break;
+                        }
+                        options = &options[(4 + padded)..];
+                    }
+                    interfaces.push(Interface {
+                        link_type,
+                        nanos_per_unit,
+                    });
+                },
+                // enhanced packet block
+                6 if body.len() >= 20 => {
+                    let interface = u32_at(body, 0, big_endian) as usize;
+                    let interface = interfaces
+                        .get(interface)
+                        .ok_or_else(|| invalid("packet of unknown interface"))?;
+                    let timestamp = ((u32_at(body, 4, big_endian) as u64) << 32)
+                        | (u32_at(body, 8, big_endian) as u64);
+                    let captured = (u32_at(body, 12, big_endian) as usize).min(body.len() - 20);
+                    return Ok(Some(Packet {
+                        timestamp_nanos: (timestamp as f64 * interface.nanos_per_unit) as u64,
+                        link_type: interface.link_type,
+                        data: body[20..(20 + captured)].to_vec(),
+                    }));
+                },
+                // simple packet block, has no timestamp
+                3 if body.len() >= 4 => {
+                    let interface = interfaces
+                        .first()
+                        .ok_or_else(|| invalid("packet of unknown interface"))?;
+                    return Ok(Some(Packet {
+                        timestamp_nanos: 0,
+                        link_type: interface.link_type,
+                        data: body[4..].to_vec(),
+                    }));
+                },
+                // statistics, name resolution, custom blocks and so on
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Tcp segment extracted from the packet
+struct Segment<'a> {
+    src: SocketAddr,
+    dst: SocketAddr,
+    seq: u32,
+    syn: bool,
+    ack: bool,
+    fin: bool,
+    rst: bool,
+    payload: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    fn parse(link_type: u32, data: &'a [u8]) -> Option<Self> {
+        let ip = match link_type {
+            // null/loopback, the family is in host byte order
+            0 => data.get(4..)?,
+            // ethernet
+            1 => {
+                let mut offset = 12;
+                // skip vlan tags
+                while matches!(
+                    u16_at(data.get(..(offset + 2))?, offset, true),
+                    0x8100 | 0x88a8
+                ) {
+                    offset += 4;
+                }
+                match u16_at(data, offset, true) {
+                    0x0800 | 0x86dd => data.get((offset + 2)..)?,
+                    _ => return None,
+                }
+            },
+            // raw ip
+            12 | 101 | 228 | 229 => data,
+            // linux cooked capture
+            113 => match u16_at(data.get(..16)?, 14, true) {
+                0x0800 | 0x86dd => &data[16..],
+                _ => return None,
+            },
+            // linux cooked capture v2
+            276 => match u16_at(data.get(..20)?, 0, true) {
+                0x0800 | 0x86dd => &data[20..],
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        let (src_ip, dst_ip, tcp) = match ip.first()? >> 4 {
+            4 => {
+                let header_length = ((ip[0] & 0x0f) as usize) * 4;
+                let total_length = u16_at(ip.get(..20)?, 2, true) as usize;
+                let fragment = u16_at(ip, 6, true);
+                // fragmented packets are not supported, tcp rarely fragments
+                if ip[9] != 6 || fragment & 0x3fff != 0 {
+                    return None;
+                }
+                let src = <[u8; 4]>::try_from(&ip[12..16]).unwrap();
+                let dst = <[u8; 4]>::try_from(&ip[16..20]).unwrap();
+                let end = total_length.min(ip.len());
+                (
+                    IpAddr::V4(src.into()),
+                    IpAddr::V4(dst.into()),
+                    ip.get(header_length..end)?,
+                )
+            },
+            6 => {
+                let payload_length = u16_at(ip.get(..40)?, 4, true) as usize;
+                let src = <[u8; 16]>::try_from(&ip[8..24]).unwrap();
+                let dst = <[u8; 16]>::try_from(&ip[24..40]).unwrap();
+                let mut next_header = ip[6];
+                let mut payload = &ip[40..(40 + payload_length).min(ip.len())];
+                // skip hop-by-hop, routing and destination options extension headers
+                while matches!(next_header, 0 | 43 | 60) {
+                    let length = (*payload.get(1)? as usize + 1) * 8;
+                    next_header = payload[0];
+                    payload = payload.get(length..)?;
+                }
+                if next_header != 6 {
+                    return None;
+                }
+                (IpAddr::V6(src.into()), IpAddr::V6(dst.into()), payload)
+            },
+            _ => return None,
+        };
+
+        let header_length = ((tcp.get(12)? >> 4) as usize) * 4;
+        let flags = *tcp.get(13)?;
+        Some(Segment {
+            src: SocketAddr::new(src_ip, u16_at(tcp, 0, true)),
+            dst: SocketAddr::new(dst_ip, u16_at(tcp, 2, true)),
+            seq: u32_at(tcp, 4, true),
+            syn: flags & 0x02 != 0,
+            ack: flags & 0x10 != 0,
+            fin: flags & 0x01 != 0,
+            rst: flags & 0x04 != 0,
+            payload: tcp.get(header_length..)?,
+        })
+    }
+}
+
+/// Reassembles one direction of the tcp stream
+#[derive(Default)]
+struct Stream {
+    // the sequence number of the next byte to deliver, known after syn
+    next_seq: Option<u32>,
+    // segments arrived ahead of the gap
+    pending: Vec<(u32, Vec<u8>)>,
+    pending_size: usize,
+    fin: bool,
+}
+
+impl Stream {
+    // give up the connection if the gap is not filled for so long
+    const MAX_PENDING: usize = 0x1000000;
+
+    /// Return contiguous data which became available, `None` if the stream is broken
+    fn push(&mut self, seq: u32, payload: &[u8]) -> Option<Vec<u8>> {
+        let mut output = vec![];
+        let next = match &mut self.next_seq {
+            Some(next) => next,
+            None => return Some(output),
+        };
+        if payload.is_empty() {
+            return Some(output);
+        }
+
+        let offset = seq.wrapping_sub(*next) as i32;
+        if offset > 0 {
+            self.pending_size += payload.len();
+            if self.pending_size > Self::MAX_PENDING {
+                return None;
+            }
+            self.pending.push((seq, payload.to_vec()));
+            return Some(output);
+        }
+        // retransmission, might partially overlap with the delivered data
+        let skip = (-offset) as usize;
+        if skip < payload.len() {
+            output.extend_from_slice(&payload[skip..]);
+            *next = next.wrapping_add((payload.len() - skip) as u32);
+        }
+
+        // the gap might be filled now
+        loop {
+            let next_value = *next;
+            let position = self
+                .pending
+                .iter()
+                .position(|(seq, _)| seq.wrapping_sub(next_value) as i32 <= 0);
+            match position {
+                Some(position) => {
+                    let (seq, data) = self.pending.swap_remove(position);
+                    self.pending_size -= data.len();
+                    let skip = next_value.wrapping_sub(seq) as usize;
+                    if skip < data.len() {
+                        output.extend_from_slice(&data[skip..]);
+                        *next = next.wrapping_add((data.len() - skip) as u32);
+                    }
+                },
+                None => break,
+            }
+        }
+        Some(output)
+    }
+}
+
+struct Flow {
+    socket_id: SocketId,
+    local: SocketAddr,
+    // the initial sequence number of the side which opened the connection
+    isn: u32,
+    // indexed by `incoming`
+    streams: [Stream; 2],
+}
+
+/// Yields the events reconstructed from the tcp segments in the pcap or pcapng file,
+/// the connections whose beginning is not captured are skipped,
+/// because they cannot be decrypted
+pub struct PcapCapture {
+    reader: PacketReader,
+    local_ips: Vec<IpAddr>,
+    // the synthetic pid for each watched port, the recorder binds it to the node
+    ports: Vec<(u16, u32)>,
+    flows: HashMap<(SocketAddr, SocketAddr), Flow>,
+    next_fd: u32,
+    last_timestamp: u64,
+    events: VecDeque<SnifferEvent>,
+}
+
+impl PcapCapture {
+    const BATCH: usize = 64;
+
+    /// The `local_ips` are the addresses of the node's host, used to distinguish
+    /// outgoing connections from incoming, if empty, the connection to the watched port
+    /// is considered incoming, and outgoing connections are not processed,
+    /// otherwise every connection initiated from these addresses is considered outgoing,
+    /// so the capture should be filtered to the traffic of the node
+    pub fn open<P>(path: P, local_ips: Vec<IpAddr>) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(PcapCapture {
+            reader: PacketReader::open(path)?,
+            local_ips,
+            ports: vec![],
+            flows: HashMap::new(),
+            next_fd: 1,
+            last_timestamp: 0,
+            events: VecDeque::new(),
+        })
+    }
+
+    fn flow_key(a: SocketAddr, b: SocketAddr) -> (SocketAddr, SocketAddr) {
+        if a < b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    fn pid_for(&self, local_port: u16) -> Option<u32> {
+        self.ports
+            .iter()
+            .find(|(port, _)| *port == local_port)
+            .or_else(|| self.ports.first())
+            .map(|(_, pid)| *pid)
+    }
+
+    /// Which side of the new connection is the node, and whether the connection is incoming,
+    /// the incoming connection is the one to the watched port of the node's host,
+    /// the outgoing connection is any connection initiated from the node's host,
+    /// the peers listen on arbitrary ports, so the port cannot tell
+    fn local_side(&self, segment: &Segment) -> Option<(SocketAddr, bool)> {
+        let watched = |port: u16| self.ports.iter().any(|(p, _)| *p == port);
+        if self.local_ips.contains(&segment.src.ip()) {
+            Some((segment.src, false))
+        } else if watched(segment.dst.port())
+            && (self.local_ips.is_empty() || self.local_ips.contains(&segment.dst.ip()))
+        {
+            Some((segment.dst, true))
+        } else {
+            None
+        }
+    }
+
+    fn close(&mut self, key: &(SocketAddr, SocketAddr), ts: u64) {
+        if let Some(flow) = self.flows.remove(key) {
+            let id = EventId::new(flow.socket_id, 0, ts);
+            self.events.push_back(SnifferEvent::Close { id });
+        }
+    }
+
+    fn handle_packet(&mut self, packet: Packet) {
+        let segment = match Segment::parse(packet.link_type, &packet.data) {
+            Some(s) => s,
+            None => return,
+        };
+        let ts = packet.timestamp_nanos;
+        self.last_timestamp = ts;
+        let key = Self::flow_key(segment.src, segment.dst);
+
+        if segment.syn && !segment.ack {
+            if matches!(self.flows.get(&key), Some(flow) if flow.isn == segment.seq) {
+                // retransmitted syn, the connection is already open
+                return;
+            }
+            // new connection, the old one with the same addresses is over
+            self.close(&key, ts);
+            let (local, incoming) = match self.local_side(&segment) {
+                Some(v) => v,
+                None => return,
+            };
+            let pid = match self.pid_for(local.port()) {
+                Some(pid) => pid,
+                None => return,
+            };
+            let socket_id = SocketId {
+                pid,
+                fd: self.next_fd,
+            };
+            self.next_fd += 1;
+            let id = EventId::new(socket_id, 0, ts);
+            let remote = if incoming { segment.src } else { segment.dst };
+            self.events.push_back(if incoming {
+                SnifferEvent::Accept {
+                    id,
+                    listen_on_fd: 0,
+                    address: remote,
+                }
+            } else {
+                SnifferEvent::Connect {
+                    id,
+                    address: remote,
+                }
+            });
+            let mut flow = Flow {
+                socket_id,
+                local,
+                isn: segment.seq,
+                streams: [Stream::default(), Stream::default()],
+            };
+            flow.streams[incoming as usize].next_seq = Some(segment.seq.wrapping_add(1));
+            self.flows.insert(key, flow);
+            return;
+        }
+
+        let flow = match self.flows.get_mut(&key) {
+            Some(flow) => flow,
+            None => return,
+        };
+        let incoming = segment.dst == flow.local;
+        let stream = &mut flow.streams[incoming as usize];
+        if segment.syn {
+            // syn-ack, the initial sequence number of the other direction
+            stream.next_seq = Some(segment.seq.wrapping_add(1));
+            return;
+        }
+        if segment.rst {
+            self.close(&key, ts);
+            return;
+        }
+        match stream.push(segment.seq, segment.payload) {
+            Some(data) => {
+                if !data.is_empty() {
+                    let id = EventId::new(flow.socket_id, 0, ts);
+                    self.events.push_back(SnifferEvent::Data {
+                        id,
+                        data,
+                        net: true,
+                        incoming,
+                    });
+                }
+            },
+            None => {
+                log::warn!("too many lost segments, drop the connection {}", key.1);
+                self.close(&key, ts);
+                return;
+            },
+        }
+        if segment.fin {
+            stream.fin = true;
+            if flow.streams.iter().all(|s| s.fin) {
+                self.close(&key, ts);
+            }
+        }
+    }
+}
+
+impl CaptureSource for PcapCapture {
+    fn read_events(&mut self, running: &AtomicBool) -> io::Result<Vec<SnifferEvent>> {
+        let _ = running;
+        while self.events.len() < Self::BATCH {
+            match self.reader.next_packet()? {
+                Some(packet) => self.handle_packet(packet),
+                None => {
+                    // the capture is over, close the remaining connections
+                    let keys = self.flows.keys().cloned().collect::<Vec<_>>();
+                    for key in keys {
+                        self.close(&key, self.last_timestamp);
+                    }
+                    break;
+                },
+            }
+        }
+        if self.events.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "pcap file is over",
+            ))
+        } else {
+            Ok(self.events.drain(..).collect())
+        }
+    }
+
+    /// The connections to the port belong to the node, which is bound to the synthetic pid
+    fn watch_port(&mut self, port: u16) -> io::Result<()> {
+        let pid = self.ports.len() as u32 + 1;
+        self.ports.push((port, pid));
+        let id = EventId::new(SocketId { pid, fd: 0 }, 0, 0);
+        let address = SocketAddr::new(IpAddr::from([0, 0, 0, 0]), port);
+        self.events.push_back(SnifferEvent::Bind { id, address });
+        Ok(())
+    }
+
+    fn ignore_connection(&mut self, socket_id: SocketId) -> io::Result<()> {
+        self.flows.retain(|_, flow| flow.socket_id != socket_id);
+        Ok(())
+    }
+
+    fn wall_clock(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+use thiserror::Error;
+
+/// Selects the remote address by the address with port, by the ip only,
+/// or by the subnet in CIDR notation, for example, `10.0.0.0/8` or `2001:db8::/32`
+pub enum AddrFilter {
+    Socket(SocketAddr),
+    Ip(IpAddr),
+    Subnet(IpAddr, u8),
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "invalid address filter {}, expected address, address with port, or subnet",
+    _0
+)]
+pub struct ParseAddrFilterError(String);
+
+impl FromStr for AddrFilter {
+    type Err = ParseAddrFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseAddrFilterError(s.to_string());
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(AddrFilter::Socket(canonical_socket(addr)));
+        }
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(AddrFilter::Ip(canonical(ip)));
+        }
+        let mut parts = s.splitn(2, '/');
+        let ip = parts
+            .next()
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+            .ok_or_else(error)?;
+        let length = parts
+            .next()
+            .and_then(|length| length.parse::<u8>().ok())
+            .ok_or_else(error)?;
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        if length > max {
+            return Err(error());
+        }
+        Ok(AddrFilter::Subnet(ip, length))
+    }
+}
+
+impl AddrFilter {
+    pub fn matches(&self, addr: &SocketAddr) -> bool {
+        let addr = canonical_socket(*addr);
+        match self {
+            AddrFilter::Socket(filter) => *filter == addr,
+            AddrFilter::Ip(ip) => *ip == addr.ip(),
+            AddrFilter::Subnet(network, length) => match (network, addr.ip()) {
+                (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                    let mask = u32::MAX.checked_shl(32 - *length as u32).unwrap_or(0);
+                    u32::from(*network) & mask == u32::from(ip) & mask
+                },
+                (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                    let mask = u128::MAX.checked_shl(128 - *length as u32).unwrap_or(0);
+                    u128::from(*network) & mask == u128::from(ip) & mask
+                },
+                _ => false,
+            },
+        }
+    }
+}
+
+// the address is stored as ipv6, the ipv4 address is mapped
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.octets() {
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => IpAddr::from([a, b, c, d]),
+            _ => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+fn canonical_socket(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(canonical(addr.ip()), addr.port())
+}
@@ -0,0 +1,147 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use storage::persistent::{Decoder, database::RocksDbKeyValueSchema};
+use super::{
+    rocks::{Db, DbError},
+    connection, chunk, message, node_log, peer, request, advertisement, message_ty, message_sender,
+    message_initiator, message_addr, message_cn, peer_cn, message_ref, log_level, timestamp,
+};
+
+/// The column family whose layout is versioned
+pub trait Versioned: RocksDbKeyValueSchema {
+    /// The version of the layout the recorder writes,
+    /// increment it together with adding the `Migration` from the previous version.
+    /// The database created before versioning was introduced has version 1.
+    const VERSION: u64 = 1;
+}
+
+impl Versioned for connection::Schema {
+    /// version 2 stores the lifecycle, close time, reason and counters,
+    /// version 3 is checked for protocol violations
+    const VERSION: u64 = 3;
+}
+impl Versioned for chunk::Schema {
+    /// version 2 compresses the payload
+    const VERSION: u64 = 2;
+}
+impl Versioned for message::Schema {
+    /// version 2 stores size and preview, version 3 stores the blocks and the operations
+    const VERSION: u64 = 3;
+}
+impl Versioned for node_log::Schema {
+    /// version 2 stores the host name
+    const VERSION: u64 = 2;
+}
+impl Versioned for peer::Schema {
+    /// version 2 is filled from the connections and the messages recorded before
+    const VERSION: u64 = 2;
+}
+impl Versioned for request::Schema {
+    /// version 2 is filled from the messages recorded before the pairing was introduced
+    const VERSION: u64 = 2;
+}
+impl Versioned for advertisement::Schema {
+    /// version 2 is filled from the messages recorded before the advertisements were stored
+    const VERSION: u64 = 2;
+}
+impl Versioned for message_ty::Schema {}
+impl Versioned for message_sender::Schema {}
+impl Versioned for message_initiator::Schema {}
+impl Versioned for message_addr::Schema {}
+impl Versioned for message_cn::Schema {
+    /// version 2 is filled from the messages recorded before the index was introduced
+    const VERSION: u64 = 2;
+}
+impl Versioned for peer_cn::Schema {
+    /// version 2 is filled from the connections recorded before the index was introduced
+    const VERSION: u64 = 2;
+}
+impl Versioned for message_ref::BlockSchema {}
+impl Versioned for message_ref::LevelSchema {}
+impl Versioned for message_ref::OperationSchema {}
+impl Versioned for timestamp::MessageSchema {}
+impl Versioned for log_level::Schema {}
+impl Versioned for timestamp::LogSchema {}
+
+/// Upgrade the column family `name` from the version `from` to the version `from + 1` in place
+pub struct Migration {
+    pub name: &'static str,
+    pub from: u64,
+    pub apply: fn(&Db) -> Result<(), DbError>,
+}
+
+/// All known migrations, `Db::open` applies them in order of version
+pub fn migrations() -> Vec<Migration> {
+    vec![
+        Migration {
+            name: connection::Schema::name(),
+            from: 1,
+            apply: |db| db.migrate_connections_v1(),
+        },
+        Migration {
+            name: connection::Schema::name(),
+            from: 2,
+            apply: |db| db.check_conformance(),
+        },
+        Migration {
+            name: chunk::Schema::name(),
+            from: 1,
+            apply: |db| db.rewrite_values::<chunk::Schema, _>(chunk::Value::decode_v1),
+        },
+        Migration {
+            name: message::Schema::name(),
+            from: 1,
+            apply: |db| {
+                db.rewrite_values::<message::SchemaV2, _>(|bytes| {
+                    Ok(message::ItemV1::decode(bytes)?.into())
+                })
+            },
+        },
+        Migration {
+            name: message::Schema::name(),
+            from: 2,
+            apply: |db| {
+                db.rewrite_values::<message::Schema, _>(|bytes| {
+                    let item = message::ItemV2::decode(bytes)?;
+                    Ok(db.summarize_message(item.into()))
+                })?;
+                db.fill_message_ref_indexes()
+            },
+        },
+        Migration {
+            name: node_log::Schema::name(),
+            from: 1,
+            apply: |db| {
+                db.rewrite_values::<node_log::Schema, _>(|bytes| {
+                    Ok(node_log::ItemV1::decode(bytes)?.into())
+                })
+            },
+        },
+        Migration {
+            name: message_cn::Schema::name(),
+            from: 1,
+            apply: |db| db.fill_message_cn_index(),
+        },
+        Migration {
+            name: peer_cn::Schema::name(),
+            from: 1,
+            apply: |db| db.fill_peer_cn_index(),
+        },
+        Migration {
+            name: peer::Schema::name(),
+            from: 1,
+            apply: |db| db.fill_peers(),
+        },
+        Migration {
+            name: request::Schema::name(),
+            from: 1,
+            apply: |db| db.fill_requests(),
+        },
+        Migration {
+            name: advertisement::Schema::name(),
+            from: 1,
+            apply: |db| db.fill_advertisements(),
+        },
+    ]
+}
@@ -11,11 +11,13 @@ use anyhow::Result;
 #[rustfmt::skip]
 use super::{
     // core traits
-    Database, DatabaseNew, DatabaseFetch,
+    Database, DatabaseNew, DatabaseFetch, DatabaseRetention, RetentionPolicy,
     // filters
-    ConnectionsFilter, ChunksFilter, MessagesFilter, LogsFilter,
+    ConnectionsFilter, PeersFilter, OperationsFilter, BlocksFilter, RequestsFilter, LatencyFilter,
+    DiscoveryFilter, ChunksFilter, MessagesFilter, LogsFilter, ExportFilter, ConnectionChunks,
+    ConnectionDetails, OperationReport, BlockReport, BlocksSummary, PeerLatency, DiscoveryGraph,
     // tables
-    connection, chunk, message, node_log,
+    connection, chunk, message, node_log, peer, request,
 };
 
 pub struct Db {
@@ -30,11 +32,17 @@ impl DatabaseNew for Db {
         log_full_text_index: bool,
         log_store_limit: Option<u64>,
         message_store_limit: Option<u64>,
+        chunk_payload: chunk::ChunkPayload,
     ) -> Result<Self, Self::Error>
     where
         P: AsRef<Path>,
     {
-        let _ = (log_full_text_index, log_store_limit, message_store_limit);
+        let _ = (
+            log_full_text_index,
+            log_store_limit,
+            message_store_limit,
+            chunk_payload,
+        );
 
         Ok(Db {
             file: Mutex::new(File::create(path)?),
@@ -89,6 +97,12 @@ impl Database for Db {
     }
 }
 
+impl DatabaseRetention for Db {
+    fn apply_retention(&self, policy: &RetentionPolicy) {
+        let _ = policy;
+    }
+}
+
 impl DatabaseFetch for Db {
     fn fetch_connections(
         &self,
@@ -98,6 +112,60 @@ impl DatabaseFetch for Db {
         Ok(vec![])
     }
 
+    fn fetch_connection(
+        &self,
+        key: &connection::Key,
+    ) -> Result<Option<ConnectionDetails>, Self::Error> {
+        let _ = key;
+        Ok(None)
+    }
+
+    fn fetch_peers(
+        &self,
+        filter: &PeersFilter,
+    ) -> Result<Vec<(peer::Key, peer::Value)>, Self::Error> {
+        let _ = filter;
+        Ok(vec![])
+    }
+
+    fn fetch_operations(
+        &self,
+        filter: &OperationsFilter,
+    ) -> Result<Vec<OperationReport>, Self::Error> {
+        let _ = filter;
+        Ok(vec![])
+    }
+
+    fn fetch_block_report(&self, level: u32) -> Result<BlockReport, Self::Error> {
+        Ok(BlockReport {
+            level,
+            blocks: vec![],
+        })
+    }
+
+    fn fetch_blocks_summary(&self, filter: &BlocksFilter) -> Result<BlocksSummary, Self::Error> {
+        let _ = filter;
+        Ok(BlocksSummary::new(&[]))
+    }
+
+    fn fetch_requests(
+        &self,
+        filter: &RequestsFilter,
+    ) -> Result<Vec<request::RequestFrontend>, Self::Error> {
+        let _ = filter;
+        Ok(vec![])
+    }
+
+    fn fetch_latency(&self, filter: &LatencyFilter) -> Result<Vec<PeerLatency>, Self::Error> {
+        let _ = filter;
+        Ok(vec![])
+    }
+
+    fn fetch_discovery(&self, filter: &DiscoveryFilter) -> Result<DiscoveryGraph, Self::Error> {
+        let _ = filter;
+        Ok(DiscoveryGraph::new(&[], &[]))
+    }
+
     fn fetch_chunks_truncated(
         &self,
         filter: &ChunksFilter,
@@ -128,4 +196,12 @@ impl DatabaseFetch for Db {
         let _ = filter;
         Ok(vec![])
     }
+
+    fn fetch_connection_chunks(
+        &self,
+        filter: &ExportFilter,
+    ) -> Result<Vec<ConnectionChunks>, Self::Error> {
+        let _ = filter;
+        Ok(vec![])
+    }
 }
@@ -4,12 +4,27 @@
 pub mod rocks;
 pub mod mock;
 pub mod search;
+pub mod migration;
 
 mod sorted_intersect;
+mod addr_filter;
+pub use self::addr_filter::{AddrFilter, ParseAddrFilterError};
 
 use std::{error::Error, path::Path};
-use serde::Deserialize;
-use super::{tables::*, common};
+use serde::{Serialize, Deserialize};
+use tezos_messages::p2p::encoding::{
+    connection::ConnectionMessage, metadata::MetadataMessage, ack::AckMessage,
+};
+use super::{
+    tables::*,
+    common,
+    analytics::{
+        operation::OperationReport,
+        block::{BlockReport, BlocksSummary},
+        pairing::PeerLatency,
+        discovery::DiscoveryGraph,
+    },
+};
 
 pub trait Database {
     fn store_connection(&self, item: connection::Item);
@@ -19,9 +34,111 @@ pub trait Database {
     fn store_log(&self, item: node_log::Item);
 }
 
-#[derive(Deserialize)]
+/// Limits applied periodically by the background retention task,
+/// in addition to `store_limit` enforced on every insert
+#[derive(Clone, Default)]
+pub struct RetentionPolicy {
+    /// remove messages older than this number of seconds
+    pub message_max_age: Option<u64>,
+    /// remove logs older than this number of seconds
+    pub log_max_age: Option<u64>,
+    /// remove the oldest messages and logs while the database directory is bigger than this
+    pub max_size: Option<u64>,
+}
+
+pub trait DatabaseRetention {
+    /// Remove the data which does not fit the policy,
+    /// and the data which is not referenced anymore
+    fn apply_retention(&self, policy: &RetentionPolicy);
+}
+
+#[derive(Deserialize, Default)]
 pub struct ConnectionsFilter {
+    /// `forward` from the oldest connection, default, or `backward` from the newest
+    pub direction: Option<String>,
+    pub limit: Option<u64>,
+    /// the id of the connection to start from, inclusive
+    pub cursor: Option<String>,
+    /// address, address with port, or subnet in CIDR notation
+    pub remote_addr: Option<String>,
+    pub initiator: Option<common::Initiator>,
+    /// the connections opened in the `from`..`to` range, in seconds
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub has_comments: Option<bool>,
+    /// comma separated kinds of comments, the connection has any of them,
+    /// `wrong_pow`, `too_short`, `uncertain`, `cannot_decrypt`, `suspicious`, `wrong_pk`
+    pub comments: Option<String>,
+    pub peer_id: Option<String>,
+    pub closed: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct PeersFilter {
     pub limit: Option<u64>,
+    /// the peer id to start from, inclusive
+    pub cursor: Option<String>,
+    pub peer_id: Option<String>,
+    /// address, address with port, or subnet in CIDR notation, the peer used any of them
+    pub remote_addr: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct OperationsFilter {
+    pub limit: Option<u64>,
+    /// the operations carried by the messages in the `from`..`to` range, in milliseconds,
+    /// in order of the first message, the range is at most an hour,
+    /// by default the hour before the newest message
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub operation_hash: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct BlocksFilter {
+    /// the maximal number of levels
+    pub limit: Option<u64>,
+    /// the levels carried by the messages in the `from`..`to` range, in milliseconds,
+    /// the range is at most an hour, by default the hour before the newest message
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct RequestsFilter {
+    /// `forward` from the oldest request, or `backward` from the newest, default
+    pub direction: Option<String>,
+    pub limit: Option<u64>,
+    /// the id of the request message to start from, inclusive
+    pub cursor: Option<u64>,
+    /// the connection id, for example 1617005682.953928051
+    pub cn: Option<String>,
+    pub peer_id: Option<String>,
+    /// the peer sent the request
+    pub incoming: Option<bool>,
+    /// the connection was closed before the response
+    pub unanswered: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct LatencyFilter {
+    /// the requests sent in the `from`..`to` range, in milliseconds
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    /// the requests sent by the peers, by default the requests sent by the node,
+    /// that is the latency of the peers
+    pub incoming: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct DiscoveryFilter {
+    /// the maximal number of advertisements
+    pub limit: Option<u64>,
+    /// the advertisements received in the `from`..`to` range, in milliseconds
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    /// the advertisements of the single peer
+    pub peer_id: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -36,6 +153,24 @@ pub struct MessagesFilter {
     pub limit: Option<u64>,
     pub cursor: Option<u64>,
     pub remote_addr: Option<String>,
+    /// the connection id, for example 1617005682.953928051
+    pub cn: Option<String>,
+    /// the messages of all connections with the peer
+    pub peer_id: Option<String>,
+    /// the messages which refer to the block, `current_head`, `block_header`,
+    /// `operations_for_blocks` and the requests
+    pub block_hash: Option<String>,
+    /// the messages which carry the block header of the level in the range, inclusive
+    pub level_from: Option<u32>,
+    pub level_to: Option<u32>,
+    /// the messages which carry or request the operation
+    pub operation_hash: Option<String>,
+    /// the messages whose decrypted size in bytes is in the range, inclusive
+    pub size_min: Option<u64>,
+    pub size_max: Option<u64>,
+    /// `size` returns the largest messages matching the filter, largest first,
+    /// at most 1048576 messages from the `cursor` in the `direction` are compared
+    pub sort: Option<String>,
     pub source_type: Option<common::Initiator>,
     pub incoming: Option<bool>,
     pub types: Option<String>,
@@ -60,6 +195,51 @@ pub struct LogsFilter {
     pub node_name: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct ExportFilter {
+    /// the connection to export, if not specified,
+    /// export the connections opened in the `from`..`to` range, in seconds
+    pub cn: Option<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+/// The connection with all its chunks and messages, each chunk is paired
+/// with the type of the message it belongs to, if the message is known
+pub struct ConnectionChunks {
+    pub connection: connection::Item,
+    pub chunks: Vec<(chunk::Key, chunk::Value, Option<common::MessageType>)>,
+    pub messages: Vec<(u64, message::Item)>,
+}
+
+/// The handshake messages sent by one side of the connection
+#[derive(Serialize, Default)]
+pub struct Handshake {
+    pub connection_message: Option<ConnectionMessage>,
+    pub metadata_message: Option<MetadataMessage>,
+    pub ack_message: Option<AckMessage>,
+}
+
+/// The number of messages of the type in each direction
+#[derive(Serialize)]
+pub struct MessageCount {
+    pub category: common::MessageCategory,
+    pub kind: Option<common::MessageKind>,
+    pub incoming: u64,
+    pub outgoing: u64,
+}
+
+#[derive(Serialize)]
+pub struct ConnectionDetails {
+    pub id: connection::Key,
+    #[serde(flatten)]
+    pub value: connection::Value,
+    pub local: Handshake,
+    pub remote: Handshake,
+    pub message_counts: Vec<MessageCount>,
+}
+
 pub trait DatabaseFetch
 where
     Self: DatabaseNew,
@@ -69,6 +249,34 @@ where
         filter: &ConnectionsFilter,
     ) -> Result<Vec<(connection::Key, connection::Value)>, Self::Error>;
 
+    fn fetch_connection(
+        &self,
+        key: &connection::Key,
+    ) -> Result<Option<ConnectionDetails>, Self::Error>;
+
+    fn fetch_peers(
+        &self,
+        filter: &PeersFilter,
+    ) -> Result<Vec<(peer::Key, peer::Value)>, Self::Error>;
+
+    fn fetch_operations(
+        &self,
+        filter: &OperationsFilter,
+    ) -> Result<Vec<OperationReport>, Self::Error>;
+
+    fn fetch_block_report(&self, level: u32) -> Result<BlockReport, Self::Error>;
+
+    fn fetch_blocks_summary(&self, filter: &BlocksFilter) -> Result<BlocksSummary, Self::Error>;
+
+    fn fetch_requests(
+        &self,
+        filter: &RequestsFilter,
+    ) -> Result<Vec<request::RequestFrontend>, Self::Error>;
+
+    fn fetch_latency(&self, filter: &LatencyFilter) -> Result<Vec<PeerLatency>, Self::Error>;
+
+    fn fetch_discovery(&self, filter: &DiscoveryFilter) -> Result<DiscoveryGraph, Self::Error>;
+
     fn fetch_chunks_truncated(
         &self,
         filter: &ChunksFilter,
@@ -84,6 +292,11 @@ where
     fn fetch_message(&self, id: u64) -> Result<Option<message::MessageDetails>, Self::Error>;
 
     fn fetch_log(&self, filter: &LogsFilter) -> Result<Vec<node_log::ItemWithId>, Self::Error>;
+
+    fn fetch_connection_chunks(
+        &self,
+        filter: &ExportFilter,
+    ) -> Result<Vec<ConnectionChunks>, Self::Error>;
 }
 
 pub trait DatabaseNew
@@ -97,6 +310,7 @@ where
         log_full_text_index: bool,
         log_store_limit: Option<u64>,
         message_store_limit: Option<u64>,
+        chunk_payload: chunk::ChunkPayload,
     ) -> Result<Self, Self::Error>
     where
         P: AsRef<Path>;
@@ -2,12 +2,19 @@
 // SPDX-License-Identifier: MIT
 
 use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet},
+    convert::TryFrom,
+    fs,
     net::SocketAddr,
-    ops::Add,
+    ops::{Add, Range},
     path::{Path, PathBuf},
-    sync::atomic::{Ordering, AtomicU64},
+    sync::{
+        Mutex,
+        atomic::{Ordering, AtomicU64},
+    },
 };
-use rocksdb::{Cache, DB, ReadOptions};
+use rocksdb::{Cache, DB, ReadOptions, WriteBatch, ColumnFamily, Options};
 use storage::{
     Direction, IteratorMode,
     persistent::{
@@ -16,21 +23,37 @@ use storage::{
         database::RocksDbKeyValueSchema,
     },
 };
+use crypto::hash::HashType;
 use tantivy::TantivyError;
+use serde::Serialize;
 use anyhow::Result;
 use thiserror::Error;
 use itertools::Itertools;
-use super::sorted_intersect::sorted_intersect;
+use super::{sorted_intersect::sorted_intersect, addr_filter::AddrFilter};
+use crate::analytics::{
+    Sighting, operation,
+    block::{self, BlockPropagation},
+    pairing::Pairing,
+    discovery::{self, Advertisement, Dial},
+    conformance,
+};
 #[rustfmt::skip]
 use super::{
     // core traits
-    Database, DatabaseNew, DatabaseFetch, search,
+    Database, DatabaseNew, DatabaseFetch, DatabaseRetention, RetentionPolicy, search,
+    // layout versions
+    migration::{self, Migration, Versioned},
     // filters
-    ConnectionsFilter, ChunksFilter, MessagesFilter, LogsFilter,
+    ConnectionsFilter, PeersFilter, OperationsFilter, BlocksFilter, RequestsFilter, LatencyFilter,
+    DiscoveryFilter, ChunksFilter, MessagesFilter, LogsFilter, ExportFilter, ConnectionChunks,
+    // results
+    ConnectionDetails, Handshake, MessageCount, OperationReport, BlockReport, BlocksSummary,
+    PeerLatency, DiscoveryGraph,
     // tables
-    common, connection, chunk, message, node_log,
+    common, connection, chunk, message, node_log, peer, request, advertisement, schema_version,
     // secondary indexes
-    message_ty, message_sender, message_initiator, message_addr, log_level, timestamp,
+    message_ty, message_sender, message_initiator, message_addr, message_cn, peer_cn, message_ref,
+    log_level, timestamp,
 };
 
 #[derive(Error, Debug)]
@@ -41,6 +64,19 @@ pub enum DbError {
     NoLogIndexer,
     #[error("log indexer: {}", _0)]
     LogIndexer(TantivyError),
+    #[error(
+        "column family {} has layout version {}, but version {} is expected",
+        name,
+        stored,
+        expected
+    )]
+    UnsupportedVersion {
+        name: &'static str,
+        stored: u64,
+        expected: u64,
+    },
+    #[error("no migration for column family {} from version {}", name, from)]
+    NoMigration { name: &'static str, from: u64 },
 }
 
 impl From<DBError> for DbError {
@@ -59,9 +95,23 @@ pub struct Db {
     //_cache: Cache,
     message_store_limit: Option<u64>,
     message_counter: AtomicU64,
+    chunk_payload: chunk::ChunkPayload,
     log_store_limit: Option<u64>,
     log_counter: AtomicU64,
     log_indexer: Option<search::LogIndexer>,
+    // the summary of the peer is read, modified and written back
+    peers_lock: Mutex<()>,
+    // the messages counted since the connection was stored last time,
+    // they are added to the summary of the peer when the connection is stored
+    peer_messages: Mutex<HashMap<connection::Key, peer::Value>>,
+    // the requests waiting for the response on each connection
+    pairing: Mutex<Pairing>,
+    // the connections stored since the database is open and not closed yet,
+    // the other open connections were left by the recorder which did not close them
+    live_connections: Mutex<HashSet<connection::Key>>,
+    // the column families of older layout by stored version, only in read only mode
+    outdated: BTreeMap<&'static str, u64>,
+    path: PathBuf,
     inner: DB,
 }
 
@@ -90,6 +140,7 @@ impl DatabaseNew for Db {
         log_full_text_index: bool,
         log_store_limit: Option<u64>,
         message_store_limit: Option<u64>,
+        chunk_payload: chunk::ChunkPayload,
     ) -> Result<Self, Self::Error>
     where
         P: AsRef<Path>,
@@ -101,50 +152,214 @@ impl DatabaseNew for Db {
             chunk::Schema::descriptor(&cache),
             message::Schema::descriptor(&cache),
             node_log::Schema::descriptor(&cache),
+            peer::Schema::descriptor(&cache),
+            request::Schema::descriptor(&cache),
+            advertisement::Schema::descriptor(&cache),
             message_ty::Schema::descriptor(&cache),
             message_sender::Schema::descriptor(&cache),
             message_initiator::Schema::descriptor(&cache),
             message_addr::Schema::descriptor(&cache),
+            message_cn::Schema::descriptor(&cache),
+            peer_cn::Schema::descriptor(&cache),
+            message_ref::BlockSchema::descriptor(&cache),
+            message_ref::LevelSchema::descriptor(&cache),
+            message_ref::OperationSchema::descriptor(&cache),
             timestamp::MessageSchema::descriptor(&cache),
             log_level::Schema::descriptor(&cache),
             timestamp::LogSchema::descriptor(&cache),
+            schema_version::Schema::descriptor(&cache),
         ];
         let path = PathBuf::from(path.as_ref());
         let inner =
             persistent::database::open_kv(path.join("rocksdb"), cfs, &DbConfiguration::default())?;
 
-        fn counter<S>(db: &DB) -> Option<S::Key>
-        where
-            S: RocksDbKeyValueSchema,
-            S::Key: Add<u64, Output = S::Key>,
-        {
-            KeyValueStoreWithSchemaIterator::<S>::iterator(db, IteratorMode::End)
-                .ok()?
-                .next()?
-                .0
-                .ok()
-                .map(|c| c + 1)
-        }
-
-        let log_indexer = if log_full_text_index {
-            Some(search::LogIndexer::try_new(path.join("tantivy"))?)
-        } else {
-            None
-        };
-
-        Ok(Db {
+        let mut db = Db {
             message_store_limit,
             message_counter: AtomicU64::new(counter::<message::Schema>(&inner).unwrap_or(0)),
+            chunk_payload,
             log_store_limit,
             log_counter: AtomicU64::new(counter::<node_log::Schema>(&inner).unwrap_or(0)),
-            log_indexer,
+            log_indexer: None,
+            peers_lock: Mutex::new(()),
+            peer_messages: Mutex::new(HashMap::new()),
+            pairing: Mutex::new(Pairing::default()),
+            live_connections: Mutex::new(HashSet::new()),
+            outdated: BTreeMap::new(),
+            path,
             inner,
-        })
+        };
+        db.migrate(false, &mut BTreeMap::new())?;
+        db.restore_pending_requests()?;
+
+        if log_full_text_index {
+            let log_indexer = search::LogIndexer::try_new(db.path.join("tantivy"))?;
+            if log_indexer.is_empty()? {
+                // the index might be dropped or never created, fill it from the stored logs
+                db.as_kv::<node_log::Schema>()
+                    .iterator(IteratorMode::Start)?
+                    .filter_map(|(k, v)| Some((k.ok()?, v.ok()?)))
+                    .for_each(|(id, item)| log_indexer.write(&item.message, id));
+            }
+            db.log_indexer = Some(log_indexer);
+        }
+
+        Ok(db)
     }
 }
 
 impl Db {
+    /// Number of items processed in a single write batch by maintenance routines
+    const BATCH_SIZE: usize = 0x400;
+
+    fn cf<S>(&self) -> Result<&ColumnFamily, DBError>
+    where
+        S: RocksDbKeyValueSchema,
+    {
+        self.inner
+            .cf_handle(S::name())
+            .ok_or(DBError::MissingColumnFamily { name: S::name() })
+    }
+
+    fn batch_put<S>(
+        &self,
+        batch: &mut WriteBatch,
+        key: &S::Key,
+        value: &S::Value,
+    ) -> Result<(), DBError>
+    where
+        S: KeyValueSchema + RocksDbKeyValueSchema,
+    {
+        let key = key
+            .encode()
+            .map_err(|error| DBError::SchemaError { error })?;
+        let value = value
+            .encode()
+            .map_err(|error| DBError::SchemaError { error })?;
+        batch.put_cf(self.cf::<S>()?, key, value);
+        Ok(())
+    }
+
+    fn batch_delete<S>(&self, batch: &mut WriteBatch, key: &S::Key) -> Result<(), DBError>
+    where
+        S: KeyValueSchema + RocksDbKeyValueSchema,
+    {
+        let key = key
+            .encode()
+            .map_err(|error| DBError::SchemaError { error })?;
+        batch.delete_cf(self.cf::<S>()?, key);
+        Ok(())
+    }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), DBError> {
+        self.inner
+            .write(batch)
+            .map_err(|error| DBError::RocksDBError { error })
+    }
+
+    fn put_message_indexes(
+        &self,
+        index: u64,
+        item: &message::Item,
+        batch: &mut WriteBatch,
+    ) -> Result<(), DBError> {
+        let ty_index = message_ty::Item {
+            ty: item.ty.clone(),
+            index,
+        };
+        let sender_index = message_sender::Item {
+            sender: item.sender.clone(),
+            index,
+        };
+        let initiator_index = message_initiator::Item {
+            initiator: item.initiator.clone(),
+            index,
+        };
+        let addr_index = message_addr::Item {
+            addr: item.remote_addr,
+            index,
+        };
+        let cn_index = message_cn::Item {
+            cn_id: item.cn_id(),
+            index,
+        };
+        let timestamp_index = timestamp::Item {
+            timestamp: item.timestamp,
+            index,
+        };
+        self.batch_put::<message_ty::Schema>(batch, &ty_index, &())?;
+        self.batch_put::<message_sender::Schema>(batch, &sender_index, &())?;
+        self.batch_put::<message_initiator::Schema>(batch, &initiator_index, &())?;
+        self.batch_put::<message_addr::Schema>(batch, &addr_index, &())?;
+        self.batch_put::<message_cn::Schema>(batch, &cn_index, &())?;
+        self.batch_message_refs(index, &item.refs, batch, false)?;
+        self.batch_put::<timestamp::MessageSchema>(batch, &timestamp_index, &())?;
+        Ok(())
+    }
+
+    /// Put into the `batch` the index entries of the blocks and the operations
+    /// the message refers to, or their removal
+    fn batch_message_refs(
+        &self,
+        index: u64,
+        refs: &message::Refs,
+        batch: &mut WriteBatch,
+        remove: bool,
+    ) -> Result<(), DBError> {
+        for &hash in &refs.blocks {
+            let key = message_ref::HashItem { hash, index };
+            if remove {
+                self.batch_delete::<message_ref::BlockSchema>(batch, &key)?;
+            } else {
+                self.batch_put::<message_ref::BlockSchema>(batch, &key, &())?;
+            }
+        }
+        for &level in &refs.levels {
+            let key = message_ref::LevelItem { level, index };
+            if remove {
+                self.batch_delete::<message_ref::LevelSchema>(batch, &key)?;
+            } else {
+                self.batch_put::<message_ref::LevelSchema>(batch, &key, &())?;
+            }
+        }
+        for &hash in &refs.operations {
+            let key = message_ref::HashItem { hash, index };
+            if remove {
+                self.batch_delete::<message_ref::OperationSchema>(batch, &key)?;
+            } else {
+                self.batch_put::<message_ref::OperationSchema>(batch, &key, &())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn put_log_indexes(
+        &self,
+        index: u64,
+        item: &node_log::Item,
+        batch: &mut WriteBatch,
+    ) -> Result<(), DBError> {
+        let lv_index = log_level::Item {
+            lv: item.level.clone(),
+            index,
+        };
+        let timestamp_index = timestamp::Item {
+            timestamp: (item.timestamp / 1_000_000) as u64,
+            index,
+        };
+        self.batch_put::<log_level::Schema>(batch, &lv_index, &())?;
+        self.batch_put::<timestamp::LogSchema>(batch, &timestamp_index, &())?;
+        Ok(())
+    }
+
     pub fn remove_message(&self, index: u64) -> Result<(), DbError> {
+        let mut batch = WriteBatch::default();
+        self.remove_message_batch(index, &mut batch)?;
+        self.write_batch(batch).map_err(Into::into)
+    }
+
+    /// Put into the `batch` removal of the message at `index`,
+    /// its chunks and all its secondary index entries
+    fn remove_message_batch(&self, index: u64, batch: &mut WriteBatch) -> Result<(), DbError> {
         if let Some(item) = self.as_kv::<message::Schema>().get(&index)? {
             let ty_index = message_ty::Item {
                 ty: item.ty.clone(),
@@ -162,29 +377,55 @@ impl Db {
                 addr: item.remote_addr,
                 index,
             };
+            let cn_index = message_cn::Item {
+                cn_id: item.cn_id(),
+                index,
+            };
             let timestamp_index = timestamp::Item {
                 timestamp: item.timestamp,
                 index,
             };
 
             for chunk_key in item.chunks() {
-                self.as_kv::<chunk::Schema>().delete(&chunk_key)?;
+                self.batch_delete::<chunk::Schema>(batch, &chunk_key)?;
             }
 
-            self.as_kv::<message_ty::Schema>().delete(&ty_index)?;
-            self.as_kv::<message_sender::Schema>()
-                .delete(&sender_index)?;
-            self.as_kv::<message_initiator::Schema>()
-                .delete(&initiator_index)?;
-            self.as_kv::<message_addr::Schema>().delete(&addr_index)?;
-            self.as_kv::<timestamp::MessageSchema>()
-                .delete(&timestamp_index)?;
-            self.as_kv::<message::Schema>().delete(&index)?;
+            self.batch_delete::<message_ty::Schema>(batch, &ty_index)?;
+            self.batch_delete::<message_sender::Schema>(batch, &sender_index)?;
+            self.batch_delete::<message_initiator::Schema>(batch, &initiator_index)?;
+            self.batch_delete::<message_addr::Schema>(batch, &addr_index)?;
+            self.batch_delete::<message_cn::Schema>(batch, &cn_index)?;
+            self.batch_message_refs(index, &item.refs, batch, true)?;
+            self.batch_delete::<timestamp::MessageSchema>(batch, &timestamp_index)?;
+            self.batch_delete::<advertisement::Schema>(batch, &index)?;
+            self.batch_delete::<message::Schema>(batch, &index)?;
         }
+        // the message of several requested items has a request for each of them
+        let encode = |index| {
+            request::Key { index, part: 0 }
+                .encode()
+                .map_err(|error| DBError::SchemaError { error })
+        };
+        batch.delete_range_cf(
+            self.cf::<request::Schema>()?,
+            encode(index)?,
+            encode(index + 1)?,
+        );
         Ok(())
     }
 
     pub fn remove_log(&self, index: u64) -> Result<(), DbError> {
+        let mut batch = WriteBatch::default();
+        self.remove_log_batch(index, &mut batch)?;
+        self.write_batch(batch)?;
+        self.unindex_logs(&[index]);
+        Ok(())
+    }
+
+    /// Put into the `batch` removal of the log at `index`
+    /// and all its secondary index entries, the search document is removed
+    /// by `unindex_logs` after the batch is written
+    fn remove_log_batch(&self, index: u64, batch: &mut WriteBatch) -> Result<(), DbError> {
         if let Some(item) = self.as_kv::<node_log::Schema>().get(&index)? {
             let lv_index = log_level::Item {
                 lv: item.level.clone(),
@@ -195,19 +436,232 @@ impl Db {
                 index,
             };
 
-            self.as_kv::<log_level::Schema>().delete(&lv_index)?;
-            self.as_kv::<timestamp::LogSchema>()
-                .delete(&timestamp_index)?;
-            self.as_kv::<node_log::Schema>().delete(&index)?;
+            self.batch_delete::<log_level::Schema>(batch, &lv_index)?;
+            self.batch_delete::<timestamp::LogSchema>(batch, &timestamp_index)?;
+            self.batch_delete::<node_log::Schema>(batch, &index)?;
         }
         Ok(())
     }
+
+    /// Remove the search documents of the logs whose removal is written
+    fn unindex_logs(&self, indexes: &[u64]) {
+        if let Some(log_indexer) = &self.log_indexer {
+            for &index in indexes {
+                log_indexer.remove(index);
+            }
+        }
+    }
+
+    /// The connection has at least one stored message
+    fn has_messages(&self, cn_id: &connection::Key) -> Result<bool, DbError> {
+        let prefix = cn_id
+            .encode()
+            .map_err(|error| DBError::SchemaError { error })?;
+        let start = message_cn::Item {
+            cn_id: cn_id.clone(),
+            index: 0,
+        }
+        .encode()
+        .map_err(|error| DBError::SchemaError { error })?;
+        let mut opts = ReadOptions::default();
+        opts.set_prefix_same_as_start(true);
+        let mode = rocksdb::IteratorMode::From(&start, rocksdb::Direction::Forward);
+        let found = self
+            .inner
+            .iterator_cf_opt(self.cf::<message_cn::Schema>()?, opts, mode)
+            .next()
+            .map_or(false, |(k, _)| k.starts_with(&prefix));
+        Ok(found)
+    }
+
+    /// Load all chunks of the connection, and the types of the messages they belong to
+    fn connection_chunks(&self, connection: connection::Item) -> Result<ConnectionChunks, DbError> {
+        let cn_id = connection.key();
+        let prefix = cn_id
+            .encode()
+            .map_err(|error| DBError::SchemaError { error })?;
+        let mut opts = ReadOptions::default();
+        opts.set_prefix_same_as_start(true);
+        let mode = rocksdb::IteratorMode::From(&prefix, rocksdb::Direction::Forward);
+        let mut chunks = self
+            .inner
+            .iterator_cf_opt(self.cf::<chunk::Schema>()?, opts, mode)
+            .take_while(|(k, _)| k.starts_with(&prefix))
+            .filter_map(
+                |(k, v)| match (chunk::Key::decode(&k), chunk::Value::decode(&v)) {
+                    (Ok(key), Ok(value)) => Some((key, value, None)),
+                    (Ok(key), Err(err)) => {
+                        log::warn!("Failed to load value at {}: {}", key, err);
+                        None
+                    },
+                    (Err(err), _) => {
+                        log::warn!("Failed to load index: {}", err);
+                        None
+                    },
+                },
+            )
+            .collect::<Vec<_>>();
+
+        let messages = self.connection_messages(&connection)?;
+        for (_, message) in &messages {
+            for key in message.chunks() {
+                let position = chunks
+                    .binary_search_by_key(&(key.counter, key.sender.incoming()), |(k, ..)| {
+                        (k.counter, k.sender.incoming())
+                    });
+                if let Ok(position) = position {
+                    chunks[position].2 = Some(message.ty.clone());
+                }
+            }
+        }
+
+        Ok(ConnectionChunks {
+            connection,
+            chunks,
+            messages,
+        })
+    }
+
+    /// Load all messages of the connection with their ids
+    fn connection_messages(
+        &self,
+        connection: &connection::Item,
+    ) -> Result<Vec<(u64, message::Item)>, DbError> {
+        let start = message_cn::Item {
+            cn_id: connection.key(),
+            index: 0,
+        }
+        .encode()
+        .map_err(|error| DBError::SchemaError { error })?;
+        let mut opts = ReadOptions::default();
+        opts.set_prefix_same_as_start(true);
+        let mode = rocksdb::IteratorMode::From(&start, rocksdb::Direction::Forward);
+        let indexes = self
+            .inner
+            .iterator_cf_opt(self.cf::<message_cn::Schema>()?, opts, mode)
+            .filter_map(|(k, _)| Some(message_cn::Item::decode(&k).ok()?.index));
+        let mut messages = vec![];
+        for index in indexes {
+            if let Some(message) = self.as_kv::<message::Schema>().get(&index)? {
+                messages.push((index, message));
+            }
+        }
+
+        Ok(messages)
+    }
+
+    /// Decode the handshake messages sent by one side of the connection
+    fn handshake(&self, cn_id: &connection::Key, incoming: bool) -> Result<Handshake, DbError> {
+        use self::message::TezosMessage;
+
+        let tys = [
+            common::MessageType::Connection,
+            common::MessageType::Meta,
+            common::MessageType::Ack,
+        ];
+        let mut handshake = Handshake::default();
+        for (counter, ty) in tys.iter().enumerate() {
+            let key = chunk::Key {
+                cn_id: cn_id.clone(),
+                counter: counter as u64,
+                sender: common::Sender::new(incoming),
+            };
+            let chunk = match self.as_kv::<chunk::Schema>().get(&key)? {
+                Some(chunk) => chunk,
+                None => continue,
+            };
+            // the connection message is not encrypted, the plain bytes might be not stored
+            let plain = if chunk.plain.is_empty() && counter == 0 && chunk.bytes.len() > 2 {
+                &chunk.bytes[2..]
+            } else {
+                &chunk.plain
+            };
+            match TezosMessage::decode(ty, plain) {
+                Ok(TezosMessage::ConnectionMessage(m)) => handshake.connection_message = Some(m),
+                Ok(TezosMessage::MetadataMessage(m)) => handshake.metadata_message = Some(m),
+                Ok(TezosMessage::AckMessage(m)) => handshake.ack_message = Some(m),
+                Ok(TezosMessage::PeerMessage(_)) => (),
+                Err(error) => log::warn!("cannot decode handshake message {}: {}", key, error),
+            }
+        }
+
+        Ok(handshake)
+    }
+
+    /// Remove connections which are older than the oldest stored message
+    /// and have no messages left, together with all their chunks,
+    /// including the chunks which never became a part of any message,
+    /// the connection must be closed, or left open by the recorder which did not close it;
+    /// without messages, the boundary is the start of the retention window `max_age`,
+    /// in seconds, or the newest connection, `now` is milliseconds since epoch
+    fn collect_garbage(&self, max_age: Option<u64>, now: u64) -> Result<u64, DbError> {
+        let oldest_message = self
+            .as_kv::<message::Schema>()
+            .iterator(IteratorMode::Start)?
+            .next()
+            .and_then(|(_, v)| v.ok());
+        let boundary = match (oldest_message, max_age) {
+            (Some(item), _) => item.timestamp / 1000,
+            // all messages are evicted, or none was ever stored
+            (None, Some(max_age)) => (now / 1000).saturating_sub(max_age),
+            (None, None) => match self
+                .as_kv::<connection::Schema>()
+                .iterator(IteratorMode::End)?
+                .next()
+            {
+                Some((Ok(newest), _)) => newest.ts,
+                _ => return Ok(0),
+            },
+        };
+        let live = self.live_connections.lock().unwrap().clone();
+        let candidates = self
+            .as_kv::<connection::Schema>()
+            .iterator(IteratorMode::Start)?
+            .filter_map(|(k, v)| Some((k.ok()?, v.ok()?)))
+            .take_while(|(k, _)| k.ts < boundary)
+            .filter(|(k, v)| v.lifecycle().closed_at.is_some() || !live.contains(k))
+            .collect::<Vec<_>>();
+
+        let mut batch = WriteBatch::default();
+        let mut removed = 0;
+        for (cn_id, value) in candidates {
+            if self.has_messages(&cn_id)? {
+                continue;
+            }
+            let begin = chunk::Key::begin(cn_id.clone())
+                .encode()
+                .map_err(|error| DBError::SchemaError { error })?;
+            let end = chunk::Key::end(cn_id.clone())
+                .encode()
+                .map_err(|error| DBError::SchemaError { error })?;
+            batch.delete_range_cf(self.cf::<chunk::Schema>()?, begin, end);
+            if let Some(peer) = peer::Key::from_pk(&value.peer_pk()) {
+                let item = peer_cn::Item {
+                    peer,
+                    cn_id: cn_id.clone(),
+                };
+                self.batch_delete::<peer_cn::Schema>(&mut batch, &item)?;
+            }
+            self.batch_delete::<connection::Schema>(&mut batch, &cn_id)?;
+            removed += 1;
+        }
+        self.write_batch(batch)?;
+        Ok(removed)
+    }
 }
 
 impl Database for Db {
     fn store_connection(&self, item: connection::Item) {
         let (key, value) = item.split();
-        if let Err(error) = self.as_kv::<connection::Schema>().put(&key, &value) {
+        let inner = || -> Result<(), DbError> {
+            if value.lifecycle().closed_at.is_none() {
+                self.live_connections.lock().unwrap().insert(key.clone());
+            }
+            self.as_kv::<connection::Schema>().put(&key, &value)?;
+            self.index_peer_connection(&key, None, &value)?;
+            self.count_peer_connection(&key, None, &value)
+        };
+        if let Err(error) = inner() {
             log::error!("database error: {}", error);
         }
     }
@@ -215,12 +669,24 @@ impl Database for Db {
     fn update_connection(&self, item: connection::Item) {
         let (key, value) = item.split();
         let kv = self.as_kv::<connection::Schema>();
-        if let Err(error) = kv.delete(&key).and_then(|()| kv.put(&key, &value)) {
+        let inner = || -> Result<(), DbError> {
+            let old = kv.get(&key)?;
+            kv.put(&key, &value)?;
+            self.index_peer_connection(&key, old.as_ref(), &value)?;
+            if value.lifecycle().closed_at.is_some() {
+                self.live_connections.lock().unwrap().remove(&key);
+                let requests = self.pairing.lock().unwrap().close(&key);
+                self.store_requests(requests)?;
+            }
+            self.count_peer_connection(&key, old.as_ref(), &value)
+        };
+        if let Err(error) = inner() {
             log::error!("database error: {}", error);
         }
     }
 
-    fn store_chunk(&self, item: chunk::Item) {
+    fn store_chunk(&self, mut item: chunk::Item) {
+        item.retain(self.chunk_payload);
         let (key, value) = item.split();
         if let Err(error) = self.as_kv::<chunk::Schema>().put(&key, &value) {
             log::error!("database error: {}", error);
@@ -229,82 +695,1339 @@ impl Database for Db {
 
     fn store_message(&self, item: message::Item) {
         let index = self.reserve_message_counter();
-        if let Some(store_limit) = self.message_store_limit {
-            if index >= store_limit {
-                if let Err(error) = self.remove_message(index - store_limit) {
-                    log::error!("database error: {}", error);
+
+        let inner = || -> Result<(), DbError> {
+            let mut batch = WriteBatch::default();
+            let mut evicted = None;
+            if let Some(store_limit) = self.message_store_limit {
+                if index >= store_limit {
+                    self.remove_message_batch(index - store_limit, &mut batch)?;
+                    evicted = Some(index - store_limit);
                 }
             }
+            self.put_message_indexes(index, &item, &mut batch)?;
+            if let Some(value) = advertisement::Value::new(&item) {
+                self.batch_put::<advertisement::Schema>(&mut batch, &index, &value)?;
+            }
+            self.batch_put::<message::Schema>(&mut batch, &index, &item)?;
+            // the request records are written together with the message,
+            // the records of the message removed by this batch are dropped
+            let mut requests = self.pairing.lock().unwrap().message(index, &item);
+            requests.retain(|(key, _)| Some(key.index) != evicted);
+            self.batch_requests(&requests, Some(index), &mut batch)?;
+            self.write_batch(batch)?;
+            self.count_peer_message(&item);
+            Ok(())
+        };
+        if let Err(error) = inner() {
+            log::error!("database error: {}", error);
         }
+    }
 
-        let ty_index = message_ty::Item {
-            ty: item.ty.clone(),
-            index,
+    fn store_log(&self, item: node_log::Item) {
+        let index = self.reserve_log_counter();
+
+        let inner = || -> Result<(), DbError> {
+            let mut batch = WriteBatch::default();
+            let mut evicted = None;
+            if let Some(store_limit) = self.log_store_limit {
+                if index >= store_limit {
+                    self.remove_log_batch(index - store_limit, &mut batch)?;
+                    evicted = Some(index - store_limit);
+                }
+            }
+            self.put_log_indexes(index, &item, &mut batch)?;
+            self.batch_put::<node_log::Schema>(&mut batch, &index, &item)?;
+            self.write_batch(batch)?;
+            if let Some(evicted) = evicted {
+                self.unindex_logs(&[evicted]);
+            }
+            Ok(())
         };
-        let sender_index = message_sender::Item {
-            sender: item.sender.clone(),
-            index,
+        if let Some(log_indexer) = &self.log_indexer {
+            log_indexer.write(&item.message, index);
+        }
+        if let Err(error) = inner() {
+            log::error!("database error: {}", error);
+        }
+    }
+}
+
+impl Drop for Db {
+    fn drop(&mut self) {
+        // the connection might be not updated since its last messages
+        if let Err(error) = self.flush_peer_messages() {
+            log::error!("database error: {}", error);
+        }
+    }
+}
+
+impl Db {
+    /// Remove every item whose timestamp secondary index entry is less than `timestamp`,
+    /// `removed` is called with the indexes of each written batch
+    fn remove_before<S>(
+        &self,
+        timestamp: u64,
+        remove: impl Fn(u64, &mut WriteBatch) -> Result<(), DbError>,
+        removed: impl Fn(&[u64]),
+    ) -> Result<u64, DbError>
+    where
+        S: KeyValueSchema<Key = timestamp::Item, Value = ()> + RocksDbKeyValueSchema,
+    {
+        let mut count = 0;
+        loop {
+            let keys = self
+                .as_kv::<S>()
+                .iterator(IteratorMode::Start)?
+                .filter_map(|(k, _)| k.ok())
+                .take_while(|k| k.timestamp < timestamp)
+                .take(Self::BATCH_SIZE)
+                .collect::<Vec<_>>();
+            if keys.is_empty() {
+                break Ok(count);
+            }
+            let mut batch = WriteBatch::default();
+            for key in &keys {
+                remove(key.index, &mut batch)?;
+                // the index entry might be dangling, remove it explicitly
+                self.batch_delete::<S>(&mut batch, key)?;
+            }
+            self.write_batch(batch)?;
+            removed(&keys.iter().map(|k| k.index).collect::<Vec<_>>());
+            count += keys.len() as u64;
+        }
+    }
+
+    /// Remove the oldest `fraction` of items in the table `S`, at least one item,
+    /// `removed` is called with the indexes of each written batch
+    fn remove_oldest<S>(
+        &self,
+        end: u64,
+        fraction: u64,
+        remove: impl Fn(u64, &mut WriteBatch) -> Result<(), DbError>,
+        removed: impl Fn(&[u64]),
+    ) -> Result<u64, DbError>
+    where
+        S: KeyValueSchema<Key = u64> + RocksDbKeyValueSchema,
+    {
+        let begin = match self.as_kv::<S>().iterator(IteratorMode::Start)?.next() {
+            Some((Ok(begin), _)) => begin,
+            _ => return Ok(0),
         };
-        let initiator_index = message_initiator::Item {
-            initiator: item.initiator.clone(),
-            index,
+        let count = ((end.saturating_sub(begin)) / fraction).max(1);
+        let mut batch = WriteBatch::default();
+        let mut indexes = vec![];
+        for index in begin..(begin + count) {
+            remove(index, &mut batch)?;
+            indexes.push(index);
+            if batch.len() >= Self::BATCH_SIZE {
+                self.write_batch(std::mem::take(&mut batch))?;
+                removed(&std::mem::take(&mut indexes));
+            }
+        }
+        self.write_batch(batch)?;
+        removed(&indexes);
+        Ok(count)
+    }
+
+    /// Total size of files of the key-value store, the log index is not counted,
+    /// removing the logs barely shrinks it until its segments are merged
+    fn size_on_disk(&self) -> u64 {
+        fn dir_size(path: &Path) -> u64 {
+            match fs::read_dir(path) {
+                Ok(entries) => entries
+                    .filter_map(Result::ok)
+                    .map(|entry| match entry.metadata() {
+                        Ok(m) if m.is_dir() => dir_size(&entry.path()),
+                        Ok(m) => m.len(),
+                        Err(_) => 0,
+                    })
+                    .sum(),
+                Err(_) => 0,
+            }
+        }
+
+        dir_size(&self.path.join("rocksdb"))
+    }
+
+    fn compact(&self) {
+        let names = [
+            connection::Schema::name(),
+            chunk::Schema::name(),
+            message::Schema::name(),
+            node_log::Schema::name(),
+            peer::Schema::name(),
+            request::Schema::name(),
+            advertisement::Schema::name(),
+            message_ty::Schema::name(),
+            message_sender::Schema::name(),
+            message_initiator::Schema::name(),
+            message_addr::Schema::name(),
+            message_cn::Schema::name(),
+            peer_cn::Schema::name(),
+            message_ref::BlockSchema::name(),
+            message_ref::LevelSchema::name(),
+            message_ref::OperationSchema::name(),
+            timestamp::MessageSchema::name(),
+            log_level::Schema::name(),
+            timestamp::LogSchema::name(),
+            schema_version::Schema::name(),
+        ];
+        for name in &names {
+            if let Some(cf) = self.inner.cf_handle(name) {
+                self.inner
+                    .compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>);
+            }
+        }
+    }
+
+    fn shrink(&self, max_size: u64) -> Result<(), DbError> {
+        // removing 1/10 of the stored items per iteration
+        const FRACTION: u64 = 10;
+
+        // the space is reclaimed only after compaction
+        let mut size = self.size_on_disk();
+        while size > max_size {
+            let messages = self.remove_oldest::<message::Schema>(
+                self.message_counter.load(Ordering::SeqCst),
+                FRACTION,
+                |index, batch| self.remove_message_batch(index, batch),
+                |_| (),
+            )?;
+            let logs = self.remove_oldest::<node_log::Schema>(
+                self.log_counter.load(Ordering::SeqCst),
+                FRACTION,
+                |index, batch| self.remove_log_batch(index, batch),
+                |indexes| self.unindex_logs(indexes),
+            )?;
+            if messages == 0 && logs == 0 {
+                log::warn!(
+                    "database at {:?} is empty, but still exceeds size limit {}",
+                    self.path,
+                    max_size,
+                );
+                break;
+            }
+            log::info!(
+                "database size limit {} exceeded, removed {} messages and {} logs",
+                max_size,
+                messages,
+                logs,
+            );
+            self.compact();
+            let new_size = self.size_on_disk();
+            if new_size >= size {
+                log::warn!(
+                    "removing items did not shrink database at {:?}, size {}, limit {}",
+                    self.path,
+                    new_size,
+                    max_size,
+                );
+                break;
+            }
+            size = new_size;
+        }
+        Ok(())
+    }
+}
+
+impl DatabaseRetention for Db {
+    fn apply_retention(&self, policy: &RetentionPolicy) {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        if let Some(max_age) = policy.message_max_age {
+            let remove = |index, batch: &mut WriteBatch| self.remove_message_batch(index, batch);
+            let before = now.saturating_sub(max_age * 1000);
+            match self.remove_before::<timestamp::MessageSchema>(before, remove, |_| ()) {
+                Ok(0) => (),
+                Ok(removed) => log::info!("retention removed {} messages", removed),
+                Err(error) => log::error!("database error: {}", error),
+            }
+        }
+        if let Some(max_age) = policy.log_max_age {
+            let remove = |index, batch: &mut WriteBatch| self.remove_log_batch(index, batch);
+            let before = now.saturating_sub(max_age * 1000);
+            let removed = |indexes: &[u64]| self.unindex_logs(indexes);
+            match self.remove_before::<timestamp::LogSchema>(before, remove, removed) {
+                Ok(0) => (),
+                Ok(removed) => log::info!("retention removed {} logs", removed),
+                Err(error) => log::error!("database error: {}", error),
+            }
+        }
+        if let Some(max_size) = policy.max_size {
+            if let Err(error) = self.shrink(max_size) {
+                log::error!("database error: {}", error);
+            }
+        }
+        match self.collect_garbage(policy.message_max_age, now) {
+            Ok(0) => (),
+            Ok(removed) => log::info!("garbage collector removed {} connections", removed),
+            Err(error) => log::error!("database error: {}", error),
+        }
+    }
+}
+
+impl Db {
+    /// In read only mode the column families of older layout are collected in `outdated`
+    fn migrate(
+        &self,
+        read_only: bool,
+        outdated: &mut BTreeMap<&'static str, u64>,
+    ) -> Result<(), DbError> {
+        let migrations = migration::migrations();
+        self.migrate_cf::<chunk::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<message::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<node_log::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<message_ty::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<message_sender::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<message_initiator::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<message_addr::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<message_cn::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<message_ref::BlockSchema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<message_ref::LevelSchema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<message_ref::OperationSchema>(&migrations, read_only, outdated)?;
+        // the conformance check replays the messages, they must be migrated first
+        self.migrate_cf::<connection::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<peer_cn::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<peer::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<request::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<advertisement::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<timestamp::MessageSchema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<log_level::Schema>(&migrations, read_only, outdated)?;
+        self.migrate_cf::<timestamp::LogSchema>(&migrations, read_only, outdated)?;
+        Ok(())
+    }
+
+    /// Bring the layout of the column family `S` to `S::VERSION` applying the migrations,
+    /// in read only mode just verify the version and collect the older one in `outdated`
+    fn migrate_cf<S>(
+        &self,
+        migrations: &[Migration],
+        read_only: bool,
+        outdated: &mut BTreeMap<&'static str, u64>,
+    ) -> Result<(), DbError>
+    where
+        S: Versioned,
+    {
+        let key = schema_version::Key(S::name().to_string());
+        let stored = if self
+            .inner
+            .cf_handle(schema_version::Schema::name())
+            .is_some()
+        {
+            self.as_kv::<schema_version::Schema>().get(&key)?
+        } else {
+            None
         };
-        let addr_index = message_addr::Item {
-            addr: item.remote_addr,
-            index,
+        // the database created before versioning was introduced has the first layout
+        let mut version = stored.unwrap_or(1);
+        if version > S::VERSION {
+            return Err(DbError::UnsupportedVersion {
+                name: S::name(),
+                stored: version,
+                expected: S::VERSION,
+            });
+        }
+        if read_only {
+            // the absent column family is created on open, it needs migration too
+            if self.inner.cf_handle(S::name()).is_none() {
+                outdated.insert(S::name(), 0);
+            } else if version < S::VERSION {
+                outdated.insert(S::name(), version);
+            }
+            return Ok(());
+        }
+
+        while version < S::VERSION {
+            let migration = migrations
+                .iter()
+                .find(|m| m.name == S::name() && m.from == version)
+                .ok_or(DbError::NoMigration {
+                    name: S::name(),
+                    from: version,
+                })?;
+            log::info!("migrating {} from version {}", S::name(), version);
+            (migration.apply)(self)?;
+            version += 1;
+            self.as_kv::<schema_version::Schema>().put(&key, &version)?;
+        }
+        if stored.is_none() {
+            self.as_kv::<schema_version::Schema>().put(&key, &version)?;
+        }
+
+        Ok(())
+    }
+
+    /// The ids of the messages of the connection starting from the `cursor`
+    fn message_cn_indexes(
+        &self,
+        cn_id: connection::Key,
+        cursor: u64,
+        direction: Direction,
+    ) -> Result<impl Iterator<Item = u64> + '_, DbError> {
+        let key = message_cn::Item {
+            cn_id,
+            index: cursor,
+        }
+        .encode()
+        .map_err(|error| DBError::SchemaError { error })?;
+        let mode = rocksdb::IteratorMode::From(&key, direction.into());
+        let mut opts = ReadOptions::default();
+        opts.set_prefix_same_as_start(true);
+        let it = self
+            .inner
+            .iterator_cf_opt(self.cf::<message_cn::Schema>()?, opts, mode)
+            .filter_map(|(k, _)| Some(message_cn::Item::decode(&k).ok()?.index));
+        Ok(it)
+    }
+
+    /// The ids of the connections of the peer
+    fn peer_cn_indexes(
+        &self,
+        peer: peer::Key,
+    ) -> Result<impl Iterator<Item = connection::Key> + '_, DbError> {
+        let key = peer_cn::Item {
+            peer,
+            cn_id: connection::Key { ts: 0, ts_nanos: 0 },
+        }
+        .encode()
+        .map_err(|error| DBError::SchemaError { error })?;
+        let mode = rocksdb::IteratorMode::From(&key, rocksdb::Direction::Forward);
+        let mut opts = ReadOptions::default();
+        opts.set_prefix_same_as_start(true);
+        let it = self
+            .inner
+            .iterator_cf_opt(self.cf::<peer_cn::Schema>()?, opts, mode)
+            .filter_map(|(k, _)| Some(peer_cn::Item::decode(&k).ok()?.cn_id));
+        Ok(it)
+    }
+
+    /// The `from`..`to` window of the message timestamps in milliseconds, at most `MAX_TIME_SPAN`,
+    /// the missing end is `MAX_TIME_SPAN` away from the other one, without both
+    /// the window ends with the newest message
+    fn message_window(&self, from: Option<u64>, to: Option<u64>) -> Result<Range<u64>, DbError> {
+        let span = timestamp::MAX_TIME_SPAN;
+        let (begin, end) = match (from, to) {
+            (Some(begin), Some(end)) if end.saturating_sub(begin) <= span => (begin, end),
+            (Some(_), Some(_)) => {
+                let error = format!("the range is at most {} milliseconds", span);
+                return Err(DBError::SchemaError {
+                    error: SchemaError::DecodeValidationError(error),
+                }
+                .into());
+            },
+            (Some(begin), None) => (begin, begin.saturating_add(span)),
+            (None, Some(end)) => (end.saturating_sub(span), end),
+            (None, None) => {
+                let end = self
+                    .as_kv::<timestamp::MessageSchema>()
+                    .iterator(IteratorMode::End)?
+                    .filter_map(|(k, _)| k.ok())
+                    .next()
+                    .map_or(0, |key| key.timestamp + 1);
+                (end.saturating_sub(span), end)
+            },
         };
-        let timestamp_index = timestamp::Item {
+        Ok(begin..end)
+    }
+
+    /// Collect the messages which carry the operation and analyse them
+    fn operation_report(&self, hash: [u8; 32]) -> Result<OperationReport, DbError> {
+        let mut peers = HashMap::new();
+        let mut sightings = Vec::new();
+        let indexes =
+            self.message_hash_indexes::<message_ref::OperationSchema>(hash, 0, Direction::Forward)?;
+        for index in indexes {
+            let item = match self.as_kv::<message::Schema>().get(&index)? {
+                Some(item) => item,
+                None => continue,
+            };
+            if operation::carries(&item.ty) {
+                sightings.push(self.sighting(index, item, &mut peers)?);
+            }
+        }
+
+        let hash = HashType::OperationHash
+            .hash_to_b58check(&hash)
+            .unwrap_or_else(|_| hex::encode(&hash));
+        Ok(OperationReport::new(hash, &sightings))
+    }
+
+    /// Collect the messages which carry the block headers of the level,
+    /// and the requests of the headers, and analyse them
+    fn block_report(
+        &self,
+        level: u32,
+        peers: &mut HashMap<(u64, u32), String>,
+    ) -> Result<BlockReport, DbError> {
+        let start = message_ref::LevelItem { level, index: 0 };
+        let indexes = self
+            .as_kv::<message_ref::LevelSchema>()
+            .iterator(IteratorMode::From(&start, Direction::Forward))?
+            .filter_map(|(k, _)| k.ok())
+            .take_while(|k| k.level == level)
+            .map(|k| k.index)
+            .collect::<Vec<_>>();
+        // there might be several blocks of the level
+        let mut blocks = BTreeMap::<[u8; 32], Vec<Sighting>>::new();
+        for index in indexes {
+            let item = match self.as_kv::<message::Schema>().get(&index)? {
+                Some(item) => item,
+                None => continue,
+            };
+            if !block::carries(&item.ty) {
+                continue;
+            }
+            // the hash of the header the message carries is the first
+            if let Some(hash) = item.refs.blocks.first().cloned() {
+                blocks
+                    .entry(hash)
+                    .or_default()
+                    .push(self.sighting(index, item, peers)?);
+            }
+        }
+
+        let mut report = BlockReport {
+            level,
+            blocks: Vec::with_capacity(blocks.len()),
+        };
+        for (hash, mut sightings) in blocks {
+            let indexes =
+                self.message_hash_indexes::<message_ref::BlockSchema>(hash, 0, Direction::Forward)?;
+            for index in indexes {
+                let item = match self.as_kv::<message::Schema>().get(&index)? {
+                    Some(item) => item,
+                    None => continue,
+                };
+                if matches!(
+                    &item.ty,
+                    common::MessageType::P2p(common::MessageKind::GetBlockHeaders)
+                ) {
+                    sightings.push(self.sighting(index, item, peers)?);
+                }
+            }
+            sightings.sort_by_key(|s| s.message_id);
+            let hash = HashType::BlockHash
+                .hash_to_b58check(&hash)
+                .unwrap_or_else(|_| hex::encode(&hash));
+            report.blocks.push(BlockPropagation::new(hash, &sightings));
+        }
+        // the block most peers announced goes first
+        report
+            .blocks
+            .sort_by(|a, b| b.announcements.len().cmp(&a.announcements.len()));
+        Ok(report)
+    }
+
+    /// The `peers` caches the peer of each connection
+    fn sighting(
+        &self,
+        index: u64,
+        item: message::Item,
+        peers: &mut HashMap<(u64, u32), String>,
+    ) -> Result<Sighting, DbError> {
+        Ok(Sighting {
+            message_id: index,
+            incoming: item.sender.incoming(),
             timestamp: item.timestamp,
-            index,
+            peer: self.connection_peer(&item.cn_id(), peers)?,
+            ty: item.ty,
+        })
+    }
+
+    /// The peer id, or the remote address if the peer is unknown,
+    /// or the connection id if the connection is removed,
+    /// the `peers` caches the peer of each connection
+    fn connection_peer(
+        &self,
+        cn_id: &connection::Key,
+        peers: &mut HashMap<(u64, u32), String>,
+    ) -> Result<String, DbError> {
+        if let Some(peer) = peers.get(&(cn_id.ts, cn_id.ts_nanos)) {
+            return Ok(peer.clone());
+        }
+        let peer = match self.as_kv::<connection::Schema>().get(cn_id)? {
+            Some(value) => value
+                .peer_id()
+                .unwrap_or_else(|_| value.remote_addr().to_string()),
+            None => cn_id.to_string(),
         };
-        let inner = || -> Result<(), DbError> {
-            self.as_kv::<message_ty::Schema>().put(&ty_index, &())?;
-            self.as_kv::<message_sender::Schema>()
-                .put(&sender_index, &())?;
-            self.as_kv::<message_initiator::Schema>()
-                .put(&initiator_index, &())?;
-            self.as_kv::<message_addr::Schema>().put(&addr_index, &())?;
-            self.as_kv::<timestamp::MessageSchema>()
-                .put(&timestamp_index, &())?;
-            self.as_kv::<message::Schema>().put(&index, &item)?;
-            Ok(())
+        peers.insert((cn_id.ts, cn_id.ts_nanos), peer.clone());
+        Ok(peer)
+    }
+
+    /// The ids of the messages which refer to the block or the operation
+    /// starting from the `cursor`
+    fn message_hash_indexes<S>(
+        &self,
+        hash: [u8; 32],
+        cursor: u64,
+        direction: Direction,
+    ) -> Result<impl Iterator<Item = u64> + '_, DbError>
+    where
+        S: KeyValueSchema<Key = message_ref::HashItem> + RocksDbKeyValueSchema,
+    {
+        let key = message_ref::HashItem {
+            hash,
+            index: cursor,
+        }
+        .encode()
+        .map_err(|error| DBError::SchemaError { error })?;
+        let mode = rocksdb::IteratorMode::From(&key, direction.into());
+        let mut opts = ReadOptions::default();
+        opts.set_prefix_same_as_start(true);
+        let it = self
+            .inner
+            .iterator_cf_opt(self.cf::<S>()?, opts, mode)
+            .filter_map(|(k, _)| Some(message_ref::HashItem::decode(&k).ok()?.index));
+        Ok(it)
+    }
+
+    /// The ids of the messages carrying the block header of the level starting from the `cursor`
+    fn message_level_indexes(
+        &self,
+        level: u32,
+        cursor: u64,
+        direction: Direction,
+    ) -> Result<impl Iterator<Item = u64> + '_, DbError> {
+        let key = message_ref::LevelItem {
+            level,
+            index: cursor,
+        }
+        .encode()
+        .map_err(|error| DBError::SchemaError { error })?;
+        let mode = rocksdb::IteratorMode::From(&key, direction.into());
+        let it = self
+            .inner
+            .iterator_cf_opt(
+                self.cf::<message_ref::LevelSchema>()?,
+                ReadOptions::default(),
+                mode,
+            )
+            .filter_map(|(k, _)| message_ref::LevelItem::decode(&k).ok())
+            .take_while(move |k| k.level == level)
+            .map(|k| k.index);
+        Ok(it)
+    }
+
+    /// Fill the connection index from the messages stored before the index was introduced
+    pub(super) fn fill_message_cn_index(&self) -> Result<(), DbError> {
+        let mut batch = WriteBatch::default();
+        for (index, item) in self
+            .as_kv::<message::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            if let (Ok(index), Ok(item)) = (index, item) {
+                let cn_index = message_cn::Item {
+                    cn_id: item.cn_id(),
+                    index,
+                };
+                self.batch_put::<message_cn::Schema>(&mut batch, &cn_index, &())?;
+            }
+            if batch.len() >= Self::BATCH_SIZE {
+                self.write_batch(std::mem::take(&mut batch))?;
+            }
+        }
+        self.write_batch(batch)?;
+        Ok(())
+    }
+
+    /// Fill the indexes of the blocks and the operations from the messages
+    /// whose refs were computed by the migration
+    pub(super) fn fill_message_ref_indexes(&self) -> Result<(), DbError> {
+        let mut batch = WriteBatch::default();
+        for (index, item) in self
+            .as_kv::<message::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            if let (Ok(index), Ok(item)) = (index, item) {
+                self.batch_message_refs(index, &item.refs, &mut batch, false)?;
+            }
+            if batch.len() >= Self::BATCH_SIZE {
+                self.write_batch(std::mem::take(&mut batch))?;
+            }
+        }
+        self.write_batch(batch)?;
+        Ok(())
+    }
+
+    /// Add the change of the connection and the messages counted in memory
+    /// to the summary of its peer, the connection with unknown peer is not counted
+    fn count_peer_connection(
+        &self,
+        key: &connection::Key,
+        old: Option<&connection::Value>,
+        new: &connection::Value,
+    ) -> Result<(), DbError> {
+        let messages = self.peer_messages.lock().unwrap().remove(key);
+        let peer_key = match peer::Key::from_pk(&new.peer_pk()) {
+            Some(peer_key) => peer_key,
+            None => return Ok(()),
+        };
+        // the peer was unknown before, the connection is new for the peer
+        let old = old.filter(|old| old.peer_pk() == new.peer_pk());
+
+        let _lock = self.peers_lock.lock().unwrap();
+        let kv = self.as_kv::<peer::Schema>();
+        let mut value = kv.get(&peer_key)?.unwrap_or_default();
+        value.count_connection(key, old, new);
+        if let Some(messages) = messages {
+            value.merge_messages(&messages);
+        }
+        kv.put(&peer_key, &value)?;
+        Ok(())
+    }
+
+    /// Count the message in memory, the counters are added to the summary of the peer
+    /// when the connection is stored next time
+    fn count_peer_message(&self, item: &message::Item) {
+        self.peer_messages
+            .lock()
+            .unwrap()
+            .entry(item.cn_id())
+            .or_default()
+            .count_message(&item.ty, item.sender.incoming(), item.timestamp);
+    }
+
+    /// Put the connection into the index of its peer, if the peer is known
+    /// and the connection is new or its peer was unknown before
+    fn index_peer_connection(
+        &self,
+        key: &connection::Key,
+        old: Option<&connection::Value>,
+        new: &connection::Value,
+    ) -> Result<(), DbError> {
+        if old.map_or(false, |old| old.peer_pk() == new.peer_pk()) {
+            return Ok(());
+        }
+        if let Some(peer) = peer::Key::from_pk(&new.peer_pk()) {
+            let item = peer_cn::Item {
+                peer,
+                cn_id: key.clone(),
+            };
+            self.as_kv::<peer_cn::Schema>().put(&item, &())?;
+        }
+        Ok(())
+    }
+
+    /// Fill the index of the connections by peer from the connections
+    /// stored before the index was introduced
+    pub(super) fn fill_peer_cn_index(&self) -> Result<(), DbError> {
+        let mut batch = WriteBatch::default();
+        for (key, value) in self
+            .as_kv::<connection::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            if let (Ok(cn_id), Ok(value)) = (key, value) {
+                if let Some(peer) = peer::Key::from_pk(&value.peer_pk()) {
+                    let item = peer_cn::Item { peer, cn_id };
+                    self.batch_put::<peer_cn::Schema>(&mut batch, &item, &())?;
+                }
+            }
+            if batch.len() >= Self::BATCH_SIZE {
+                self.write_batch(std::mem::take(&mut batch))?;
+            }
+        }
+        self.write_batch(batch)?;
+        Ok(())
+    }
+
+    /// Fill the summaries of the peers from the connections and the messages
+    /// stored before the summaries were introduced
+    pub(super) fn fill_peers(&self) -> Result<(), DbError> {
+        for (key, value) in self
+            .as_kv::<connection::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            if let (Ok(key), Ok(value)) = (key, value) {
+                self.count_peer_connection(&key, None, &value)?;
+            }
+        }
+        for (_, item) in self
+            .as_kv::<message::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            if let Ok(item) = item {
+                self.count_peer_message(&item);
+            }
+        }
+        self.flush_peer_messages()
+    }
+
+    /// Add the messages counted in memory to the summaries of the peers
+    /// without waiting for the next update of their connections
+    fn flush_peer_messages(&self) -> Result<(), DbError> {
+        // store the connections again to flush the counted messages,
+        // the connection is not new and its counters do not change
+        let cn_ids = self
+            .peer_messages
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for cn_id in cn_ids {
+            match self.as_kv::<connection::Schema>().get(&cn_id)? {
+                Some(value) => self.count_peer_connection(&cn_id, Some(&value), &value)?,
+                None => {
+                    self.peer_messages.lock().unwrap().remove(&cn_id);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Match the message against the requests pending on its connection,
+    /// and store the new and the answered requests
+    fn pair_message(&self, index: u64, item: &message::Item) -> Result<(), DbError> {
+        let requests = self.pairing.lock().unwrap().message(index, item);
+        self.store_requests(requests)
+    }
+
+    fn store_requests(&self, requests: Vec<(request::Key, request::Value)>) -> Result<(), DbError> {
+        if requests.is_empty() {
+            return Ok(());
+        }
+        let mut batch = WriteBatch::default();
+        self.batch_requests(&requests, None, &mut batch)?;
+        self.write_batch(batch)?;
+        Ok(())
+    }
+
+    /// Put the request records into the `batch`, the message at `index`
+    /// is written by the same batch, the other messages must be stored
+    fn batch_requests(
+        &self,
+        requests: &[(request::Key, request::Value)],
+        index: Option<u64>,
+        batch: &mut WriteBatch,
+    ) -> Result<(), DbError> {
+        for (key, value) in requests {
+            // the request message is removed together with its records,
+            // the record must not appear again when the request is answered or closed
+            let stored = Some(key.index) == index
+                || self.as_kv::<message::Schema>().get(&key.index)?.is_some();
+            if !stored {
+                continue;
+            }
+            self.batch_put::<request::Schema>(batch, key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Put back the requests which were waiting for the response when the database was closed,
+    /// the requests of the closed connections are unanswered
+    fn restore_pending_requests(&self) -> Result<(), DbError> {
+        let mut closed = Vec::new();
+        let mut pairing = self.pairing.lock().unwrap();
+        for (key, value) in self
+            .as_kv::<request::Schema>()
+            .iterator(IteratorMode::Start)?
+            .filter_map(|(k, v)| Some((k.ok()?, v.ok()?)))
+            .filter(|(_, v)| v.response.is_none() && !v.unanswered)
+        {
+            let item = match self.as_kv::<message::Schema>().get(&key.index)? {
+                Some(item) => item,
+                None => continue,
+            };
+            let open = self
+                .as_kv::<connection::Schema>()
+                .get(&value.cn_id())?
+                .map_or(false, |cn| cn.lifecycle().closed_at.is_none());
+            if open {
+                pairing.restore(key, value, &item);
+            } else {
+                let mut value = value;
+                value.unanswered = true;
+                closed.push((key, value));
+            }
+        }
+        drop(pairing);
+        self.store_requests(closed)
+    }
+
+    /// Rewrite the connections recorded before the lifecycle was stored,
+    /// they are closed at the time of their last chunk
+    pub(super) fn migrate_connections_v1(&self) -> Result<(), DbError> {
+        let cf = self.cf::<connection::Schema>()?;
+        let chunk_cf = self.cf::<chunk::Schema>()?;
+        let mut batch = WriteBatch::default();
+        for (key, value) in self.inner.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+            let cn_id =
+                connection::Key::decode(&key).map_err(|error| DBError::SchemaError { error })?;
+            let mut value = connection::Value::decode_v1(&value)
+                .map_err(|error| DBError::SchemaError { error })?;
+            let last_chunk = chunk::Key::end(cn_id.clone())
+                .encode()
+                .map_err(|error| DBError::SchemaError { error })?;
+            let mode = rocksdb::IteratorMode::From(&last_chunk, rocksdb::Direction::Reverse);
+            // the chunks of each side are in order of time, but the sides are interleaved
+            // in order of counters, so take the last chunk of each side
+            let mut last = [None, None];
+            for (k, v) in self
+                .inner
+                .iterator_cf(chunk_cf, mode)
+                .take_while(|(k, _)| k.starts_with(&key))
+            {
+                let incoming = match chunk::Key::decode(&k) {
+                    Ok(k) => k.sender.incoming() as usize,
+                    Err(_) => continue,
+                };
+                if last[incoming].is_none() {
+                    last[incoming] = chunk::Value::decode(&v).ok().map(|v| v.timestamp());
+                }
+                if last.iter().all(Option::is_some) {
+                    break;
+                }
+            }
+            let closed_at = match last.iter().flatten().max() {
+                Some(timestamp) => timestamp * 1_000_000_000,
+                None => cn_id.ts * 1_000_000_000 + cn_id.ts_nanos as u64,
+            };
+            value.close_v1(closed_at);
+            let value = value
+                .encode()
+                .map_err(|error| DBError::SchemaError { error })?;
+            batch.put_cf(cf, key, value);
+            if batch.len() >= Self::BATCH_SIZE {
+                self.write_batch(std::mem::take(&mut batch))?;
+            }
+        }
+        self.write_batch(batch)?;
+        Ok(())
+    }
+
+    /// Pair the requests and the responses recorded before the pairing was introduced,
+    /// the requests of the closed connections left without response are unanswered
+    pub(super) fn fill_requests(&self) -> Result<(), DbError> {
+        for (index, item) in self
+            .as_kv::<message::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            if let (Ok(index), Ok(item)) = (index, item) {
+                self.pair_message(index, &item)?;
+            }
+        }
+        for (key, value) in self
+            .as_kv::<connection::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            if let (Ok(key), Ok(value)) = (key, value) {
+                if value.lifecycle().closed_at.is_some() {
+                    let requests = self.pairing.lock().unwrap().close(&key);
+                    self.store_requests(requests)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Store the addresses advertised by the messages recorded before they were stored,
+    /// the addresses are decoded from the chunks again
+    pub(super) fn fill_advertisements(&self) -> Result<(), DbError> {
+        let mut batch = WriteBatch::default();
+        for (index, item) in self
+            .as_kv::<message::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            if let (Ok(index), Ok(item)) = (index, item) {
+                if discovery::carries(&item.ty) {
+                    let item = self.summarize_message(item);
+                    if let Some(value) = advertisement::Value::new(&item) {
+                        self.batch_put::<advertisement::Schema>(&mut batch, &index, &value)?;
+                    }
+                }
+            }
+            if batch.len() >= Self::BATCH_SIZE {
+                self.write_batch(std::mem::take(&mut batch))?;
+            }
+        }
+        self.write_batch(batch)?;
+        Ok(())
+    }
+
+    /// Check the connections recorded before the conformance check was introduced,
+    /// the messages are replayed in order, the plain bytes are taken from the chunks
+    pub(super) fn check_conformance(&self) -> Result<(), DbError> {
+        let mut batch = WriteBatch::default();
+        for (key, value) in self
+            .as_kv::<connection::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            let connection = match (key, value) {
+                (Ok(key), Ok(value)) => connection::Item::unite(key, value),
+                _ => continue,
+            };
+            let ConnectionChunks {
+                mut connection,
+                chunks,
+                messages,
+            } = self.connection_chunks(connection)?;
+            let (comments, state) = connection.conformance();
+            let before = (comments.incoming_violations, comments.outgoing_violations);
+
+            let plain = chunks
+                .iter()
+                .map(|(k, v, _)| ((k.counter, k.sender.incoming()), v.plain.as_slice()))
+                .collect::<HashMap<_, _>>();
+            for (_, message) in &messages {
+                // only the single chunk message needs the plain bytes, it is the `ack`
+                let plain = message
+                    .chunks()
+                    .next()
+                    .filter(|_| message.chunk_count() == 1)
+                    .and_then(|k| plain.get(&(k.counter, k.sender.incoming())).cloned())
+                    .filter(|p| !p.is_empty());
+                conformance::check_message(comments, state, message, plain);
+            }
+            // the parser stops at the first chunk which is too short to start the message
+            for incoming in [false, true].iter().cloned() {
+                let stray = chunks.iter().find(|(k, _, ty)| {
+                    k.sender.incoming() == incoming && k.counter >= 3 && ty.is_none()
+                });
+                if let Some((_, value, _)) = stray {
+                    if value.plain.len() < 6 {
+                        conformance::check_stray_chunk(comments, incoming, &value.plain);
+                    }
+                }
+            }
+
+            if before != (comments.incoming_violations, comments.outgoing_violations) {
+                let (key, value) = connection.split();
+                self.batch_put::<connection::Schema>(&mut batch, &key, &value)?;
+            }
+            if batch.len() >= Self::BATCH_SIZE {
+                self.write_batch(std::mem::take(&mut batch))?;
+            }
+        }
+        self.write_batch(batch)?;
+        Ok(())
+    }
+
+    /// Compute size, preview and refs of the message stored
+    /// before they were computed at ingest time
+    pub(super) fn summarize_message(&self, mut item: message::Item) -> message::Item {
+        let mut plain = Vec::new();
+        for key in item.chunks() {
+            match self.as_kv::<chunk::Schema>().get(&key) {
+                Ok(Some(c)) => plain.extend_from_slice(&c.plain),
+                _ => break,
+            }
+        }
+        item.summarize(&plain);
+        item
+    }
+
+    /// Decode each value of the column family `S` using the old layout,
+    /// and write it back in the current layout, the keys remain the same
+    pub(super) fn rewrite_values<S, F>(&self, decode_old: F) -> Result<(), DbError>
+    where
+        S: KeyValueSchema + RocksDbKeyValueSchema,
+        F: Fn(&[u8]) -> Result<S::Value, SchemaError>,
+    {
+        let cf = self.cf::<S>()?;
+        let mut batch = WriteBatch::default();
+        for (key, value) in self.inner.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+            let value = decode_old(&value)
+                .and_then(|value| value.encode())
+                .map_err(|error| DBError::SchemaError { error })?;
+            batch.put_cf(cf, key, value);
+            if batch.len() >= Self::BATCH_SIZE {
+                self.write_batch(std::mem::take(&mut batch))?;
+            }
+        }
+        self.write_batch(batch)?;
+        Ok(())
+    }
+}
+
+/// Result of the database consistency check
+#[derive(Default, Serialize)]
+pub struct ConsistencyReport {
+    pub messages: u64,
+    pub logs: u64,
+    /// number of secondary index entries which point to absent primary row, by column family
+    pub dangling_index_entries: BTreeMap<&'static str, u64>,
+    /// number of messages which refer to absent chunks
+    pub messages_missing_chunks: u64,
+    /// number of logs absent in the full text search index
+    pub logs_not_indexed: Option<u64>,
+    /// number of full text search documents whose log is absent
+    pub dangling_search_documents: Option<u64>,
+    /// stored version of the column families which need migration, zero if absent,
+    /// the checks involving them are skipped
+    pub needs_migration: BTreeMap<&'static str, u64>,
+}
+
+impl ConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.dangling_index_entries.values().all(|n| *n == 0)
+            && self.messages_missing_chunks == 0
+            && self.logs_not_indexed.unwrap_or(0) == 0
+            && self.dangling_search_documents.unwrap_or(0) == 0
+            && self.needs_migration.is_empty()
+    }
+}
+
+impl Db {
+    /// Open the database without write access and without the log indexer,
+    /// it can be done while the recorder is running
+    pub fn open_read_only<P>(path: P) -> Result<Self, DbError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = PathBuf::from(path.as_ref());
+        let opts = Options::default();
+        // the database created before versioning has no `schema_version` column family,
+        // so open whatever column families exist
+        let names = DB::list_cf(&opts, path.join("rocksdb"))
+            .map_err(|error| DBError::RocksDBError { error })?;
+        let inner = DB::open_cf_for_read_only(&opts, path.join("rocksdb"), &names, false)
+            .map_err(|error| DBError::RocksDBError { error })?;
+
+        let mut db = Db {
+            message_store_limit: None,
+            message_counter: AtomicU64::new(counter::<message::Schema>(&inner).unwrap_or(0)),
+            chunk_payload: chunk::ChunkPayload::default(),
+            log_store_limit: None,
+            log_counter: AtomicU64::new(counter::<node_log::Schema>(&inner).unwrap_or(0)),
+            log_indexer: None,
+            peers_lock: Mutex::new(()),
+            peer_messages: Mutex::new(HashMap::new()),
+            pairing: Mutex::new(Pairing::default()),
+            live_connections: Mutex::new(HashSet::new()),
+            outdated: BTreeMap::new(),
+            path,
+            inner,
+        };
+        let mut outdated = BTreeMap::new();
+        db.migrate(true, &mut outdated)?;
+        db.outdated = outdated;
+
+        Ok(db)
+    }
+
+    fn batch_clear<S>(&self, batch: &mut WriteBatch) -> Result<(), DBError>
+    where
+        S: RocksDbKeyValueSchema,
+    {
+        // the keys of the secondary indexes and of the derived tables are at most 40 bytes long,
+        // so each of them, even consisting of `0xff` only, is less than the end of the range
+        batch.delete_range_cf(self.cf::<S>()?, [0x00u8; 0], [0xffu8; 64]);
+        Ok(())
+    }
+
+    /// Count the entries of the secondary index `S` which point to absent row of the table `P`,
+    /// `None` if any of them needs migration
+    fn check_index<S, P>(&self, index: impl Fn(&S::Key) -> P::Key) -> Result<Option<u64>, DbError>
+    where
+        S: KeyValueSchema<Value = ()> + RocksDbKeyValueSchema,
+        P: KeyValueSchema + RocksDbKeyValueSchema,
+    {
+        if self.outdated.contains_key(S::name()) || self.outdated.contains_key(P::name()) {
+            return Ok(None);
+        }
+        let mut dangling = 0;
+        for (key, _) in self.as_kv::<S>().iterator(IteratorMode::Start)? {
+            match key {
+                Ok(key) => {
+                    if self.as_kv::<P>().get(&index(&key))?.is_none() {
+                        dangling += 1;
+                    }
+                },
+                Err(err) => {
+                    log::warn!("Failed to load index: {}", err);
+                    dangling += 1;
+                },
+            }
+        }
+        Ok(Some(dangling))
+    }
+
+    pub fn check(&self) -> Result<ConsistencyReport, DbError> {
+        let mut report = ConsistencyReport {
+            needs_migration: self.outdated.clone(),
+            ..ConsistencyReport::default()
+        };
+
+        let mut check = |name: &'static str, dangling: Result<Option<u64>, DbError>| {
+            dangling.map(|n| {
+                if let Some(n) = n {
+                    report.dangling_index_entries.insert(name, n);
+                }
+            })
+        };
+        check(
+            message_ty::Schema::name(),
+            self.check_index::<message_ty::Schema, message::Schema>(|k| k.index),
+        )?;
+        check(
+            message_sender::Schema::name(),
+            self.check_index::<message_sender::Schema, message::Schema>(|k| k.index),
+        )?;
+        check(
+            message_initiator::Schema::name(),
+            self.check_index::<message_initiator::Schema, message::Schema>(|k| k.index),
+        )?;
+        check(
+            message_addr::Schema::name(),
+            self.check_index::<message_addr::Schema, message::Schema>(|k| k.index),
+        )?;
+        check(
+            message_cn::Schema::name(),
+            self.check_index::<message_cn::Schema, message::Schema>(|k| k.index),
+        )?;
+        check(
+            peer_cn::Schema::name(),
+            self.check_index::<peer_cn::Schema, connection::Schema>(|k| k.cn_id.clone()),
+        )?;
+        check(
+            message_ref::BlockSchema::name(),
+            self.check_index::<message_ref::BlockSchema, message::Schema>(|k| k.index),
+        )?;
+        check(
+            message_ref::LevelSchema::name(),
+            self.check_index::<message_ref::LevelSchema, message::Schema>(|k| k.index),
+        )?;
+        check(
+            message_ref::OperationSchema::name(),
+            self.check_index::<message_ref::OperationSchema, message::Schema>(|k| k.index),
+        )?;
+        check(
+            timestamp::MessageSchema::name(),
+            self.check_index::<timestamp::MessageSchema, message::Schema>(|k| k.index),
+        )?;
+        check(
+            log_level::Schema::name(),
+            self.check_index::<log_level::Schema, node_log::Schema>(|k| k.index),
+        )?;
+        check(
+            timestamp::LogSchema::name(),
+            self.check_index::<timestamp::LogSchema, node_log::Schema>(|k| k.index),
+        )?;
+
+        let check_messages = !self.outdated.contains_key(message::Schema::name());
+        let check_chunks = !self.outdated.contains_key(chunk::Schema::name());
+        if check_messages {
+            for (_, item) in self
+                .as_kv::<message::Schema>()
+                .iterator(IteratorMode::Start)?
+            {
+                report.messages += 1;
+                if let (true, Ok(item)) = (check_chunks, item) {
+                    for chunk_key in item.chunks() {
+                        if self.as_kv::<chunk::Schema>().get(&chunk_key)?.is_none() {
+                            report.messages_missing_chunks += 1;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        if self.outdated.contains_key(node_log::Schema::name()) {
+            return Ok(report);
+        }
+        let search_path = self.path.join("tantivy");
+        let mut search_ids = if search_path.exists() {
+            Some(search::read_ids(search_path)?)
+        } else {
+            None
         };
-        if let Err(error) = inner() {
-            log::error!("database error: {}", error);
+        let mut logs_not_indexed = 0;
+        for (id, _) in self
+            .as_kv::<node_log::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            report.logs += 1;
+            if let (Some(search_ids), Ok(id)) = (&mut search_ids, id) {
+                if !search_ids.remove(&id) {
+                    logs_not_indexed += 1;
+                }
+            }
+        }
+        if let Some(search_ids) = search_ids {
+            report.logs_not_indexed = Some(logs_not_indexed);
+            report.dangling_search_documents = Some(search_ids.len() as u64);
         }
+
+        Ok(report)
     }
 
-    fn store_log(&self, item: node_log::Item) {
-        let index = self.reserve_log_counter();
-        if let Some(store_limit) = self.log_store_limit {
-            if index >= store_limit {
-                if let Err(error) = self.remove_log(index - store_limit) {
-                    log::error!("database error: {}", error);
-                }
+    /// Drop all secondary indexes, the full text search index and the derived tables
+    /// (the summaries of the peers, the paired requests and the advertisements),
+    /// and build them again from the primary tables,
+    /// the full text search index is committed before it returns
+    pub fn rebuild_indexes(&self) -> Result<(), DbError> {
+        let mut batch = WriteBatch::default();
+        self.batch_clear::<message_ty::Schema>(&mut batch)?;
+        self.batch_clear::<message_sender::Schema>(&mut batch)?;
+        self.batch_clear::<message_initiator::Schema>(&mut batch)?;
+        self.batch_clear::<message_addr::Schema>(&mut batch)?;
+        self.batch_clear::<message_cn::Schema>(&mut batch)?;
+        self.batch_clear::<peer_cn::Schema>(&mut batch)?;
+        self.batch_clear::<message_ref::BlockSchema>(&mut batch)?;
+        self.batch_clear::<message_ref::LevelSchema>(&mut batch)?;
+        self.batch_clear::<message_ref::OperationSchema>(&mut batch)?;
+        self.batch_clear::<timestamp::MessageSchema>(&mut batch)?;
+        self.batch_clear::<log_level::Schema>(&mut batch)?;
+        self.batch_clear::<timestamp::LogSchema>(&mut batch)?;
+        self.batch_clear::<peer::Schema>(&mut batch)?;
+        self.batch_clear::<request::Schema>(&mut batch)?;
+        self.batch_clear::<advertisement::Schema>(&mut batch)?;
+        self.write_batch(batch)?;
+
+        let mut batch = WriteBatch::default();
+        for (index, item) in self
+            .as_kv::<message::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            if let (Ok(index), Ok(item)) = (index, item) {
+                self.put_message_indexes(index, &item, &mut batch)?;
+            }
+            if batch.len() >= Self::BATCH_SIZE {
+                self.write_batch(std::mem::take(&mut batch))?;
             }
         }
+        self.write_batch(batch)?;
+        self.fill_peer_cn_index()?;
+        self.fill_peers()?;
+        // the pending requests restored on open are paired again
+        *self.pairing.lock().unwrap() = Pairing::default();
+        self.fill_requests()?;
+        self.fill_advertisements()?;
 
-        let lv_index = log_level::Item {
-            lv: item.level.clone(),
-            index,
-        };
-        let timestamp_index = timestamp::Item {
-            timestamp: (item.timestamp / 1_000_000) as u64,
-            index,
-        };
-        let inner = || -> Result<(), DbError> {
-            self.as_kv::<log_level::Schema>().put(&lv_index, &())?;
-            self.as_kv::<timestamp::LogSchema>()
-                .put(&timestamp_index, &())?;
-            self.as_kv::<node_log::Schema>().put(&index, &item)?;
-            Ok(())
-        };
         if let Some(log_indexer) = &self.log_indexer {
-            log_indexer.write(&item.message, index);
+            log_indexer.clear()?;
         }
-        if let Err(error) = inner() {
-            log::error!("database error: {}", error);
+        let mut batch = WriteBatch::default();
+        for (index, item) in self
+            .as_kv::<node_log::Schema>()
+            .iterator(IteratorMode::Start)?
+        {
+            if let (Ok(index), Ok(item)) = (index, item) {
+                self.put_log_indexes(index, &item, &mut batch)?;
+                if let Some(log_indexer) = &self.log_indexer {
+                    log_indexer.write(&item.message, index);
+                }
+            }
+            if batch.len() >= Self::BATCH_SIZE {
+                self.write_batch(std::mem::take(&mut batch))?;
+            }
+        }
+        self.write_batch(batch)?;
+        // the background commit might not happen before the caller reads the index
+        if let Some(log_indexer) = &self.log_indexer {
+            log_indexer.commit()?;
         }
+        self.compact();
+
+        Ok(())
     }
 }
 
@@ -314,8 +2037,90 @@ impl DatabaseFetch for Db {
         &self,
         filter: &ConnectionsFilter,
     ) -> Result<Vec<(connection::Key, connection::Value)>, Self::Error> {
+        let invalid = |e: String| DBError::SchemaError {
+            error: SchemaError::DecodeValidationError(e),
+        };
+
         let limit = filter.limit.unwrap_or(100) as usize;
-        let mode = IteratorMode::Start;
+        // from the oldest connection by default
+        let forward = filter.direction != Some("backward".to_string());
+        let direction = if forward {
+            Direction::Forward
+        } else {
+            Direction::Reverse
+        };
+
+        let addr = match &filter.remote_addr {
+            Some(addr) => Some(
+                addr.parse::<AddrFilter>()
+                    .map_err(|e| invalid(e.to_string()))?,
+            ),
+            None => None,
+        };
+        let comments = match &filter.comments {
+            Some(comments) => {
+                let kinds = comments.split(',').collect::<Vec<_>>();
+                for kind in &kinds {
+                    if !connection::Comments::KINDS.iter().any(|k| k == kind) {
+                        return Err(invalid(format!("unknown kind of comment {}", kind)).into());
+                    }
+                }
+                Some(kinds)
+            },
+            None => None,
+        };
+        let start = match &filter.cursor {
+            Some(cursor) => Some(
+                cursor
+                    .parse::<connection::Key>()
+                    .map_err(|e| invalid(e.to_string()))?,
+            ),
+            None if forward => filter.from.map(|ts| connection::Key { ts, ts_nanos: 0 }),
+            None => filter.to.map(|ts| connection::Key { ts, ts_nanos: 0 }),
+        };
+        let mode = match &start {
+            Some(key) => IteratorMode::From(key, direction),
+            None if forward => IteratorMode::Start,
+            None => IteratorMode::End,
+        };
+
+        let before_end = |key: &connection::Key| filter.to.map_or(true, |to| key.ts < to);
+        let after_begin = |key: &connection::Key| filter.from.map_or(true, |from| key.ts >= from);
+        let matches = |value: &connection::Value| {
+            if let Some(addr) = &addr {
+                if !addr.matches(&value.remote_addr()) {
+                    return false;
+                }
+            }
+            if let Some(initiator) = &filter.initiator {
+                if initiator.incoming() != value.initiator().incoming() {
+                    return false;
+                }
+            }
+            let kinds = value.comments().kinds();
+            if let Some(has_comments) = filter.has_comments {
+                if has_comments == kinds.is_empty() {
+                    return false;
+                }
+            }
+            if let Some(comments) = &comments {
+                if !kinds.iter().any(|kind| comments.contains(kind)) {
+                    return false;
+                }
+            }
+            if let Some(peer_id) = &filter.peer_id {
+                if value.peer_id().ok().as_ref() != Some(peer_id) {
+                    return false;
+                }
+            }
+            if let Some(closed) = filter.closed {
+                if closed != value.lifecycle().closed_at.is_some() {
+                    return false;
+                }
+            }
+            true
+        };
+
         let vec = self
             .as_kv::<connection::Schema>()
             .iterator(mode)?
@@ -330,11 +2135,390 @@ impl DatabaseFetch for Db {
                     None
                 },
             })
+            .take_while(|(key, _)| {
+                if forward {
+                    before_end(key)
+                } else {
+                    after_begin(key)
+                }
+            })
+            .filter(|(key, value)| before_end(key) && after_begin(key) && matches(value))
+            .take(limit)
+            .collect();
+        Ok(vec)
+    }
+
+    fn fetch_connection(
+        &self,
+        key: &connection::Key,
+    ) -> Result<Option<ConnectionDetails>, Self::Error> {
+        let value = match self.as_kv::<connection::Schema>().get(key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let connection = connection::Item::unite(key.clone(), value);
+
+        let mut counts = BTreeMap::<u8, MessageCount>::new();
+        for (_, message) in self.connection_messages(&connection)? {
+            let incoming = message.sender.incoming();
+            let count = counts
+                .entry(message.ty.clone().into_int())
+                .or_insert_with(|| {
+                    let (category, kind) = message.ty.split();
+                    MessageCount {
+                        category,
+                        kind,
+                        incoming: 0,
+                        outgoing: 0,
+                    }
+                });
+            if incoming {
+                count.incoming += 1;
+            } else {
+                count.outgoing += 1;
+            }
+        }
+
+        let local = self.handshake(key, false)?;
+        let remote = self.handshake(key, true)?;
+        let (id, value) = connection.split();
+        Ok(Some(ConnectionDetails {
+            id,
+            value,
+            local,
+            remote,
+            message_counts: counts.into_iter().map(|(_, count)| count).collect(),
+        }))
+    }
+
+    fn fetch_peers(
+        &self,
+        filter: &PeersFilter,
+    ) -> Result<Vec<(peer::Key, peer::Value)>, Self::Error> {
+        let invalid = |e: String| DBError::SchemaError {
+            error: SchemaError::DecodeValidationError(e),
+        };
+
+        let limit = filter.limit.unwrap_or(100) as usize;
+        let addr = match &filter.remote_addr {
+            Some(addr) => Some(
+                addr.parse::<AddrFilter>()
+                    .map_err(|e| invalid(e.to_string()))?,
+            ),
+            None => None,
+        };
+        let matches = |value: &peer::Value| match &addr {
+            Some(addr) => value.addresses().iter().any(|a| addr.matches(a)),
+            None => true,
+        };
+
+        if let Some(peer_id) = &filter.peer_id {
+            let key = peer_id
+                .parse::<peer::Key>()
+                .map_err(|e| invalid(e.to_string()))?;
+            let value = self.as_kv::<peer::Schema>().get(&key)?;
+            return Ok(value
+                .filter(matches)
+                .map(|value| (key, value))
+                .into_iter()
+                .collect());
+        }
+
+        let start = match &filter.cursor {
+            Some(cursor) => Some(
+                cursor
+                    .parse::<peer::Key>()
+                    .map_err(|e| invalid(e.to_string()))?,
+            ),
+            None => None,
+        };
+        let mode = match &start {
+            Some(key) => IteratorMode::From(key, Direction::Forward),
+            None => IteratorMode::Start,
+        };
+        let vec = self
+            .as_kv::<peer::Schema>()
+            .iterator(mode)?
+            .filter_map(|(k, v)| Some((k.ok()?, v.ok()?)))
+            .filter(|(_, value)| matches(value))
             .take(limit)
             .collect();
         Ok(vec)
     }
 
+    fn fetch_operations(
+        &self,
+        filter: &OperationsFilter,
+    ) -> Result<Vec<OperationReport>, Self::Error> {
+        let limit = filter.limit.unwrap_or(100) as usize;
+
+        let hashes = if let Some(hash) = &filter.operation_hash {
+            vec![parse_hash(HashType::OperationHash, hash)?]
+        } else {
+            let window = self.message_window(filter.from, filter.to)?;
+            let begin = timestamp::Item {
+                timestamp: window.start,
+                index: 0,
+            };
+            let mut hashes = Vec::new();
+            let mut known = HashSet::new();
+            for key in self
+                .as_kv::<timestamp::MessageSchema>()
+                .iterator(IteratorMode::From(&begin, Direction::Forward))?
+                .filter_map(|(k, _)| k.ok())
+            {
+                if key.timestamp >= window.end || hashes.len() >= limit {
+                    break;
+                }
+                let item = match self.as_kv::<message::Schema>().get(&key.index)? {
+                    Some(item) => item,
+                    None => continue,
+                };
+                if !operation::carries(&item.ty) {
+                    continue;
+                }
+                for hash in item.refs.operations {
+                    if known.insert(hash) {
+                        hashes.push(hash);
+                    }
+                }
+            }
+            hashes.truncate(limit);
+            hashes
+        };
+
+        hashes
+            .into_iter()
+            .map(|hash| self.operation_report(hash))
+            .collect()
+    }
+
+    fn fetch_block_report(&self, level: u32) -> Result<BlockReport, Self::Error> {
+        self.block_report(level, &mut HashMap::new())
+    }
+
+    fn fetch_blocks_summary(&self, filter: &BlocksFilter) -> Result<BlocksSummary, Self::Error> {
+        let limit = filter.limit.unwrap_or(100) as usize;
+
+        let window = self.message_window(filter.from, filter.to)?;
+        let begin = timestamp::Item {
+            timestamp: window.start,
+            index: 0,
+        };
+        let mut levels = BTreeSet::new();
+        for key in self
+            .as_kv::<timestamp::MessageSchema>()
+            .iterator(IteratorMode::From(&begin, Direction::Forward))?
+            .filter_map(|(k, _)| k.ok())
+        {
+            if key.timestamp >= window.end || levels.len() >= limit {
+                break;
+            }
+            let item = match self.as_kv::<message::Schema>().get(&key.index)? {
+                Some(item) => item,
+                None => continue,
+            };
+            if block::carries(&item.ty) {
+                levels.extend(item.refs.levels);
+            }
+        }
+
+        let mut peers = HashMap::new();
+        let reports = levels
+            .into_iter()
+            .take(limit)
+            .map(|level| self.block_report(level, &mut peers))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(BlocksSummary::new(&reports))
+    }
+
+    fn fetch_requests(
+        &self,
+        filter: &RequestsFilter,
+    ) -> Result<Vec<request::RequestFrontend>, Self::Error> {
+        let invalid = |e: String| DBError::SchemaError {
+            error: SchemaError::DecodeValidationError(e),
+        };
+
+        let limit = filter.limit.unwrap_or(100) as usize;
+        let cn = match &filter.cn {
+            Some(cn) => Some(
+                cn.parse::<connection::Key>()
+                    .map_err(|e| invalid(e.to_string()))?,
+            ),
+            None => None,
+        };
+        if let Some(peer_id) = &filter.peer_id {
+            peer_id
+                .parse::<peer::Key>()
+                .map_err(|e| invalid(e.to_string()))?;
+        }
+
+        let forward = filter.direction == Some("forward".to_string());
+        let start = filter.cursor.map(|index| request::Key {
+            index,
+            part: if forward { 0 } else { u32::MAX },
+        });
+        let mode = match (&start, forward) {
+            (Some(key), true) => IteratorMode::From(key, Direction::Forward),
+            (Some(key), false) => IteratorMode::From(key, Direction::Reverse),
+            (None, true) => IteratorMode::Start,
+            (None, false) => IteratorMode::End,
+        };
+
+        let mut peers = HashMap::new();
+        let mut vec = Vec::new();
+        for (key, value) in self
+            .as_kv::<request::Schema>()
+            .iterator(mode)?
+            .filter_map(|(k, v)| Some((k.ok()?, v.ok()?)))
+        {
+            if vec.len() >= limit {
+                break;
+            }
+            let cn_id = value.cn_id();
+            if let Some(cn) = &cn {
+                if cn.ts != cn_id.ts || cn.ts_nanos != cn_id.ts_nanos {
+                    continue;
+                }
+            }
+            if filter.incoming.map_or(false, |i| i != value.incoming)
+                || filter.unanswered.map_or(false, |u| u != value.unanswered)
+            {
+                continue;
+            }
+            let peer = self.connection_peer(&cn_id, &mut peers)?;
+            if filter.peer_id.as_ref().map_or(false, |p| *p != peer) {
+                continue;
+            }
+            vec.push(request::RequestFrontend::new(key, value, peer));
+        }
+        Ok(vec)
+    }
+
+    fn fetch_latency(&self, filter: &LatencyFilter) -> Result<Vec<PeerLatency>, Self::Error> {
+        let incoming = filter.incoming.unwrap_or(false);
+
+        // the request id is the id of the message, so start from the first message in the range
+        let begin = timestamp::Item {
+            timestamp: filter.from.unwrap_or(0),
+            index: 0,
+        };
+        let first = self
+            .as_kv::<timestamp::MessageSchema>()
+            .iterator(IteratorMode::From(&begin, Direction::Forward))?
+            .filter_map(|(k, _)| k.ok())
+            .next();
+        let first = match first {
+            Some(key) => request::Key {
+                index: key.index,
+                part: 0,
+            },
+            None => return Ok(vec![]),
+        };
+
+        let mut peers = HashMap::new();
+        let mut requests = Vec::new();
+        for (_, value) in self
+            .as_kv::<request::Schema>()
+            .iterator(IteratorMode::From(&first, Direction::Forward))?
+            .filter_map(|(k, v)| Some((k.ok()?, v.ok()?)))
+        {
+            if filter.to.map_or(false, |to| value.timestamp >= to) {
+                break;
+            }
+            if value.incoming != incoming {
+                continue;
+            }
+            requests.push((self.connection_peer(&value.cn_id(), &mut peers)?, value));
+        }
+        Ok(PeerLatency::new(requests))
+    }
+
+    fn fetch_discovery(&self, filter: &DiscoveryFilter) -> Result<DiscoveryGraph, Self::Error> {
+        let limit = filter.limit.unwrap_or(1000) as usize;
+        if let Some(peer_id) = &filter.peer_id {
+            peer_id
+                .parse::<peer::Key>()
+                .map_err(|e| DBError::SchemaError {
+                    error: SchemaError::DecodeValidationError(e.to_string()),
+                })?;
+        }
+
+        // the key is the id of the message, so start from the first message in the range
+        let begin = timestamp::Item {
+            timestamp: filter.from.unwrap_or(0),
+            index: 0,
+        };
+        let first = self
+            .as_kv::<timestamp::MessageSchema>()
+            .iterator(IteratorMode::From(&begin, Direction::Forward))?
+            .filter_map(|(k, _)| k.ok())
+            .next();
+        let first = match first {
+            Some(key) => key.index,
+            None => return Ok(DiscoveryGraph::new(&[], &[])),
+        };
+
+        let mut peers = HashMap::new();
+        let mut advertisements = Vec::new();
+        for (_, value) in self
+            .as_kv::<advertisement::Schema>()
+            .iterator(IteratorMode::From(&first, Direction::Forward))?
+            .filter_map(|(k, v)| Some((k.ok()?, v.ok()?)))
+        {
+            if filter.to.map_or(false, |to| value.timestamp >= to) || advertisements.len() >= limit
+            {
+                break;
+            }
+            // the addresses the node advertised are not interesting
+            if !value.incoming {
+                continue;
+            }
+            let advertiser = self.connection_peer(&value.cn_id(), &mut peers)?;
+            if filter.peer_id.as_ref().map_or(false, |p| *p != advertiser) {
+                continue;
+            }
+            advertisements.push(Advertisement {
+                advertiser,
+                kind: value.kind,
+                timestamp: value.timestamp,
+                points: value.points,
+            });
+        }
+
+        // the connections the node initiated since the first advertisement
+        // until the end of the range, or until the last advertisement
+        let mut dials = Vec::new();
+        if let (Some(first), Some(last)) = (advertisements.first(), advertisements.last()) {
+            let begin = connection::Key {
+                ts: first.timestamp / 1_000,
+                ts_nanos: 0,
+            };
+            let end = filter.to.unwrap_or(last.timestamp + 1);
+            for (key, value) in self
+                .as_kv::<connection::Schema>()
+                .iterator(IteratorMode::From(&begin, Direction::Forward))?
+                .take(MAX_SCANNED_DIALS)
+                .filter_map(|(k, v)| Some((k.ok()?, v.ok()?)))
+            {
+                if key.ts * 1_000 + key.ts_nanos as u64 / 1_000_000 >= end {
+                    break;
+                }
+                if value.initiator().incoming() {
+                    continue;
+                }
+                dials.push(Dial {
+                    addr: value.remote_addr(),
+                    timestamp: key.ts * 1_000_000_000 + key.ts_nanos as u64,
+                    peer: self.connection_peer(&key, &mut peers)?,
+                });
+            }
+        }
+
+        Ok(DiscoveryGraph::new(&advertisements, &dials))
+    }
+
     fn fetch_chunks_truncated(
         &self,
         filter: &ChunksFilter,
@@ -411,8 +2595,26 @@ impl DatabaseFetch for Db {
                 Direction::Reverse
             }
         };
+        let by_size = match filter.sort.as_deref() {
+            None => false,
+            Some("size") => true,
+            Some(sort) => {
+                return Err(DBError::SchemaError {
+                    error: SchemaError::DecodeValidationError(format!("cannot sort by {}", sort)),
+                }
+                .into())
+            },
+        };
 
         if filter.remote_addr.is_none()
+            && filter.cn.is_none()
+            && filter.peer_id.is_none()
+            && filter.block_hash.is_none()
+            && filter.level_from.is_none()
+            && filter.level_to.is_none()
+            && filter.operation_hash.is_none()
+            && filter.size_min.is_none()
+            && filter.size_max.is_none()
             && filter.source_type.is_none()
             && filter.incoming.is_none()
             && filter.types.is_none()
@@ -429,34 +2631,15 @@ impl DatabaseFetch for Db {
                     IteratorMode::End
                 }
             };
-            let v = self
-                .as_kv::<message::Schema>()
-                .iterator(mode)?
+            let it = self.as_kv::<message::Schema>().iterator(mode)?;
+            if by_size {
+                let items = it.filter_map(|(k, v)| Some((k.ok()?, v.ok()?)));
+                return Ok(largest_messages(items, limit));
+            }
+            let v = it
                 .take(limit)
                 .filter_map(|(k, v)| match (k, v) {
-                    (Ok(key), Ok(value)) => {
-                        let preview = match details(&value, key, self.as_kv()) {
-                            Ok(details) => match details.json_string() {
-                                Ok(p) => p.map(|mut s| {
-                                    utf8_truncate(&mut s, 100);
-                                    s
-                                }),
-                                Err(error) => {
-                                    log::error!(
-                                        "Failed to deserialize message {:?}, error: {}",
-                                        value,
-                                        error
-                                    );
-                                    None
-                                },
-                            },
-                            Err(error) => {
-                                log::error!("Failed to chunks for {:?}, error: {}", value, error);
-                                None
-                            },
-                        };
-                        Some(message::MessageFrontend::new(value, key, preview))
-                    },
+                    (Ok(key), Ok(value)) => Some(message::MessageFrontend::new(value, key)),
                     (Ok(index), Err(err)) => {
                         log::warn!("Failed to load value at {:?}: {}", index, err);
                         None
@@ -466,7 +2649,7 @@ impl DatabaseFetch for Db {
                         None
                     },
                 })
-                .collect();
+                .collect::<Vec<_>>();
 
             Ok(v)
         } else {
@@ -579,6 +2762,93 @@ impl DatabaseFetch for Db {
                     .filter_map(|(k, _)| Some(message_addr::Item::decode(&k).ok()?.index));
                 iters.push(Box::new(it));
             }
+            if let Some(cn_id) = &filter.cn {
+                let cn_id = cn_id.parse().map_err(|e: connection::KeyFromStrError| {
+                    DBError::SchemaError {
+                        error: SchemaError::DecodeValidationError(e.to_string()),
+                    }
+                })?;
+                iters.push(Box::new(self.message_cn_indexes(
+                    cn_id,
+                    cursor,
+                    direction(),
+                )?));
+            }
+            if let Some(peer_id) = &filter.peer_id {
+                let peer_key = peer_id
+                    .parse::<peer::Key>()
+                    .map_err(|e| DBError::SchemaError {
+                        error: SchemaError::DecodeValidationError(e.to_string()),
+                    })?;
+                let mut cns = Vec::new();
+                for cn_id in self.peer_cn_indexes(peer_key)? {
+                    cns.push(self.message_cn_indexes(cn_id, cursor, direction())?);
+                }
+                iters.push(Box::new(
+                    cns.into_iter().kmerge_by(move |x, y| (x < y) == forward),
+                ));
+            }
+            if let Some(hash) = &filter.block_hash {
+                let hash = parse_hash(HashType::BlockHash, hash)?;
+                let it = self.message_hash_indexes::<message_ref::BlockSchema>(
+                    hash,
+                    cursor,
+                    direction(),
+                )?;
+                iters.push(Box::new(it));
+            }
+            if let Some(hash) = &filter.operation_hash {
+                let hash = parse_hash(HashType::OperationHash, hash)?;
+                let it = self.message_hash_indexes::<message_ref::OperationSchema>(
+                    hash,
+                    cursor,
+                    direction(),
+                )?;
+                iters.push(Box::new(it));
+            }
+            if filter.level_from.is_some() || filter.level_to.is_some() {
+                let (begin, end) = match (filter.level_from, filter.level_to) {
+                    (Some(begin), Some(end))
+                        if begin <= end && end - begin < message_ref::MAX_LEVEL_SPAN =>
+                    {
+                        (begin, end)
+                    },
+                    _ => {
+                        let error = format!(
+                            "both `level_from` and `level_to` are required, \
+                             the range is at most {} levels",
+                            message_ref::MAX_LEVEL_SPAN,
+                        );
+                        return Err(DBError::SchemaError {
+                            error: SchemaError::DecodeValidationError(error),
+                        }
+                        .into());
+                    },
+                };
+                // the messages of different levels are interleaved,
+                // the message carrying several headers is in several levels
+                let mut levels = Vec::new();
+                for level in begin..=end {
+                    levels.push(self.message_level_indexes(level, cursor, direction())?);
+                }
+                iters.push(Box::new(
+                    levels
+                        .into_iter()
+                        .kmerge_by(move |x, y| (x < y) == forward)
+                        .dedup(),
+                ));
+            }
+            if filter.size_min.is_some() || filter.size_max.is_some() {
+                // the size is not indexed, scan the messages
+                let range = filter.size_min.unwrap_or(0)..=filter.size_max.unwrap_or(u64::MAX);
+                let it = self
+                    .as_kv::<message::Schema>()
+                    .iterator(IteratorMode::From(&cursor, direction()))?
+                    .filter_map(|(k, v)| Some((k.ok()?, v.ok()?)))
+                    .filter(move |(_, item)| range.contains(&item.size))
+                    .map(|(index, _)| index);
+                iters.push(Box::new(it));
+            }
             if filter.from.is_some() || filter.to.is_some() {
                 let mut timestamp = timestamp::Item {
                     timestamp: u64::MAX,
@@ -621,37 +2891,19 @@ impl DatabaseFetch for Db {
                 iters.push(Box::new(it));
             }
 
+            if by_size {
+                let indexes = sorted_intersect(iters.as_mut_slice(), MAX_SORTED_MESSAGES, forward);
+                let items = indexes.into_iter().filter_map(|index| {
+                    let item = self.as_kv::<message::Schema>().get(&index).ok()??;
+                    Some((index, item))
+                });
+                return Ok(largest_messages(items, limit));
+            }
             let v = sorted_intersect(iters.as_mut_slice(), limit, forward)
                 .into_iter()
                 .filter_map(
                     move |index| match self.as_kv::<message::Schema>().get(&index) {
-                        Ok(Some(value)) => {
-                            let preview = match details(&value, index, self.as_kv()) {
-                                Ok(details) => match details.json_string() {
-                                    Ok(p) => p.map(|mut s| {
-                                        utf8_truncate(&mut s, 100);
-                                        s
-                                    }),
-                                    Err(error) => {
-                                        log::error!(
-                                            "Failed to deserialize message {:?}, error: {}",
-                                            value,
-                                            error
-                                        );
-                                        None
-                                    },
-                                },
-                                Err(error) => {
-                                    log::error!(
-                                        "Failed to chunks for {:?}, error: {}",
-                                        value,
-                                        error
-                                    );
-                                    None
-                                },
-                            };
-                            Some(message::MessageFrontend::new(value, index, preview))
-                        },
+                        Ok(Some(value)) => Some(message::MessageFrontend::new(value, index)),
                         Ok(None) => {
                             log::info!("No value at index: {}", index);
                             None
@@ -662,7 +2914,7 @@ impl DatabaseFetch for Db {
                         },
                     },
                 )
-                .collect();
+                .collect::<Vec<_>>();
             Ok(v)
         }
     }
@@ -836,6 +3088,103 @@ impl DatabaseFetch for Db {
             Ok(v)
         }
     }
+
+    fn fetch_connection_chunks(
+        &self,
+        filter: &ExportFilter,
+    ) -> Result<Vec<ConnectionChunks>, Self::Error> {
+        let connections = if let Some(connection_id) = &filter.cn {
+            let key = connection_id
+                .parse()
+                .map_err(|e: connection::KeyFromStrError| DBError::SchemaError {
+                    error: SchemaError::DecodeValidationError(e.to_string()),
+                })?;
+            let value = self.as_kv::<connection::Schema>().get(&key)?;
+            value.map(|value| (key, value)).into_iter().collect()
+        } else {
+            let limit = filter.limit.unwrap_or(100) as usize;
+            let from = connection::Key {
+                ts: filter.from.unwrap_or(0),
+                ts_nanos: 0,
+            };
+            let to = filter.to.unwrap_or(u64::MAX);
+            self.as_kv::<connection::Schema>()
+                .iterator(IteratorMode::From(&from, Direction::Forward))?
+                .filter_map(|(k, v)| match (k, v) {
+                    (Ok(key), Ok(value)) => Some((key, value)),
+                    (Ok(index), Err(err)) => {
+                        log::warn!("Failed to load value at {:?}: {}", index, err);
+                        None
+                    },
+                    (Err(err), _) => {
+                        log::warn!("Failed to load index: {}", err);
+                        None
+                    },
+                })
+                .take_while(|(key, _)| key.ts < to)
+                .take(limit)
+                .collect::<Vec<_>>()
+        };
+
+        connections
+            .into_iter()
+            .map(|(key, value)| self.connection_chunks(connection::Item::unite(key, value)))
+            .collect()
+    }
+}
+
+fn counter<S>(db: &DB) -> Option<S::Key>
+where
+    S: RocksDbKeyValueSchema,
+    S::Key: Add<u64, Output = S::Key>,
+{
+    KeyValueStoreWithSchemaIterator::<S>::iterator(db, IteratorMode::End)
+        .ok()?
+        .next()?
+        .0
+        .ok()
+        .map(|c| c + 1)
+}
+
+/// Number of messages matching the filter among which the largest are selected
+const MAX_SORTED_MESSAGES: usize = 0x100000;
+
+/// Number of connections after the first advertisement among which the dials are looked for
+const MAX_SCANNED_DIALS: usize = 0x10000;
+
+/// The `limit` largest of at most `MAX_SORTED_MESSAGES` messages, largest first,
+/// the messages of the same size in order of iteration
+fn largest_messages(
+    items: impl Iterator<Item = (u64, message::Item)>,
+    limit: usize,
+) -> Vec<message::MessageFrontend> {
+    // the smallest kept message is on top of the heap
+    let mut heap = BinaryHeap::with_capacity(limit + 1);
+    let mut kept = HashMap::with_capacity(limit + 1);
+    for (position, (index, item)) in items.take(MAX_SORTED_MESSAGES).enumerate() {
+        heap.push(Reverse((item.size, Reverse(position))));
+        kept.insert(position, (index, item));
+        if heap.len() > limit {
+            if let Some(Reverse((_, Reverse(position)))) = heap.pop() {
+                kept.remove(&position);
+            }
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .filter_map(|Reverse((_, Reverse(position)))| kept.remove(&position))
+        .map(|(index, item)| message::MessageFrontend::new(item, index))
+        .collect()
+}
+
+fn parse_hash(hash_type: HashType, hash: &str) -> Result<[u8; 32], DBError> {
+    hash_type
+        .b58check_to_hash(hash)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes.as_slice()).ok())
+        .ok_or_else(|| DBError::SchemaError {
+            error: SchemaError::DecodeValidationError(format!("invalid hash {}", hash)),
+        })
 }
 
 fn details(
@@ -853,11 +3202,3 @@ fn details(
     }
     Ok(message::MessageDetails::new(id, &message_item.ty, &chunks))
 }
-
-fn utf8_truncate(input: &mut String, max_size: usize) {
-    let mut m = max_size;
-    while !input.is_char_boundary(m) {
-        m -= 1;
-    }
-    input.truncate(m);
-}
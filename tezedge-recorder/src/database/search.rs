@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeSet,
     fs,
     ops::DerefMut,
     path::Path,
@@ -9,8 +10,11 @@ use std::{
     thread,
 };
 use tantivy::{
-    directory::MmapDirectory, schema, Index, IndexWriter, Document, ReloadPolicy,
-    query::QueryParser, collector::TopDocs, TantivyError,
+    directory::MmapDirectory,
+    schema, Index, IndexWriter, Document, ReloadPolicy, Term,
+    query::{QueryParser, AllQuery},
+    collector::TopDocs,
+    TantivyError,
 };
 
 pub struct LogIndexer {
@@ -23,6 +27,7 @@ pub struct LogIndexer {
 struct DocumentQueue {
     start_id: u64,
     messages: Vec<String>,
+    removed: Vec<u64>,
 }
 
 impl DocumentQueue {
@@ -33,16 +38,22 @@ impl DocumentQueue {
         self.messages.push(msg.to_string());
     }
 
+    fn remove(&mut self, id: u64) {
+        self.removed.push(id);
+    }
+
     fn is_empty(&self) -> bool {
-        self.messages.is_empty()
+        self.messages.is_empty() && self.removed.is_empty()
     }
 
-    fn drain(self) -> impl Iterator<Item = (u64, String)> {
+    fn drain(self) -> (impl Iterator<Item = (u64, String)>, Vec<u64>) {
         let id = self.start_id;
-        self.messages
+        let messages = self
+            .messages
             .into_iter()
             .enumerate()
-            .map(move |(i, msg)| (id + (i as u64), msg))
+            .map(move |(i, msg)| (id + (i as u64), msg));
+        (messages, self.removed)
     }
 }
 
@@ -93,14 +104,22 @@ impl CommitState {
         if !queue_lock.is_empty() {
             let queue = mem::replace(queue_lock.deref_mut(), DocumentQueue::default());
             drop(queue_lock);
-            for (id, message) in queue.drain() {
-                writer.add_document(self.prepare_doc(&message, id));
-            }
+            self.apply(&writer, queue);
             self.dirty.fetch_or(true, Ordering::SeqCst);
         }
         writer
     }
 
+    fn apply(&self, writer: &IndexWriter, queue: DocumentQueue) {
+        let (messages, removed) = queue.drain();
+        for (id, message) in messages {
+            writer.add_document(self.prepare_doc(&message, id));
+        }
+        for id in removed {
+            writer.delete_term(Term::from_field_u64(self.id_field, id));
+        }
+    }
+
     fn prepare_doc(&self, message: &str, id: u64) -> Document {
         let mut doc = Document::default();
         doc.add_text(self.message_field, message);
@@ -130,11 +149,22 @@ impl LogIndexer {
     {
         let mut schema_builder = schema::Schema::builder();
         schema_builder.add_text_field("message", schema::TEXT);
-        schema_builder.add_text_field("id", schema::STORED);
+        schema_builder.add_u64_field("id", schema::INDEXED | schema::STORED);
         let schema = schema_builder.build();
 
         let _ = fs::create_dir_all(&path);
-        let index = Index::open_or_create(MmapDirectory::open(path)?, schema.clone())?;
+        let index = match Index::open_or_create(MmapDirectory::open(&path)?, schema.clone()) {
+            Ok(index) => index,
+            // the index was created by older version, ids were not indexed,
+            // so documents cannot be removed, drop it and let the caller rebuild it
+            Err(TantivyError::SchemaError(error)) => {
+                log::warn!("recreating log index: {}", error);
+                let _ = fs::remove_dir_all(&path);
+                let _ = fs::create_dir_all(&path);
+                Index::open_or_create(MmapDirectory::open(&path)?, schema.clone())?
+            },
+            Err(error) => return Err(error),
+        };
         let message_field = schema.get_field("message").unwrap();
         let id_field = schema.get_field("id").unwrap();
         let queue = Default::default();
@@ -167,9 +197,7 @@ impl LogIndexer {
                 let mut queue_lock = self.commit_state.queue.lock().unwrap();
                 let queue = mem::replace(queue_lock.deref_mut(), DocumentQueue::default());
                 drop(queue_lock);
-                for (id, message) in queue.drain() {
-                    writer.add_document(self.commit_state.prepare_doc(&message, id));
-                }
+                self.commit_state.apply(&writer, queue);
                 writer.add_document(self.commit_state.prepare_doc(message, id));
                 self.commit_state.dirty.fetch_or(true, Ordering::SeqCst);
             },
@@ -180,6 +208,39 @@ impl LogIndexer {
         }
     }
 
+    pub fn remove(&self, id: u64) {
+        match self.commit_state.writer.try_lock() {
+            Ok(writer) => {
+                let term = Term::from_field_u64(self.commit_state.id_field, id);
+                writer.delete_term(term);
+                self.commit_state.dirty.fetch_or(true, Ordering::SeqCst);
+            },
+            Err(TryLockError::Poisoned(e)) => Err::<(), _>(e).unwrap(),
+            Err(TryLockError::WouldBlock) => self.commit_state.queue.lock().unwrap().remove(id),
+        }
+    }
+
+    /// Remove all documents, including not yet written
+    pub fn clear(&self) -> Result<(), TantivyError> {
+        let writer = self.commit_state.writer.lock().unwrap();
+        *self.commit_state.queue.lock().unwrap() = DocumentQueue::default();
+        writer.delete_all_documents()?;
+        self.commit_state.dirty.fetch_or(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Apply the queued changes and commit them now, without waiting for the writer thread
+    pub fn commit(&self) -> Result<(), TantivyError> {
+        let mut writer = self.commit_state.finalize();
+        self.commit_state.dirty.store(false, Ordering::SeqCst);
+        writer.commit()?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> Result<bool, TantivyError> {
+        Ok(self.index.reader()?.searcher().num_docs() == 0)
+    }
+
     pub fn read(
         &self,
         query: &str,
@@ -212,3 +273,28 @@ impl LogIndexer {
         Ok(it)
     }
 }
+
+/// Read ids of all documents in the index at `path`, does not lock the index for writing
+pub fn read_ids<P>(path: P) -> Result<BTreeSet<u64>, TantivyError>
+where
+    P: AsRef<Path>,
+{
+    let index = Index::open_in_dir(path)?;
+    let id_field = index
+        .schema()
+        .get_field("id")
+        .ok_or_else(|| TantivyError::SchemaError("no id field".to_string()))?;
+    let searcher = index.reader()?.searcher();
+    let num_docs = searcher.num_docs() as usize;
+    if num_docs == 0 {
+        return Ok(BTreeSet::new());
+    }
+    let mut ids = BTreeSet::new();
+    for (_, doc_address) in searcher.search(&AllQuery, &TopDocs::with_limit(num_docs))? {
+        let doc = searcher.doc(doc_address)?;
+        if let Some(&schema::Value::U64(id)) = doc.get_first(id_field) {
+            ids.insert(id);
+        }
+    }
+    Ok(ids)
+}
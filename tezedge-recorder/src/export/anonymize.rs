@@ -0,0 +1,446 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+use crypto::{blake2b, hash::HashType};
+use tezos_messages::p2p::encoding::{ack::AckMessage, peer::PeerMessage};
+use crate::{
+    common::{MessageType, MessageKind},
+    database::ConnectionChunks,
+    tables::{node_log, message::TezosMessage},
+};
+
+// the pseudonym must not collide with another one, give up after this number of attempts
+const MAX_ATTEMPTS: u32 = 0x100;
+
+/// Replaces the addresses, the ports and the public keys of the peers by pseudonyms.
+/// The same value always gets the same pseudonym, so the connections to the same peer
+/// still share it. The pseudonym of an address or a port has the same textual length
+/// as the original, so it is replaced in place and the messages remain decodable.
+pub struct Anonymizer {
+    secret: [u8; 32],
+    ips: HashMap<IpAddr, IpAddr>,
+    used_ips: HashSet<IpAddr>,
+    ports: HashMap<u16, u16>,
+    used_ports: HashSet<u16>,
+    keys: HashMap<[u8; 32], [u8; 32]>,
+    peer_ids: HashMap<String, String>,
+    // replaced in the text of the logs as is, public keys in hex and host names
+    words: Vec<(String, String)>,
+    hostnames: usize,
+}
+
+impl Anonymizer {
+    /// The pseudonyms are the same for each export made with the same secret
+    pub fn new(secret: [u8; 32]) -> Self {
+        Anonymizer {
+            secret,
+            ips: HashMap::new(),
+            used_ips: HashSet::new(),
+            ports: HashMap::new(),
+            used_ports: HashSet::new(),
+            keys: HashMap::new(),
+            peer_ids: HashMap::new(),
+            words: Vec::new(),
+            hostnames: 0,
+        }
+    }
+
+    /// The pseudonyms are valid only for a single export
+    pub fn random() -> Self {
+        Self::new(rand::random())
+    }
+
+    /// The word is replaced in the text of the logs, it is not an address,
+    /// so it cannot be found automatically, the host names of the syslog records
+    /// are taken by `logs`, this is for other names
+    pub fn hostname(&mut self, hostname: &str) {
+        if hostname.is_empty() || self.words.iter().any(|(w, _)| w == hostname) {
+            return;
+        }
+        let pseudonym = format!("host{}", self.hostnames);
+        self.hostnames += 1;
+        self.words.push((hostname.to_string(), pseudonym));
+    }
+
+    pub fn connection_chunks(&mut self, c: &mut ConnectionChunks) {
+        c.connection.remote_addr = self.socket_addr(c.connection.remote_addr);
+        let peer_pk = c.connection.peer_pk();
+        if peer_pk != [0; 32] {
+            let peer_pk = self.public_key(peer_pk);
+            c.connection.set_peer_pk(peer_pk);
+        }
+
+        let mut positions = HashMap::new();
+        for (index, (key, value, ty)) in c.chunks.iter_mut().enumerate() {
+            positions.insert((key.counter, key.sender.incoming()), index);
+            if key.counter == 0 {
+                // the connection message is not encrypted,
+                // `[length(2)][port(2)][public_key(32)]...`, the plain bytes has no length
+                self.connection_message(&mut value.bytes, 2);
+                self.connection_message(&mut value.plain, 0);
+                continue;
+            }
+            match ty {
+                Some(ty @ MessageType::Ack)
+                | Some(ty @ MessageType::P2p(MessageKind::Advertise))
+                | Some(ty @ MessageType::P2p(MessageKind::SwapRequest))
+                | Some(ty @ MessageType::P2p(MessageKind::SwapAck)) => {
+                    self.points(ty, &mut value.plain)
+                },
+                _ => (),
+            }
+        }
+
+        for (_, item) in &mut c.messages {
+            item.remote_addr = self.socket_addr(item.remote_addr);
+            // the preview is computed again from the anonymized bytes
+            let mut plain = Vec::new();
+            let mut complete = true;
+            for key in item.chunks() {
+                match positions.get(&(key.counter, key.sender.incoming())) {
+                    Some(&index) if !c.chunks[index].1.plain.is_empty() => {
+                        plain.extend_from_slice(&c.chunks[index].1.plain)
+                    },
+                    _ => complete = false,
+                }
+            }
+            if complete {
+                item.summarize(&plain);
+            } else {
+                item.preview = None;
+            }
+        }
+    }
+
+    /// The host names of all the records are collected first,
+    /// so the name is replaced in the text of the records before its own record
+    pub fn logs(&mut self, logs: &mut [node_log::ItemWithId]) {
+        for log in logs.iter() {
+            self.hostname(&log.hostname);
+        }
+        for log in logs {
+            log.hostname = self.text(&log.hostname);
+            log.section = self.text(&log.section);
+            log.message = self.text(&log.message);
+        }
+    }
+
+    fn hash(&self, domain: &[u8], data: &[u8], attempt: u32) -> Vec<u8> {
+        let input = [&self.secret[..], domain, data, &attempt.to_le_bytes()].concat();
+        // cannot fail, the output size is valid
+        blake2b::digest_256(&input).expect("blake2b 256 bit digest")
+    }
+
+    fn connection_message(&mut self, bytes: &mut [u8], offset: usize) {
+        if bytes.len() < offset + 34 {
+            return;
+        }
+        let port = u16::from_be_bytes([bytes[offset], bytes[offset + 1]]);
+        let port = self.port(port);
+        bytes[offset..(offset + 2)].clone_from_slice(&port.to_be_bytes());
+        let pk = <[u8; 32]>::try_from(&bytes[(offset + 2)..(offset + 34)]).unwrap();
+        let pk = self.public_key(pk);
+        bytes[(offset + 2)..(offset + 34)].clone_from_slice(&pk);
+    }
+
+    fn public_key(&mut self, pk: [u8; 32]) -> [u8; 32] {
+        if let Some(pseudonym) = self.keys.get(&pk) {
+            return *pseudonym;
+        }
+        let pseudonym = <[u8; 32]>::try_from(self.hash(b"public_key", &pk, 0).as_slice()).unwrap();
+        self.keys.insert(pk, pseudonym);
+        self.words.push((hex::encode(pk), hex::encode(pseudonym)));
+        if let (Some(id), Some(pseudonym_id)) = (peer_id(&pk), peer_id(&pseudonym)) {
+            self.peer_ids.insert(id, pseudonym_id);
+        }
+        pseudonym
+    }
+
+    fn socket_addr(&mut self, addr: SocketAddr) -> SocketAddr {
+        SocketAddr::new(self.ip(addr.ip()), self.port(addr.port()))
+    }
+
+    fn ip(&mut self, ip: IpAddr) -> IpAddr {
+        if let Some(pseudonym) = self.ips.get(&ip) {
+            return *pseudonym;
+        }
+        let pseudonym = match ip {
+            IpAddr::V4(ip) => IpAddr::V4(self.ipv4(ip)),
+            IpAddr::V6(ip) => match ip.octets() {
+                [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => {
+                    IpAddr::V6(self.ipv4(Ipv4Addr::new(a, b, c, d)).to_ipv6_mapped())
+                },
+                _ => IpAddr::V6(self.ipv6(ip)),
+            },
+        };
+        self.ips.insert(ip, pseudonym);
+        self.used_ips.insert(pseudonym);
+        pseudonym
+    }
+
+    // each octet keeps the number of decimal digits
+    fn ipv4(&mut self, ip: Ipv4Addr) -> Ipv4Addr {
+        if let Some(IpAddr::V4(pseudonym)) = self.ips.get(&IpAddr::V4(ip)) {
+            return *pseudonym;
+        }
+        let mut pseudonym = ip;
+        for attempt in 0..MAX_ATTEMPTS {
+            let hash = self.hash(b"ipv4", &ip.octets(), attempt);
+            let mut octets = ip.octets();
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = same_digits(&hash[(i * 4)..], *octet as u32, u8::MAX as u32) as u8;
+            }
+            pseudonym = Ipv4Addr::from(octets);
+            if pseudonym != ip && !self.used_ips.contains(&IpAddr::V4(pseudonym)) {
+                break;
+            }
+        }
+        self.ips.insert(IpAddr::V4(ip), IpAddr::V4(pseudonym));
+        self.used_ips.insert(IpAddr::V4(pseudonym));
+        pseudonym
+    }
+
+    // each group keeps the number of hexadecimal digits, zero groups compressed by `::` remain
+    fn ipv6(&mut self, ip: Ipv6Addr) -> Ipv6Addr {
+        const HEX: &[u8] = b"0123456789abcdef";
+
+        let text = ip.to_string();
+        let mut pseudonym = ip;
+        for attempt in 0..MAX_ATTEMPTS {
+            let hash = self.hash(b"ipv6", &ip.octets(), attempt);
+            let mut bytes = hash.iter().cycle();
+            let mut first = true;
+            let new_text = text
+                .chars()
+                .map(|c| {
+                    if c == ':' {
+                        first = true;
+                        return c;
+                    }
+                    let b = *bytes.next().unwrap() as usize;
+                    let digit = if first { 1 + b % 15 } else { b % 16 };
+                    first = false;
+                    HEX[digit] as char
+                })
+                .collect::<String>();
+            pseudonym = match new_text.parse::<Ipv6Addr>() {
+                Ok(new) if new.to_string() == new_text => new,
+                // unusual textual form, the length is not preserved
+                _ => {
+                    let mut octets = <[u8; 16]>::try_from(&hash[..16]).unwrap();
+                    octets[0] = 0xfd;
+                    Ipv6Addr::from(octets)
+                },
+            };
+            if pseudonym != ip && !self.used_ips.contains(&IpAddr::V6(pseudonym)) {
+                break;
+            }
+        }
+        pseudonym
+    }
+
+    // keeps the number of decimal digits
+    fn port(&mut self, port: u16) -> u16 {
+        if port == 0 {
+            return port;
+        }
+        if let Some(pseudonym) = self.ports.get(&port) {
+            return *pseudonym;
+        }
+        let mut pseudonym = port;
+        for attempt in 0..MAX_ATTEMPTS {
+            let hash = self.hash(b"port", &port.to_be_bytes(), attempt);
+            pseudonym = same_digits(&hash, port as u32, u16::MAX as u32) as u16;
+            if pseudonym != port && !self.used_ports.contains(&pseudonym) {
+                break;
+            }
+        }
+        self.ports.insert(port, pseudonym);
+        self.used_ports.insert(pseudonym);
+        pseudonym
+    }
+
+    /// Decode the message and replace the points it carries, and the peer id of the swap,
+    /// each point is encoded as `[length(4)][text]`, the pseudonym has the same length,
+    /// so it is replaced in place and the message remains decodable
+    fn points(&mut self, ty: &MessageType, plain: &mut [u8]) {
+        let (points, peer_id) = match TezosMessage::decode(ty, plain) {
+            Ok(TezosMessage::PeerMessage(PeerMessage::Advertise(m))) => (m.id().clone(), None),
+            Ok(TezosMessage::PeerMessage(PeerMessage::SwapRequest(m)))
+            | Ok(TezosMessage::PeerMessage(PeerMessage::SwapAck(m))) => {
+                let peer_id = HashType::CryptoboxPublicKeyHash
+                    .hash_to_b58check(m.peer_id().as_ref())
+                    .ok();
+                (vec![m.point().clone()], peer_id)
+            },
+            Ok(TezosMessage::AckMessage(AckMessage::NackWithList(info))) => {
+                (info.potential_peers_to_connect().clone(), None)
+            },
+            _ => return,
+        };
+
+        for point in points {
+            let pseudonym = if let Ok(addr) = point.parse::<SocketAddr>() {
+                self.socket_addr(addr).to_string()
+            } else if let Ok(ip) = point.parse::<IpAddr>() {
+                self.ip(ip).to_string()
+            } else {
+                continue;
+            };
+            if pseudonym.len() != point.len() {
+                log::warn!("cannot replace the point {} in place", point);
+                continue;
+            }
+            let length = (point.len() as u32).to_be_bytes();
+            replace(
+                plain,
+                &[&length[..], point.as_bytes()].concat(),
+                &[&length[..], pseudonym.as_bytes()].concat(),
+            );
+        }
+
+        if let Some(peer_id) = peer_id {
+            let pseudonym = self.peer_id(&peer_id);
+            let hash_type = HashType::CryptoboxPublicKeyHash;
+            if let (Ok(hash), Ok(pseudonym)) = (
+                hash_type.b58check_to_hash(&peer_id),
+                hash_type.b58check_to_hash(&pseudonym),
+            ) {
+                replace(plain, &hash, &pseudonym);
+            }
+        }
+    }
+
+    /// Replace in place each textual address, with or without port,
+    /// the bytes around the addresses are untouched
+    fn addresses(&mut self, data: &mut [u8]) {
+        let is_address_byte =
+            |b: &u8| b.is_ascii_hexdigit() || *b == b'.' || *b == b':' || *b == b'[' || *b == b']';
+
+        let mut position = 0;
+        while position < data.len() {
+            if !is_address_byte(&data[position]) {
+                position += 1;
+                continue;
+            }
+            let length = data[position..]
+                .iter()
+                .take_while(|b| is_address_byte(b))
+                .count();
+            // the punctuation at the end does not belong to the address
+            let trimmed = data[position..(position + length)]
+                .iter()
+                .rev()
+                .skip_while(|b| **b == b'.' || **b == b':')
+                .count();
+            let range = position..(position + trimmed);
+            position += length;
+
+            let text = match std::str::from_utf8(&data[range.clone()]) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            let new_text = if let Ok(addr) = text.parse::<SocketAddr>() {
+                self.socket_addr(addr).to_string()
+            } else if let Ok(ip) = text.parse::<IpAddr>() {
+                self.ip(ip).to_string()
+            } else {
+                continue;
+            };
+            if new_text.len() == range.len() {
+                data[range].clone_from_slice(new_text.as_bytes());
+            }
+        }
+    }
+
+    fn text(&mut self, text: &str) -> String {
+        let mut bytes = text.as_bytes().to_vec();
+        // only ascii bytes are replaced, the text remains valid utf8
+        self.addresses(&mut bytes);
+        let text = String::from_utf8_lossy(&bytes);
+
+        // peer ids, `id...` in base58check
+        let mut result = String::with_capacity(text.len());
+        let mut word = String::new();
+        for c in text.chars().chain(std::iter::once(' ')) {
+            if c.is_ascii_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            let hash_type = HashType::CryptoboxPublicKeyHash;
+            if word.starts_with("id") && hash_type.b58check_to_hash(&word).is_ok() {
+                result.push_str(&self.peer_id(&word));
+            } else {
+                result.push_str(&word);
+            }
+            word.clear();
+            result.push(c);
+        }
+        result.pop();
+
+        for (word, pseudonym) in &self.words {
+            result = result.replace(word, pseudonym);
+        }
+        result
+    }
+
+    fn peer_id(&mut self, id: &str) -> String {
+        if let Some(pseudonym) = self.peer_ids.get(id) {
+            return pseudonym.clone();
+        }
+        let hash_type = HashType::CryptoboxPublicKeyHash;
+        let hash = match hash_type.b58check_to_hash(id) {
+            Ok(hash) => hash,
+            Err(_) => return id.to_string(),
+        };
+        let pseudonym = self.hash(b"peer_id", &hash, 0);
+        let pseudonym = match hash_type.hash_to_b58check(&pseudonym[..hash.len()]) {
+            Ok(pseudonym) => pseudonym,
+            Err(_) => return id.to_string(),
+        };
+        self.peer_ids.insert(id.to_string(), pseudonym.clone());
+        pseudonym
+    }
+}
+
+/// Replace each occurrence of `old` by `new` of the same length
+fn replace(data: &mut [u8], old: &[u8], new: &[u8]) {
+    debug_assert_eq!(old.len(), new.len());
+    if old.is_empty() {
+        return;
+    }
+    let mut position = 0;
+    while position + old.len() <= data.len() {
+        if &data[position..(position + old.len())] == old {
+            data[position..(position + old.len())].clone_from_slice(new);
+            position += old.len();
+        } else {
+            position += 1;
+        }
+    }
+}
+
+fn peer_id(pk: &[u8; 32]) -> Option<String> {
+    let hash = blake2b::digest_128(pk).ok()?;
+    HashType::CryptoboxPublicKeyHash
+        .hash_to_b58check(&hash)
+        .ok()
+}
+
+/// The number which has the same number of decimal digits as `original`, but not greater than `max`
+fn same_digits(hash: &[u8], original: u32, max: u32) -> u32 {
+    let digits = original.to_string().len() as u32;
+    let low = if digits == 1 {
+        1
+    } else {
+        10u32.pow(digits - 1)
+    };
+    let high = (10u32.pow(digits) - 1).min(max);
+    let random = u32::from_le_bytes(<[u8; 4]>::try_from(&hash[..4]).unwrap());
+    low + random % (high - low + 1)
+}
@@ -0,0 +1,388 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    fs::File,
+    io::{self, Read, Write, Seek, SeekFrom, BufRead, BufReader, BufWriter},
+};
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+use storage::persistent::{Encoder, Decoder, SchemaError};
+use crate::{
+    analytics::discovery,
+    database::{Database, DatabaseFetch, ExportFilter, LogsFilter},
+    tables::{connection, chunk, message, node_log},
+};
+use super::anonymize::Anonymizer;
+
+/// The archive is a tar file with the entries:
+/// * `manifest.json` describes the archive, it goes first
+/// * `connections.ndjson` one connection per line, the `raw` field is the stored value in hex
+/// * `chunks.bin` the chunks in the database encoding, the value is encoded again,
+/// so its payload is compressed, record layout: `[key_length(4)][key][value_length(4)][value]`,
+/// lengths are little endian
+/// * `messages.ndjson` one message per line, with its id in the source database
+/// * `logs.ndjson` node logs of the time window, one per line
+const MANIFEST: &str = "manifest.json";
+const CONNECTIONS: &str = "connections.ndjson";
+const CHUNKS: &str = "chunks.bin";
+const MESSAGES: &str = "messages.ndjson";
+const LOGS: &str = "logs.ndjson";
+
+const FORMAT: &str = "tezedge-recorder-archive";
+const VERSION: u64 = 1;
+
+// the number of logs is not limited by the filter, but it should be bounded
+const MAX_LOGS: u64 = 0x100000;
+
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: String,
+    pub version: u64,
+    /// when the archive was created, seconds since epoch
+    pub created: u64,
+    /// the time window of the logs, seconds since epoch
+    pub from: u64,
+    pub to: u64,
+    pub connections: u64,
+    pub chunks: u64,
+    pub messages: u64,
+    pub logs: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ConnectionLine {
+    id: String,
+    #[serde(skip_deserializing)]
+    value: Option<connection::Value>,
+    raw: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MessageLine {
+    id: u64,
+    #[serde(flatten)]
+    item: message::Item,
+}
+
+// the error of the storage crate does not implement `std::error::Error`
+fn schema(error: SchemaError) -> anyhow::Error {
+    anyhow!("schema error: {}", error)
+}
+
+fn header(size: u64, mtime: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_cksum();
+    header
+}
+
+/// The entry is written to a temporary file, because the tar header needs its size
+struct Spool(BufWriter<File>);
+
+impl Spool {
+    fn new() -> io::Result<Self> {
+        Ok(Spool(BufWriter::new(tempfile::tempfile()?)))
+    }
+
+    fn line<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        serde_json::to_writer(&mut self.0, value)?;
+        self.0.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn record(&mut self, data: &[u8]) -> io::Result<()> {
+        self.0.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.0.write_all(data)
+    }
+
+    fn append<W>(self, builder: &mut tar::Builder<W>, name: &str, mtime: u64) -> Result<()>
+    where
+        W: Write,
+    {
+        let mut file = self.0.into_inner().map_err(|e| e.into_error())?;
+        let size = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+        builder.append_data(&mut header(size, mtime), name, file)?;
+        Ok(())
+    }
+}
+
+/// Write the connections selected by the filter, with their chunks and messages,
+/// and the node logs of the time window, if the filter selects a single connection
+/// and has no time window, the window is the lifetime of the connection
+pub fn write<W, Db>(
+    w: W,
+    db: &Db,
+    filter: &ExportFilter,
+    mut anonymizer: Option<&mut Anonymizer>,
+) -> Result<Manifest>
+where
+    W: Write,
+    Db: DatabaseFetch,
+{
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let mut connections = db.fetch_connection_chunks(filter)?;
+    if let Some(anonymizer) = &mut anonymizer {
+        connections
+            .iter_mut()
+            .for_each(|c| anonymizer.connection_chunks(c));
+    }
+
+    let mut connections_data = Spool::new()?;
+    let mut chunks_data = Spool::new()?;
+    let mut messages_data = Spool::new()?;
+    let (mut chunks_count, mut messages_count) = (0, 0);
+    let (mut begin, mut end) = (u64::MAX, 0);
+    for c in connections.iter() {
+        let (key, value) = c.connection.clone().split();
+        begin = begin.min(key.ts);
+        end = end.max(key.ts + 1);
+        let line = ConnectionLine {
+            id: key.to_string(),
+            raw: hex::encode(value.encode().map_err(schema)?),
+            value: Some(value),
+        };
+        connections_data.line(&line)?;
+
+        for (key, value, _) in &c.chunks {
+            end = end.max(value.timestamp() + 1);
+            let key = key.encode().map_err(schema)?;
+            let value = value.encode().map_err(schema)?;
+            chunks_data.record(&key)?;
+            chunks_data.record(&value)?;
+            chunks_count += 1;
+        }
+
+        for (id, item) in &c.messages {
+            let line = MessageLine {
+                id: *id,
+                item: item.clone(),
+            };
+            messages_data.line(&line)?;
+            messages_count += 1;
+        }
+    }
+
+    let from = filter
+        .from
+        .unwrap_or(if begin == u64::MAX { 0 } else { begin });
+    let to = filter.to.unwrap_or(end);
+    let mut logs_data = Spool::new()?;
+    let mut logs = if from < to {
+        let logs_filter = LogsFilter {
+            direction: None,
+            limit: Some(MAX_LOGS),
+            cursor: None,
+            log_level: None,
+            from: Some(from * 1_000),
+            to: Some(to * 1_000),
+            timestamp: None,
+            query: None,
+            node_name: None,
+        };
+        db.fetch_log(&logs_filter)?
+    } else {
+        vec![]
+    };
+    // fetched from the newest to the oldest
+    logs.reverse();
+    if let Some(anonymizer) = &mut anonymizer {
+        anonymizer.logs(&mut logs);
+    }
+    for log in &logs {
+        logs_data.line(log)?;
+    }
+
+    let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let manifest = Manifest {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        created,
+        from,
+        to,
+        connections: connections.len() as u64,
+        chunks: chunks_count,
+        messages: messages_count,
+        logs: logs.len() as u64,
+    };
+
+    let mut builder = tar::Builder::new(w);
+    let manifest_data = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = header(manifest_data.len() as u64, created);
+    builder.append_data(&mut header, MANIFEST, manifest_data.as_slice())?;
+    connections_data.append(&mut builder, CONNECTIONS, created)?;
+    chunks_data.append(&mut builder, CHUNKS, created)?;
+    messages_data.append(&mut builder, MESSAGES, created)?;
+    logs_data.append(&mut builder, LOGS, created)?;
+    builder.into_inner()?.flush()?;
+
+    Ok(manifest)
+}
+
+/// Load the archive into the database, connections and chunks keep their ids,
+/// messages and logs get new ids; the archive is refused if the database
+/// already has a connection with the same id, nothing is loaded in such case
+pub fn read<R, Db>(r: R, db: &Db) -> Result<Manifest>
+where
+    R: Read,
+    Db: Database + DatabaseFetch,
+{
+    let mut archive = tar::Archive::new(r);
+    let mut manifest = None::<Manifest>;
+    let mut connections = Vec::new();
+    let mut connections_loaded = false;
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        // the entries are streamed, the chunks might not fit in memory
+        let mut entry = BufReader::new(entry);
+
+        if name == MANIFEST {
+            let m = serde_json::from_reader::<_, Manifest>(&mut entry)?;
+            if m.format != FORMAT || m.version != VERSION {
+                return Err(anyhow!(
+                    "unsupported archive {} version {}",
+                    m.format,
+                    m.version
+                ));
+            }
+            manifest = Some(m);
+            continue;
+        }
+        if manifest.is_none() {
+            return Err(anyhow!("the archive must start with {}", MANIFEST));
+        }
+
+        match name.as_str() {
+            CONNECTIONS => {
+                let mut items = Vec::new();
+                let mut collisions = Vec::new();
+                for line in lines(entry) {
+                    let line = serde_json::from_slice::<ConnectionLine>(&line?)?;
+                    let key = line.id.parse::<connection::Key>()?;
+                    let raw = hex::decode(line.raw)?;
+                    // the archive written before the lifecycle was stored
+                    let value = connection::Value::decode(&raw)
+                        .or_else(|_| {
+                            let mut value = connection::Value::decode_v1(&raw)?;
+                            value.close_v1(key.ts * 1_000_000_000 + key.ts_nanos as u64);
+                            Ok(value)
+                        })
+                        .map_err(schema)?;
+                    if db.fetch_connection(&key)?.is_some() {
+                        collisions.push(key.to_string());
+                    }
+                    items.push(connection::Item::unite(key, value));
+                }
+                if !collisions.is_empty() {
+                    return Err(anyhow!(
+                        "the database already has the connections: {}",
+                        collisions.join(", ")
+                    ));
+                }
+                for item in items {
+                    db.store_connection(item.clone());
+                    connections.push(item);
+                }
+                connections_loaded = true;
+            },
+            // the chunks and the messages must not be loaded before the connections are checked
+            CHUNKS | MESSAGES if !connections_loaded => {
+                return Err(anyhow!(
+                    "the archive must have {} before {}",
+                    CONNECTIONS,
+                    name
+                ));
+            },
+            CHUNKS => {
+                while let Some(key) = next_record(&mut entry)? {
+                    let value = next_record(&mut entry)?
+                        .ok_or_else(|| anyhow!("{} is truncated", CHUNKS))?;
+                    let key = chunk::Key::decode(&key).map_err(schema)?;
+                    let value = chunk::Value::decode(&value).map_err(schema)?;
+                    db.store_chunk(chunk::Item::unite(key, value));
+                }
+            },
+            MESSAGES => {
+                for line in lines(entry) {
+                    let mut item = serde_json::from_slice::<MessageLine>(&line?)?.item;
+                    // the advertised addresses are not in the archive,
+                    // decode them from the chunks, which precede the messages
+                    if discovery::carries(&item.ty) {
+                        let mut plain = Vec::new();
+                        for key in item.chunks() {
+                            match db.fetch_chunk(&key)? {
+                                Some(c) => plain.extend_from_slice(&c.plain),
+                                None => break,
+                            }
+                        }
+                        let mut summarized = item.clone();
+                        summarized.summarize(&plain);
+                        item.refs.points = summarized.refs.points;
+                    }
+                    db.store_message(item);
+                }
+            },
+            LOGS => {
+                for line in lines(entry) {
+                    let line = serde_json::from_slice::<node_log::ItemWithId>(&line?)?;
+                    db.store_log(node_log::Item {
+                        level: line.level,
+                        timestamp: line.timestamp,
+                        section: line.section,
+                        message: line.message,
+                        hostname: line.hostname,
+                    });
+                }
+            },
+            // unknown entries are written by newer version, but compatible
+            _ => log::warn!("skip unknown archive entry {}", name),
+        }
+    }
+
+    // store the connections again, the messages are added to the summaries of the peers
+    for item in connections {
+        db.update_connection(item);
+    }
+
+    manifest.ok_or_else(|| anyhow!("the archive has no {}", MANIFEST))
+}
+
+/// The non empty lines of the entry
+fn lines<R>(entry: R) -> impl Iterator<Item = io::Result<Vec<u8>>>
+where
+    R: BufRead,
+{
+    entry
+        .split(b'\n')
+        .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+}
+
+/// The next record of the chunks entry, `None` if the entry is over
+fn next_record<R>(entry: &mut R) -> Result<Option<Vec<u8>>>
+where
+    R: Read,
+{
+    let mut length = [0; 4];
+    if entry.read(&mut length[..1])? == 0 {
+        return Ok(None);
+    }
+    entry
+        .read_exact(&mut length[1..])
+        .map_err(|_| anyhow!("{} is truncated", CHUNKS))?;
+    let length = u32::from_le_bytes(length) as u64;
+    // the length might be corrupted, allocate only what is actually read
+    let mut record = Vec::new();
+    entry.by_ref().take(length).read_to_end(&mut record)?;
+    if record.len() as u64 != length {
+        return Err(anyhow!("{} is truncated", CHUNKS));
+    }
+    Ok(Some(record))
+}
@@ -0,0 +1,6 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+pub mod pcapng;
+pub mod archive;
+pub mod anonymize;
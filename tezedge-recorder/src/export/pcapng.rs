@@ -0,0 +1,302 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+use crate::database::ConnectionChunks;
+
+/// Raw ip packets, no link layer header
+const LINKTYPE_RAW: u16 = 101;
+
+/// The chunk which does not fit is split into several segments
+const MAX_SEGMENT: usize = 0x8000;
+
+/// The option value length is 16 bit
+const MAX_COMMENT: usize = 0xf000;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// Write the connections as pcapng file, each chunk becomes a tcp segment
+/// carrying the bytes as they were on the wire, the packet comments contain
+/// the chunk id, the type of the message and the decrypted bytes in hex;
+/// the local address of the connection is not recorded,
+/// the node's side is the unspecified address with the `local_port`
+pub fn write<W>(mut w: W, connections: &[ConnectionChunks], local_port: u16) -> io::Result<()>
+where
+    W: Write,
+{
+    // section header block
+    let mut body = Vec::new();
+    body.extend_from_slice(&0x1a2b3c4du32.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // section length is not specified
+    body.extend_from_slice(&(-1i64).to_le_bytes());
+    // shb_userappl
+    option(&mut body, 4, b"tezedge-recorder");
+    option(&mut body, 0, &[]);
+    block(&mut w, 0x0a0d0d0a, &body)?;
+
+    // interface description block
+    let mut body = Vec::new();
+    body.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    body.extend_from_slice(&0u32.to_le_bytes());
+    // if_tsresol, nanoseconds
+    option(&mut body, 9, &[9]);
+    option(&mut body, 0, &[]);
+    block(&mut w, 1, &body)?;
+
+    for connection in connections {
+        Flow::new(connection, local_port).write(&mut w)?;
+    }
+
+    w.flush()
+}
+
+fn option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+fn block<W>(w: &mut W, ty: u32, body: &[u8]) -> io::Result<()>
+where
+    W: Write,
+{
+    let length = (body.len() + 12) as u32;
+    w.write_all(&ty.to_le_bytes())?;
+    w.write_all(&length.to_le_bytes())?;
+    w.write_all(body)?;
+    w.write_all(&length.to_le_bytes())
+}
+
+fn enhanced_packet<W>(w: &mut W, ts: u64, packet: &[u8], comments: &[String]) -> io::Result<()>
+where
+    W: Write,
+{
+    let mut body = Vec::with_capacity(packet.len() + 32);
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(ts as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(packet);
+    pad(&mut body);
+    if !comments.is_empty() {
+        for comment in comments {
+            // opt_comment
+            option(&mut body, 1, comment.as_bytes());
+        }
+        option(&mut body, 0, &[]);
+    }
+    block(w, 6, &body)
+}
+
+fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial;
+    for pair in data.chunks(2) {
+        let word = if pair.len() == 2 {
+            u16::from_be_bytes([pair[0], pair[1]])
+        } else {
+            u16::from_be_bytes([pair[0], 0])
+        };
+        sum += word as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn ip_sum(ip: &IpAddr) -> u32 {
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    octets
+        .chunks(2)
+        .map(|p| u16::from_be_bytes([p[0], p[1]]) as u32)
+        .sum()
+}
+
+/// Build the ip packet with tcp segment
+fn tcp_packet(
+    src: &SocketAddr,
+    dst: &SocketAddr,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut tcp = Vec::with_capacity(payload.len() + 20);
+    tcp.extend_from_slice(&src.port().to_be_bytes());
+    tcp.extend_from_slice(&dst.port().to_be_bytes());
+    tcp.extend_from_slice(&seq.to_be_bytes());
+    tcp.extend_from_slice(&ack.to_be_bytes());
+    tcp.push(5 << 4);
+    tcp.push(flags);
+    tcp.extend_from_slice(&0xffffu16.to_be_bytes());
+    tcp.extend_from_slice(&[0, 0, 0, 0]);
+    tcp.extend_from_slice(payload);
+
+    // pseudo header
+    let pseudo = ip_sum(&src.ip()) + ip_sum(&dst.ip()) + 6 + tcp.len() as u32;
+    let sum = checksum(&tcp, pseudo);
+    tcp[16..18].clone_from_slice(&sum.to_be_bytes());
+
+    let mut packet = Vec::with_capacity(tcp.len() + 40);
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            packet.push(0x45);
+            packet.push(0);
+            packet.extend_from_slice(&((tcp.len() + 20) as u16).to_be_bytes());
+            // identification, don't fragment
+            packet.extend_from_slice(&[0, 0, 0x40, 0]);
+            // ttl, protocol
+            packet.extend_from_slice(&[64, 6, 0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            let sum = checksum(&packet, 0);
+            packet[10..12].clone_from_slice(&sum.to_be_bytes());
+        },
+        (src, dst) => {
+            let to_v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+            // next header, hop limit
+            packet.extend_from_slice(&[6, 64]);
+            packet.extend_from_slice(&to_v6(src).octets());
+            packet.extend_from_slice(&to_v6(dst).octets());
+        },
+    }
+    packet.extend_from_slice(&tcp);
+    packet
+}
+
+/// Synthetic tcp connection
+struct Flow<'a> {
+    connection: &'a ConnectionChunks,
+    local: SocketAddr,
+    remote: SocketAddr,
+    // the sequence number of the next byte sent by the local and by the remote side
+    local_seq: u32,
+    remote_seq: u32,
+    ts: u64,
+}
+
+impl<'a> Flow<'a> {
+    fn new(connection: &'a ConnectionChunks, local_port: u16) -> Self {
+        // the address is stored as ipv6, the ipv4 address is mapped
+        let remote = connection.connection.remote_addr;
+        let remote = match remote.ip() {
+            IpAddr::V6(ip) => match ip.octets() {
+                [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => {
+                    SocketAddr::new(Ipv4Addr::new(a, b, c, d).into(), remote.port())
+                },
+                _ => remote,
+            },
+            IpAddr::V4(_) => remote,
+        };
+        let local_ip = match remote.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let key = connection.connection.key();
+        Flow {
+            connection,
+            local: SocketAddr::new(local_ip, local_port),
+            remote,
+            local_seq: 0,
+            remote_seq: 0,
+            ts: key.ts * 1_000_000_000 + key.ts_nanos as u64,
+        }
+    }
+
+    fn send<W>(
+        &mut self,
+        w: &mut W,
+        incoming: bool,
+        flags: u8,
+        payload: &[u8],
+        comments: &[String],
+    ) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let (src, dst, seq, ack) = if incoming {
+            (&self.remote, &self.local, self.remote_seq, self.local_seq)
+        } else {
+            (&self.local, &self.remote, self.local_seq, self.remote_seq)
+        };
+        let packet = tcp_packet(src, dst, seq, ack, flags, payload);
+        enhanced_packet(w, self.ts, &packet, comments)?;
+        let length = payload.len() as u32
+            + if flags & (TCP_SYN | TCP_FIN) != 0 {
+                1
+            } else {
+                0
+            };
+        if incoming {
+            self.remote_seq = self.remote_seq.wrapping_add(length);
+        } else {
+            self.local_seq = self.local_seq.wrapping_add(length);
+        }
+        Ok(())
+    }
+
+    fn write<W>(mut self, w: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        // the chunks are stored in order of their counters, restore the order of time
+        let mut chunks = self.connection.chunks.iter().collect::<Vec<_>>();
+        chunks.sort_by_key(|(_, value, _)| value.timestamp());
+
+        let incoming = self.connection.connection.initiator.incoming();
+        self.send(w, incoming, TCP_SYN, &[], &[])?;
+        self.send(w, !incoming, TCP_SYN | TCP_ACK, &[], &[])?;
+        self.send(w, incoming, TCP_ACK, &[], &[])?;
+
+        for (key, value, ty) in chunks {
+            // the timestamp of the chunk is in seconds, keep the packets ordered
+            self.ts = (value.timestamp() * 1_000_000_000).max(self.ts + 1_000);
+            let mut comments = vec![match ty {
+                Some(ty) => format!("chunk: {}, message: {:?}", key, ty),
+                None => format!("chunk: {}, message: unknown", key),
+            }];
+            let plain = hex::encode(&value.plain);
+            comments.extend(
+                plain
+                    .as_bytes()
+                    .chunks(MAX_COMMENT)
+                    .map(|c| format!("plain: {}", String::from_utf8_lossy(c))),
+            );
+
+            let incoming = key.sender.incoming();
+            let mut segments = value.bytes.chunks(MAX_SEGMENT);
+            let first = segments.next().unwrap_or(&[]);
+            self.send(w, incoming, TCP_PSH | TCP_ACK, first, &comments)?;
+            for segment in segments {
+                self.send(w, incoming, TCP_PSH | TCP_ACK, segment, &[])?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+use super::{processor::SessionKeys, tables::connection};
+
+/// Appends the secrets of every connection to the file, one line per connection:
+/// `TEZOS_SESSION <connection id> <remote address> <initiator> <local nonce> <remote nonce> <precomputed key>`,
+/// the initiator is `local` or `remote`, the nonces and the key are in hex.
+/// Anyone who can read the file can decrypt the recorded traffic,
+/// so the file is only readable by the owner.
+pub struct KeyLog {
+    file: Mutex<File>,
+}
+
+impl KeyLog {
+    pub fn open<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&path)?;
+        // the file might exist before with wider permissions
+        file.set_permissions(PermissionsExt::from_mode(0o600))?;
+        log::warn!(
+            "writing session keys to {:?}, the recorded traffic can be decrypted with them",
+            path.as_ref(),
+        );
+        Ok(KeyLog {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn write(&self, cn: &connection::Item, keys: &SessionKeys) {
+        let line = format!(
+            "TEZOS_SESSION {} {} {} {} {} {}\n",
+            cn.key(),
+            cn.remote_addr,
+            if cn.initiator.incoming() {
+                "remote"
+            } else {
+                "local"
+            },
+            hex::encode(&keys.local_nonce),
+            hex::encode(&keys.remote_nonce),
+            hex::encode(&keys.precomputed_key),
+        );
+        // write the whole line at once, so the lines of concurrent writers do not interleave
+        let mut file = self.file.lock().unwrap();
+        if let Err(error) = file.write_all(line.as_bytes()) {
+            log::error!("cannot write session keys: {}", error);
+        }
+    }
+}
@@ -10,9 +10,14 @@ pub mod common;
 pub mod tables;
 mod system;
 mod log_client;
+mod retention;
 mod processor;
+mod keylog;
+pub mod capture;
 pub mod main_loop;
 pub mod database;
+pub mod export;
+pub mod analytics;
 mod server;
 
-pub use self::system::System;
+pub use self::{system::System, server::QueryPool};
@@ -3,6 +3,7 @@
 
 use std::{
     collections::HashMap,
+    io,
     net::SocketAddr,
     sync::{
         Arc,
@@ -10,24 +11,63 @@ use std::{
     },
 };
 use anyhow::Result;
-use bpf_recorder::{BpfModuleClient, SnifferEvent, Command, EventId, SocketId};
+use bpf_recorder::{SnifferEvent, EventId, SocketId};
 
 use super::{
+    capture::{CaptureSource, BpfCapture, CaptureWriter, Recording},
     processor::Connection,
-    database::{Database, DatabaseNew, DatabaseFetch},
+    tables::connection::CloseReason,
+    database::{Database, DatabaseNew, DatabaseFetch, DatabaseRetention},
     system::System,
 };
 
 pub fn run<Db>(system: &mut System<Db>, running: Arc<AtomicBool>) -> Result<()>
 where
-    Db: Database + DatabaseNew + DatabaseFetch + Sync + Send + 'static,
+    Db: Database + DatabaseNew + DatabaseFetch + DatabaseRetention + Sync + Send + 'static,
 {
-    let (client, mut rb) = BpfModuleClient::new_sync(system.sniffer_path())?;
-    let mut list = ConnectionList::new(client, system);
+    let source = BpfCapture::connect(system.sniffer_path())?;
+    if let Some(path) = system.capture_file() {
+        let writer = CaptureWriter::create(path)?;
+        run_with_source(Recording::new(source, writer), system, running)
+    } else {
+        run_with_source(source, system, running)
+    }
+}
+
+/// Process the events from any source, the same way as intercepted by bpf
+pub fn run_with_source<Db, S>(
+    source: S,
+    system: &mut System<Db>,
+    running: Arc<AtomicBool>,
+) -> Result<()>
+where
+    Db: Database + DatabaseNew + DatabaseFetch + DatabaseRetention + Sync + Send + 'static,
+    S: CaptureSource,
+{
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let mut list = ConnectionList::new(source, system);
     list.watching()?;
 
+    let wall_clock = list.source.wall_clock();
+    // the replayed event happened at the recorded time, the live event happens now
+    let timestamp = |id: &EventId| {
+        if wall_clock {
+            id.ts_finish()
+        } else {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64
+        }
+    };
+
     while running.load(Ordering::Relaxed) {
-        let events = rb.read_blocking::<SnifferEvent>(&running)?;
+        let events = match list.source.read_events(&running) {
+            Ok(events) => events,
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error.into()),
+        };
         for event in events {
             match event {
                 SnifferEvent::Bind { id, address } => {
@@ -40,7 +80,8 @@ where
                     let _ = id;
                 },
                 SnifferEvent::Connect { id, address } => {
-                    list.handle_connection(id, address, false);
+                    let ts = timestamp(&id);
+                    list.handle_connection(id, address, false, ts);
                 },
                 SnifferEvent::Accept {
                     id,
@@ -48,7 +89,8 @@ where
                     listen_on_fd,
                 } => {
                     let _ = listen_on_fd;
-                    list.handle_connection(id, address, true);
+                    let ts = timestamp(&id);
+                    list.handle_connection(id, address, true, ts);
                 },
                 SnifferEvent::Data {
                     id,
@@ -57,14 +99,17 @@ where
                     incoming,
                 } => {
                     if !data.is_empty() {
-                        list.handle_data(id, data, net, incoming);
+                        let ts = timestamp(&id);
+                        list.handle_data(id, data, net, incoming, ts);
                     }
                 },
                 SnifferEvent::Close { id } => {
-                    list.handle_close(id);
+                    let ts = timestamp(&id);
+                    list.handle_close(id, ts);
                 },
                 SnifferEvent::GetFd { id } => {
-                    list.handle_get_fd(id);
+                    let ts = timestamp(&id);
+                    list.handle_get_fd(id, ts);
                 },
                 SnifferEvent::Debug { id, msg } => {
                     log::warn!("{} {}", id, msg);
@@ -76,19 +121,20 @@ where
     Ok(())
 }
 
-struct ConnectionList<'a, Db> {
-    client: BpfModuleClient,
+struct ConnectionList<'a, Db, S> {
+    source: S,
     system: &'a mut System<Db>,
     connections: HashMap<SocketId, Connection<Db>>,
 }
 
-impl<'a, Db> ConnectionList<'a, Db>
+impl<'a, Db, S> ConnectionList<'a, Db, S>
 where
-    Db: Database + DatabaseNew + DatabaseFetch + Sync + Send + 'static,
+    Db: Database + DatabaseNew + DatabaseFetch + DatabaseRetention + Sync + Send + 'static,
+    S: CaptureSource,
 {
-    fn new(client: BpfModuleClient, system: &'a mut System<Db>) -> Self {
+    fn new(source: S, system: &'a mut System<Db>) -> Self {
         ConnectionList {
-            client,
+            source,
             system,
             connections: HashMap::new(),
         }
@@ -96,31 +142,34 @@ where
 
     fn watching(&mut self) -> Result<()> {
         for p2p_config in self.system.p2p_configs() {
-            self.client.send_command(Command::WatchPort {
-                port: p2p_config.port,
-            })?;
+            self.source.watch_port(p2p_config.port)?;
         }
 
         Ok(())
     }
 
-    fn handle_connection(&mut self, event_id: EventId, address: SocketAddr, incoming: bool) {
+    fn handle_connection(
+        &mut self,
+        event_id: EventId,
+        address: SocketAddr,
+        incoming: bool,
+        timestamp: u64,
+    ) {
         let socket_id = event_id.socket_id;
         let pid = socket_id.pid;
-        let fd = socket_id.fd;
         if !self.system.should_ignore(&address) {
             if let Some((info, db)) = self.system.get_mut(pid) {
-                let connection = Connection::new(address, incoming, info.identity(), db);
+                let identity = info.identity();
+                let keylog = info.keylog();
+                let connection =
+                    Connection::new(address, incoming, identity, keylog, db, timestamp);
                 if let Some(old) = self.connections.insert(socket_id, connection) {
-                    old.join();
+                    old.join(CloseReason::FdReused, timestamp);
                 }
                 return;
             }
         }
-        match self
-            .client
-            .send_command(Command::IgnoreConnection { pid, fd })
-        {
+        match self.source.ignore_connection(socket_id) {
             Ok(()) => (),
             Err(error) => {
                 log::error!(
@@ -132,29 +181,36 @@ where
         }
     }
 
-    fn handle_data(&mut self, id: EventId, payload: Vec<u8>, net: bool, incoming: bool) {
+    fn handle_data(
+        &mut self,
+        id: EventId,
+        payload: Vec<u8>,
+        net: bool,
+        incoming: bool,
+        timestamp: u64,
+    ) {
         if payload.len() > 0x1000000 {
             log::warn!("received from ring buffer big payload {}", payload.len());
         }
         if let Some(connection) = self.connections.get_mut(&id.socket_id) {
-            connection.handle_data(&payload, net, incoming);
+            connection.handle_data(&payload, net, incoming, timestamp);
         } else {
             log::debug!("failed to handle data, connection does not exist: {}", id);
         }
     }
 
-    fn handle_get_fd(&mut self, id: EventId) {
+    fn handle_get_fd(&mut self, id: EventId, timestamp: u64) {
         let socket_id = id.socket_id;
         if let Some(c) = self.connections.remove(&socket_id) {
             c.warn_fd_changed();
-            c.join();
+            c.join(CloseReason::FdReused, timestamp);
         }
     }
 
-    fn handle_close(&mut self, id: EventId) {
+    fn handle_close(&mut self, id: EventId, timestamp: u64) {
         let socket_id = id.socket_id;
         if let Some(old) = self.connections.remove(&socket_id) {
-            old.join();
+            old.join(CloseReason::Closed, timestamp);
         }
     }
 }
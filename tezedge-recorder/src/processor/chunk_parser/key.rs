@@ -66,6 +66,28 @@ impl Keys {
     }
 }
 
+/// The secrets of the connection, enough to decrypt its chunks without the identity
+pub struct SessionKeys {
+    pub precomputed_key: [u8; 32],
+    /// initial nonce of the chunks sent by the local node
+    pub local_nonce: [u8; 24],
+    /// initial nonce of the chunks sent by the remote peer
+    pub remote_nonce: [u8; 24],
+}
+
+impl<'a> From<&'a Keys> for SessionKeys {
+    /// Take the secrets before any chunk is decrypted, the nonces are the initial ones
+    fn from(keys: &'a Keys) -> Self {
+        let mut precomputed_key = [0; 32];
+        precomputed_key.clone_from_slice(keys.local.key.as_ref());
+        SessionKeys {
+            precomputed_key,
+            local_nonce: keys.local.nonce.get_bytes(),
+            remote_nonce: keys.remote.nonce.get_bytes(),
+        }
+    }
+}
+
 impl Key {
     pub fn decrypt(&mut self, payload: &[u8]) -> Result<Vec<u8>, CryptoError> {
         let plain = self.key.decrypt(&payload[2..], &self.nonce)?;
@@ -9,3 +9,4 @@ mod state;
 mod parser;
 
 pub use self::parser::{Handshake, HandshakeOutput, HandshakeDone, ChunkHandler};
+pub use self::key::SessionKeys;
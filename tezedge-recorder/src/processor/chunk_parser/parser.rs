@@ -5,6 +5,7 @@ use typenum::Bit;
 use either::Either;
 use super::{
     state::{Initial, HaveCm, Uncertain, HaveKey, HaveNotKey, CannotDecrypt, MakeKeyOutput},
+    key::SessionKeys,
     tables::{connection, chunk},
     common::{Local, Remote},
     Identity,
@@ -25,6 +26,8 @@ pub struct HandshakeOutput {
     pub l_chunk: Option<chunk::Item>,
     pub remote: HandshakeDone<Remote>,
     pub r_chunk: Option<chunk::Item>,
+    /// the secrets of the connection, if the keys are derived
+    pub keys: Option<SessionKeys>,
 }
 
 impl From<MakeKeyOutput> for HandshakeOutput {
@@ -34,6 +37,7 @@ impl From<MakeKeyOutput> for HandshakeOutput {
             l_chunk: v.l_chunk,
             remote: v.remote.into(),
             r_chunk: v.r_chunk,
+            keys: v.keys,
         }
     }
 }
@@ -118,6 +122,7 @@ impl Handshake {
                                 l_chunk,
                                 remote: HandshakeDone::Uncertain(r),
                                 r_chunk,
+                                keys: None,
                             })
                         },
                     }
@@ -146,6 +151,7 @@ impl Handshake {
                                 l_chunk,
                                 remote: HandshakeDone::Uncertain(r),
                                 r_chunk,
+                                keys: None,
                             })
                         },
                     }
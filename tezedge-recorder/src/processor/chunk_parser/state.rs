@@ -7,7 +7,7 @@ use thiserror::Error;
 use typenum::{self, Bit};
 use super::{
     buffer::Buffer,
-    key::{Keys, Key},
+    key::{Keys, Key, SessionKeys},
     tables::{connection, chunk},
     common::{Sender, Local, Remote},
     Identity,
@@ -167,6 +167,7 @@ pub struct MakeKeyOutput {
     pub l_chunk: Option<chunk::Item>,
     pub remote: Result<HaveKey<Remote>, HaveNotKey<Remote>>,
     pub r_chunk: Option<chunk::Item>,
+    pub keys: Option<SessionKeys>,
 }
 
 impl HaveCm<Local> {
@@ -201,7 +202,9 @@ impl HaveCm<Local> {
         let identity = &self.inner.id;
         let initiator = cn.initiator.clone();
         match Keys::new(identity, local_chunk, remote_chunk, initiator) {
-            Ok(Keys { local, remote }) => {
+            Ok(keys) => {
+                let session_keys = SessionKeys::from(&keys);
+                let Keys { local, remote } = keys;
                 let (l, l_chunk) = self.have_key(local);
                 let (r, r_chunk) = peer.have_key(remote);
                 match check(&l_chunk.bytes) {
@@ -227,6 +230,7 @@ impl HaveCm<Local> {
                     l_chunk: Some(l_chunk),
                     remote: Ok(r),
                     r_chunk: Some(r_chunk),
+                    keys: Some(session_keys),
                 }
             },
             Err(_) => {
@@ -238,6 +242,7 @@ impl HaveCm<Local> {
                     l_chunk,
                     remote: Err(r),
                     r_chunk,
+                    keys: None,
                 }
             },
         }
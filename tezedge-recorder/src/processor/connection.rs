@@ -1,20 +1,28 @@
 // Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use either::Either;
 use super::{
     chunk_parser::{Handshake, HandshakeOutput, HandshakeDone, ChunkHandler},
     message_parser::MessageParser,
     Identity, Database,
     common::{Local, Remote, Initiator},
-    tables::connection,
+    tables::connection::{self, CloseReason},
+    keylog::KeyLog,
 };
 
 pub struct Connection<Db> {
     state: Option<ConnectionState<Db>>,
     item: connection::Item,
     db: Arc<Db>,
+    keylog: Option<Arc<KeyLog>>,
+    // when the counters of the connection were stored last time
+    updated: Instant,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -32,17 +40,31 @@ impl<Db> Connection<Db>
 where
     Db: Database,
 {
-    pub fn new(remote_addr: SocketAddr, incoming: bool, identity: Identity, db: Arc<Db>) -> Self {
-        let item = connection::Item::new(Initiator::new(incoming), remote_addr);
+    /// The `timestamp` is nanoseconds since epoch, here and in other methods
+    pub fn new(
+        remote_addr: SocketAddr,
+        incoming: bool,
+        identity: Identity,
+        keylog: Option<Arc<KeyLog>>,
+        db: Arc<Db>,
+        timestamp: u64,
+    ) -> Self {
+        let item = connection::Item::new(Initiator::new(incoming), remote_addr, timestamp);
         let state = ConnectionState::Handshake(Handshake::new(&item.key(), identity));
         Connection {
             state: Some(state),
             item,
             db,
+            keylog,
+            updated: Instant::now(),
         }
     }
 
-    pub fn handle_data(&mut self, payload: &[u8], net: bool, incoming: bool) {
+    /// The counters of the open connection are stored at most once in this period
+    const UPDATE_PERIOD: Duration = Duration::from_secs(1);
+
+    pub fn handle_data(&mut self, payload: &[u8], net: bool, incoming: bool, timestamp: u64) {
+        self.item.count_bytes(incoming, payload.len());
         let state = match self.state.take().unwrap() {
             ConnectionState::Handshake(h) => {
                 match h.handle_data(payload, net, incoming, &mut self.item) {
@@ -52,10 +74,17 @@ where
                         l_chunk,
                         remote,
                         r_chunk,
+                        keys,
                     }) => {
                         let mut local_mp = MessageParser::new(self.db.clone());
                         let mut remote_mp = MessageParser::new(self.db.clone());
+                        local_mp.set_timestamp(timestamp);
+                        remote_mp.set_timestamp(timestamp);
                         self.db.store_connection(self.item.clone());
+                        self.updated = Instant::now();
+                        if let (Some(keylog), Some(keys)) = (&self.keylog, &keys) {
+                            keylog.write(&self.item, keys);
+                        }
                         if let Some(chunk) = l_chunk {
                             local_mp.handle_chunk(chunk, &mut self.item);
                         }
@@ -78,6 +107,7 @@ where
                 mut remote_mp,
             } => {
                 if !incoming {
+                    local_mp.set_timestamp(timestamp);
                     ConnectionState::HandshakeDone {
                         local: local.handle_data(payload, net, &mut self.item, &mut local_mp),
                         local_mp,
@@ -85,6 +115,7 @@ where
                         remote_mp,
                     }
                 } else {
+                    remote_mp.set_timestamp(timestamp);
                     ConnectionState::HandshakeDone {
                         local,
                         local_mp,
@@ -95,6 +126,15 @@ where
             },
         };
         self.state = Some(state);
+
+        if self.handshake_done() && self.updated.elapsed() >= Self::UPDATE_PERIOD {
+            self.db.update_connection(self.item.clone());
+            self.updated = Instant::now();
+        }
+    }
+
+    fn handshake_done(&self) -> bool {
+        matches!(&self.state, &Some(ConnectionState::HandshakeDone { .. }))
     }
 
     pub fn warn_fd_changed(&self) {
@@ -106,5 +146,12 @@ where
         }
     }
 
-    pub fn join(self) {}
+    /// Store the close time and reason, the connection which did not finish
+    /// the handshake is not stored
+    pub fn join(mut self, reason: CloseReason, timestamp: u64) {
+        if self.handshake_done() {
+            self.item.close(reason, timestamp);
+            self.db.update_connection(self.item);
+        }
+    }
 }
@@ -7,10 +7,15 @@ use super::{
     Database,
     tables::{connection, chunk, message},
 };
+use crate::analytics::conformance;
 
 pub struct MessageParser<Db> {
     builder: Option<message::MessageBuilder>,
+    // decrypted bytes of the chunks of the message being built
+    buffer: Vec<u8>,
     error: bool,
+    // the time of the data being processed, nanoseconds since epoch
+    timestamp: u64,
     db: Arc<Db>,
 }
 
@@ -21,17 +26,24 @@ where
     pub fn new(db: Arc<Db>) -> Self {
         MessageParser {
             builder: None,
+            buffer: Vec::new(),
             error: false,
+            timestamp: 0,
             db,
         }
     }
+
+    /// The chunks and the messages produced from now on get this time
+    pub fn set_timestamp(&mut self, timestamp: u64) {
+        self.timestamp = timestamp;
+    }
 }
 
 impl<Db> ChunkHandler for MessageParser<Db>
 where
     Db: Database,
 {
-    fn handle_chunk(&mut self, chunk: chunk::Item, cn: &mut connection::Item) {
+    fn handle_chunk(&mut self, mut chunk: chunk::Item, cn: &mut connection::Item) {
         use std::convert::TryFrom;
         use self::message::MessageBuilder;
         use super::common::MessageKind;
@@ -48,10 +60,20 @@ where
                 }
             },
         };
+        chunk.set_timestamp(self.timestamp);
 
         if self.error || too_small {
+            // the chunk is too short to start the p2p message, maybe the handshake is repeated
+            if !self.error && chunk.counter >= 3 && self.builder.is_none() {
+                conformance::check_stray_chunk(
+                    cn.add_comment(),
+                    chunk.sender.incoming(),
+                    &chunk.plain,
+                );
+            }
             self.error = true;
             if !chunk.bytes.is_empty() {
+                cn.count_chunk(&chunk.sender);
                 self.db.store_chunk(chunk);
             }
             return;
@@ -59,11 +81,32 @@ where
 
         let sender = &chunk.sender;
 
+        // the message is built from several chunks, its decrypted bytes are in the buffer
+        let mut buffered = false;
         let message = match chunk.counter {
-            0 => Some(MessageBuilder::connection_message().build(&sender, &cn)),
-            1 => Some(MessageBuilder::metadata_message().build(&sender, &cn)),
-            2 => Some(MessageBuilder::acknowledge_message().build(&sender, &cn)),
+            0 => Some(MessageBuilder::connection_message().build(
+                &sender,
+                &cn,
+                &chunk.plain,
+                self.timestamp,
+            )),
+            1 => Some(MessageBuilder::metadata_message().build(
+                &sender,
+                &cn,
+                &chunk.plain,
+                self.timestamp,
+            )),
+            2 => Some(MessageBuilder::acknowledge_message().build(
+                &sender,
+                &cn,
+                &chunk.plain,
+                self.timestamp,
+            )),
             c => {
+                if self.builder.is_none() {
+                    self.buffer.clear();
+                }
+                self.buffer.extend_from_slice(&chunk.plain);
                 let building_result = self
                     .builder
                     .take()
@@ -92,7 +135,12 @@ where
                     })
                     .link_chunk(chunk.plain.len());
                 match building_result {
-                    Ok(builder_full) => Some(builder_full.build(&sender, &cn)),
+                    Ok(builder_full) => {
+                        let message =
+                            builder_full.build(&sender, &cn, &self.buffer, self.timestamp);
+                        buffered = true;
+                        Some(message)
+                    },
                     Err(builder) => {
                         self.builder = builder;
                         None
@@ -101,8 +149,16 @@ where
             },
         };
 
+        if let Some(message) = &message {
+            let plain = if buffered { &self.buffer } else { &chunk.plain };
+            let (comments, state) = cn.conformance();
+            conformance::check_message(comments, state, message, Some(plain));
+        }
+
+        cn.count_chunk(&chunk.sender);
         self.db.store_chunk(chunk);
         if let Some(message) = message {
+            cn.set_last_message(&message.ty, &message.sender);
             self.db.store_message(message);
         }
     }
@@ -1,10 +1,10 @@
 // Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use super::{system::Identity, database::Database, tables, common};
+use super::{system::Identity, database::Database, tables, common, keylog};
 
 mod chunk_parser;
 mod message_parser;
 mod connection;
 
-pub use self::connection::Connection;
+pub use self::{connection::Connection, chunk_parser::SessionKeys};
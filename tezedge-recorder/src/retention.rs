@@ -0,0 +1,39 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread, io,
+    time::{Duration, Instant},
+};
+use super::database::{DatabaseRetention, RetentionPolicy};
+
+/// Spawn the thread which periodically removes the data
+/// that does not fit the retention policy
+pub fn spawn<Db>(
+    policy: RetentionPolicy,
+    db: Arc<Db>,
+    running: Arc<AtomicBool>,
+) -> io::Result<thread::JoinHandle<()>>
+where
+    Db: DatabaseRetention + Sync + Send + 'static,
+{
+    const PERIOD: Duration = Duration::from_secs(60);
+    const TICK: Duration = Duration::from_secs(1);
+
+    thread::Builder::new()
+        .name("retention".to_string())
+        .spawn(move || {
+            let mut last = None::<Instant>;
+            while running.load(Ordering::Relaxed) {
+                if last.map(|t| t.elapsed() >= PERIOD).unwrap_or(true) {
+                    db.apply_retention(&policy);
+                    last = Some(Instant::now());
+                }
+                thread::sleep(TICK);
+            }
+        })
+}
@@ -1,58 +1,197 @@
 // Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::{sync::Arc, collections::HashMap};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
 use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use tokio::{sync::Semaphore, task, time};
 use warp::{
     Filter, Rejection, Reply,
     reply::{WithStatus, Json, self},
     http::StatusCode,
 };
 use super::{
-    database::{DatabaseFetch, ConnectionsFilter, ChunksFilter, MessagesFilter, LogsFilter},
-    tables::chunk,
+    database::{
+        DatabaseFetch, ConnectionsFilter, PeersFilter, OperationsFilter, BlocksFilter,
+        RequestsFilter, LatencyFilter, DiscoveryFilter, ChunksFilter, MessagesFilter, LogsFilter,
+        ExportFilter, ConnectionDetails,
+    },
+    tables::{connection, chunk},
+    export::{self, anonymize::Anonymizer},
 };
 
+/// Runs database queries on the blocking thread pool of the runtime,
+/// so slow query does not stall the runtime, limits the number of queries running at once,
+/// and the time the client waits for the result
+#[derive(Clone)]
+pub struct QueryPool {
+    permits: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+impl QueryPool {
+    pub fn new(max_queries: usize, timeout: Duration) -> Self {
+        QueryPool {
+            permits: Arc::new(Semaphore::new(max_queries)),
+            timeout,
+        }
+    }
+
+    /// Reply with the result of the query as json, or with the status telling what went wrong
+    pub async fn run<F, T, E>(&self, query: F) -> WithStatus<Json>
+    where
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+        T: Serialize + Send + 'static,
+        E: fmt::Display + Send + 'static,
+    {
+        match self.execute(query).await {
+            Ok(v) => reply::with_status(reply::json(&v), StatusCode::OK),
+            Err(r) => r,
+        }
+    }
+
+    /// If the request is timed out or the client is gone while the query is waiting
+    /// for the free slot, the query will not run. The query which is already running
+    /// cannot be interrupted, but it keeps its slot until it is done.
+    async fn execute<F, T, E>(&self, query: F) -> Result<T, WithStatus<Json>>
+    where
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: fmt::Display + Send + 'static,
+    {
+        struct Cancel(Arc<AtomicBool>);
+
+        impl Drop for Cancel {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::Relaxed);
+            }
+        }
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let _cancel = Cancel(cancelled.clone());
+        let permits = self.permits.clone();
+        let task = async move {
+            let permit = match permits.acquire_owned().await {
+                Ok(permit) => permit,
+                // the semaphore is closed, the query will not run
+                Err(_) => return Ok(None),
+            };
+            task::spawn_blocking(move || {
+                let _permit = permit;
+                if cancelled.load(Ordering::Relaxed) {
+                    None
+                } else {
+                    Some(query())
+                }
+            })
+            .await
+        };
+
+        match time::timeout(self.timeout, task).await {
+            Ok(Ok(Some(Ok(v)))) => Ok(v),
+            Ok(Ok(Some(Err(err)))) => {
+                let r = &format!("database error: {}", err);
+                Err(reply::with_status(
+                    reply::json(&r),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ))
+            },
+            Ok(Ok(None)) => {
+                let r = &"query cancelled";
+                Err(reply::with_status(
+                    reply::json(&r),
+                    StatusCode::SERVICE_UNAVAILABLE,
+                ))
+            },
+            Ok(Err(err)) => {
+                let r = &format!("query failed: {}", err);
+                Err(reply::with_status(
+                    reply::json(&r),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ))
+            },
+            Err(_) => {
+                let r = &format!("query timed out after {:?}", self.timeout);
+                Err(reply::with_status(
+                    reply::json(&r),
+                    StatusCode::GATEWAY_TIMEOUT,
+                ))
+            },
+        }
+    }
+}
+
 fn connections<Db>(
     db: Arc<Db>,
+    pool: QueryPool,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
 where
     Db: DatabaseFetch + Sync + Send + 'static,
 {
     warp::path!("v3" / "connections")
         .and(warp::query::query())
-        .map(move |filter: ConnectionsFilter| -> WithStatus<Json> {
-            match db.fetch_connections(&filter) {
-                Ok(connections) => reply::with_status(reply::json(&connections), StatusCode::OK),
-                Err(err) => {
-                    let r = &format!("database error: {}", err);
-                    reply::with_status(reply::json(&r), StatusCode::INTERNAL_SERVER_ERROR)
-                },
+        .and_then(move |filter: ConnectionsFilter| {
+            let (db, pool) = (db.clone(), pool.clone());
+            async move {
+                let r = pool.run(move || db.fetch_connections(&filter)).await;
+                Ok::<_, Rejection>(r)
             }
         })
 }
 
+fn connection<Db>(
+    db: Arc<Db>,
+    pool: QueryPool,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
+where
+    Db: DatabaseFetch + Sync + Send + 'static,
+{
+    fn inner<Db>(db: &Arc<Db>, cn_id: String) -> Result<Option<ConnectionDetails>>
+    where
+        Db: DatabaseFetch + Sync + Send + 'static,
+    {
+        let key = cn_id.parse::<connection::Key>()?;
+        db.fetch_connection(&key).map_err(Into::into)
+    }
+
+    warp::path!("v3" / "connection" / String).and_then(move |cn_id: String| {
+        let (db, pool) = (db.clone(), pool.clone());
+        async move {
+            let r = pool.run(move || inner(&db, cn_id)).await;
+            Ok::<_, Rejection>(r)
+        }
+    })
+}
+
 fn chunks<Db>(
     db: Arc<Db>,
+    pool: QueryPool,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
 where
     Db: DatabaseFetch + Sync + Send + 'static,
 {
-    warp::path!("v3" / "chunks").and(warp::query::query()).map(
-        move |filter: ChunksFilter| -> WithStatus<Json> {
-            match db.fetch_chunks_truncated(&filter) {
-                Ok(chunks) => reply::with_status(reply::json(&chunks), StatusCode::OK),
-                Err(err) => {
-                    let r = &format!("database error: {}", err);
-                    reply::with_status(reply::json(&r), StatusCode::INTERNAL_SERVER_ERROR)
-                },
+    warp::path!("v3" / "chunks")
+        .and(warp::query::query())
+        .and_then(move |filter: ChunksFilter| {
+            let (db, pool) = (db.clone(), pool.clone());
+            async move {
+                let r = pool.run(move || db.fetch_chunks_truncated(&filter)).await;
+                Ok::<_, Rejection>(r)
             }
-        },
-    )
+        })
 }
 
 fn chunk<Db>(
     db: Arc<Db>,
+    pool: QueryPool,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
 where
     Db: DatabaseFetch + Sync + Send + 'static,
@@ -65,70 +204,266 @@ where
         db.fetch_chunk(&key).map_err(Into::into)
     }
 
-    warp::path!("v3" / "chunk" / String).map(move |chunk_id: String| -> WithStatus<Json> {
-        match inner(&db, chunk_id) {
-            Ok(v) => reply::with_status(reply::json(&v), StatusCode::OK),
-            Err(err) => {
-                let r = format!("database error: {}", err);
-                reply::with_status(reply::json(&r), StatusCode::INTERNAL_SERVER_ERROR)
-            },
+    warp::path!("v3" / "chunk" / String).and_then(move |chunk_id: String| {
+        let (db, pool) = (db.clone(), pool.clone());
+        async move {
+            let r = pool.run(move || inner(&db, chunk_id)).await;
+            Ok::<_, Rejection>(r)
+        }
+    })
+}
+
+fn peers<Db>(
+    db: Arc<Db>,
+    pool: QueryPool,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
+where
+    Db: DatabaseFetch + Sync + Send + 'static,
+{
+    warp::path!("v3" / "peers")
+        .and(warp::query::query())
+        .and_then(move |filter: PeersFilter| {
+            let (db, pool) = (db.clone(), pool.clone());
+            async move {
+                let r = pool.run(move || db.fetch_peers(&filter)).await;
+                Ok::<_, Rejection>(r)
+            }
+        })
+}
+
+fn operations<Db>(
+    db: Arc<Db>,
+    pool: QueryPool,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
+where
+    Db: DatabaseFetch + Sync + Send + 'static,
+{
+    warp::path!("v3" / "operations")
+        .and(warp::query::query())
+        .and_then(move |filter: OperationsFilter| {
+            let (db, pool) = (db.clone(), pool.clone());
+            async move {
+                let r = pool.run(move || db.fetch_operations(&filter)).await;
+                Ok::<_, Rejection>(r)
+            }
+        })
+}
+
+fn blocks<Db>(
+    db: Arc<Db>,
+    pool: QueryPool,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
+where
+    Db: DatabaseFetch + Sync + Send + 'static,
+{
+    warp::path!("v3" / "blocks")
+        .and(warp::query::query())
+        .and_then(move |filter: BlocksFilter| {
+            let (db, pool) = (db.clone(), pool.clone());
+            async move {
+                let r = pool.run(move || db.fetch_blocks_summary(&filter)).await;
+                Ok::<_, Rejection>(r)
+            }
+        })
+}
+
+fn block<Db>(
+    db: Arc<Db>,
+    pool: QueryPool,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
+where
+    Db: DatabaseFetch + Sync + Send + 'static,
+{
+    warp::path!("v3" / "blocks" / u32).and_then(move |level: u32| {
+        let (db, pool) = (db.clone(), pool.clone());
+        async move {
+            let r = pool.run(move || db.fetch_block_report(level)).await;
+            Ok::<_, Rejection>(r)
         }
     })
 }
 
+fn requests<Db>(
+    db: Arc<Db>,
+    pool: QueryPool,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
+where
+    Db: DatabaseFetch + Sync + Send + 'static,
+{
+    warp::path!("v3" / "requests")
+        .and(warp::query::query())
+        .and_then(move |filter: RequestsFilter| {
+            let (db, pool) = (db.clone(), pool.clone());
+            async move {
+                let r = pool.run(move || db.fetch_requests(&filter)).await;
+                Ok::<_, Rejection>(r)
+            }
+        })
+}
+
+fn latency<Db>(
+    db: Arc<Db>,
+    pool: QueryPool,
+) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
+where
+    Db: DatabaseFetch + Sync + Send + 'static,
+{
+    warp::path!("v3" / "latency")
+        .and(warp::query::query())
+        .and_then(move |filter: LatencyFilter| {
+            let (db, pool) = (db.clone(), pool.clone());
+            async move {
+                let r = pool.run(move || db.fetch_latency(&filter)).await;
+                Ok::<_, Rejection>(r)
+            }
+        })
+}
+
 fn messages<Db>(
     db: Arc<Db>,
+    pool: QueryPool,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
 where
     Db: DatabaseFetch + Sync + Send + 'static,
 {
     warp::path!("v3" / "messages")
         .and(warp::query::query())
-        .map(move |filter: MessagesFilter| -> reply::WithStatus<Json> {
-            match db.fetch_messages(&filter) {
-                Ok(messages) => reply::with_status(reply::json(&messages), StatusCode::OK),
-                Err(err) => {
-                    let r = &format!("database error: {}", err);
-                    reply::with_status(reply::json(&r), StatusCode::INTERNAL_SERVER_ERROR)
-                },
+        .and_then(move |filter: MessagesFilter| {
+            let (db, pool) = (db.clone(), pool.clone());
+            async move {
+                let r = pool.run(move || db.fetch_messages(&filter)).await;
+                Ok::<_, Rejection>(r)
             }
         })
 }
 
 fn message<Db>(
     db: Arc<Db>,
+    pool: QueryPool,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
 where
     Db: DatabaseFetch + Sync + Send + 'static,
 {
-    warp::path!("v3" / "message" / u64).map(move |id: u64| -> reply::WithStatus<Json> {
-        match db.fetch_message(id) {
-            Ok(message) => reply::with_status(reply::json(&message), StatusCode::OK),
-            Err(err) => {
-                let r = &format!("database error: {}", err);
-                reply::with_status(reply::json(&r), StatusCode::INTERNAL_SERVER_ERROR)
-            },
+    warp::path!("v3" / "message" / u64).and_then(move |id: u64| {
+        let (db, pool) = (db.clone(), pool.clone());
+        async move {
+            let r = pool.run(move || db.fetch_message(id)).await;
+            Ok::<_, Rejection>(r)
         }
     })
 }
 
 fn logs<Db>(
     db: Arc<Db>,
+    pool: QueryPool,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
 where
     Db: DatabaseFetch + Sync + Send + 'static,
 {
-    warp::path!("v3" / "logs").and(warp::query::query()).map(
-        move |filter: LogsFilter| -> reply::WithStatus<Json> {
-            match db.fetch_log(&filter) {
-                Ok(v) => reply::with_status(reply::json(&v), StatusCode::OK),
-                Err(err) => {
-                    let r = &format!("database error: {}", err);
-                    reply::with_status(reply::json(&r), StatusCode::INTERNAL_SERVER_ERROR)
-                },
+    warp::path!("v3" / "logs")
+        .and(warp::query::query())
+        .and_then(move |filter: LogsFilter| {
+            let (db, pool) = (db.clone(), pool.clone());
+            async move {
+                let r = pool.run(move || db.fetch_log(&filter)).await;
+                Ok::<_, Rejection>(r)
             }
-        },
-    )
+        })
+}
+
+#[derive(Deserialize)]
+struct ExportOptions {
+    /// replace the addresses and the public keys of the peers by pseudonyms
+    #[serde(default)]
+    anonymize: bool,
+}
+
+fn export_pcapng<Db>(
+    db: Arc<Db>,
+    pool: QueryPool,
+    p2p_port: u16,
+) -> impl Filter<Extract = (reply::Response,), Error = Rejection> + Clone + Sync + Send + 'static
+where
+    Db: DatabaseFetch + Sync + Send + 'static,
+{
+    fn inner<Db>(
+        db: &Arc<Db>,
+        filter: ExportFilter,
+        options: ExportOptions,
+        p2p_port: u16,
+    ) -> Result<Vec<u8>>
+    where
+        Db: DatabaseFetch + Sync + Send + 'static,
+    {
+        let mut connections = db.fetch_connection_chunks(&filter)?;
+        if options.anonymize {
+            let mut anonymizer = Anonymizer::random();
+            connections
+                .iter_mut()
+                .for_each(|c| anonymizer.connection_chunks(c));
+        }
+        let mut output = Vec::new();
+        export::pcapng::write(&mut output, &connections, p2p_port)?;
+        Ok(output)
+    }
+
+    warp::path!("v3" / "export" / "pcapng")
+        .and(warp::query::query())
+        .and(warp::query::query())
+        .and_then(move |filter: ExportFilter, options: ExportOptions| {
+            let (db, pool) = (db.clone(), pool.clone());
+            async move {
+                let r = match pool
+                    .execute(move || inner(&db, filter, options, p2p_port))
+                    .await
+                {
+                    Ok(data) => {
+                        let r = reply::with_header(data, "Content-Type", "application/x-pcapng");
+                        let r = reply::with_header(
+                            r,
+                            "Content-Disposition",
+                            "attachment; filename=\"recorder.pcapng\"",
+                        );
+                        r.into_response()
+                    },
+                    Err(r) => r.into_response(),
+                };
+                Ok::<_, Rejection>(r)
+            }
+        })
+}
+
+#[derive(Deserialize)]
+struct GraphOptions {
+    /// `json`, default, or `dot`
+    format: Option<String>,
+}
+
+fn discovery<Db>(
+    db: Arc<Db>,
+    pool: QueryPool,
+) -> impl Filter<Extract = (reply::Response,), Error = Rejection> + Clone + Sync + Send + 'static
+where
+    Db: DatabaseFetch + Sync + Send + 'static,
+{
+    warp::path!("v3" / "discovery")
+        .and(warp::query::query())
+        .and(warp::query::query())
+        .and_then(move |filter: DiscoveryFilter, options: GraphOptions| {
+            let (db, pool) = (db.clone(), pool.clone());
+            async move {
+                let graph = pool.execute(move || db.fetch_discovery(&filter)).await;
+                let r = match (graph, options.format.as_deref()) {
+                    (Ok(graph), Some("dot")) => {
+                        reply::with_header(graph.dot(), "Content-Type", "text/vnd.graphviz")
+                            .into_response()
+                    },
+                    (Ok(graph), _) => reply::json(&graph).into_response(),
+                    (Err(r), _) => r.into_response(),
+                };
+                Ok::<_, Rejection>(r)
+            }
+        })
 }
 
 pub fn version(
@@ -151,110 +486,119 @@ pub fn openapi(
         })
 }
 
+/// The `p2p_port` is the port of the node, the local side of the exported connections
 pub fn routes<Db>(
     db: Arc<Db>,
+    pool: QueryPool,
+    p2p_port: u16,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone + Sync + Send + 'static
 where
     Db: DatabaseFetch + Sync + Send + 'static,
 {
     use warp::reply::with;
 
+    let json = connections(db.clone(), pool.clone())
+        .or(connection(db.clone(), pool.clone()))
+        .or(peers(db.clone(), pool.clone()))
+        .or(operations(db.clone(), pool.clone()))
+        .or(blocks(db.clone(), pool.clone()))
+        .or(block(db.clone(), pool.clone()))
+        .or(requests(db.clone(), pool.clone()))
+        .or(latency(db.clone(), pool.clone()))
+        .or(chunks(db.clone(), pool.clone()))
+        .or(chunk(db.clone(), pool.clone()))
+        .or(messages(db.clone(), pool.clone()))
+        .or(message(db.clone(), pool.clone()))
+        .or(logs(db.clone(), pool.clone()))
+        .or(version().or(openapi()))
+        .with(with::header("Content-Type", "application/json"));
+
     warp::get()
         .and(
-            connections(db.clone())
-                .or(chunks(db.clone()))
-                .or(chunk(db.clone()))
-                .or(messages(db.clone()))
-                .or(message(db.clone()))
-                .or(logs(db))
-                .or(version().or(openapi())),
+            json.or(discovery(db.clone(), pool.clone()))
+                .or(export_pcapng(db, pool, p2p_port)),
         )
-        .with(with::header("Content-Type", "application/json"))
         .with(with::header("Access-Control-Allow-Origin", "*"))
 }
 
+/// Find the database of the node, the `node_name` is `tezedge` by default
+fn node_db<Db>(
+    dbs: &HashMap<String, Arc<Db>>,
+    node_name: &Option<String>,
+) -> Result<Arc<Db>, WithStatus<Json>> {
+    let node_name = node_name.clone().unwrap_or("tezedge".to_string());
+    dbs.get(&node_name).cloned().ok_or_else(|| {
+        let r = &format!("no such node: {:?}", node_name);
+        reply::with_status(reply::json(&r), StatusCode::NOT_FOUND)
+    })
+}
+
 fn p2p<Db>(
     dbs: HashMap<String, Arc<Db>>,
+    pool: QueryPool,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
 where
     Db: DatabaseFetch + Sync + Send + 'static,
 {
-    warp::path!("v2" / "p2p").and(warp::query::query()).map(
-        move |filter: MessagesFilter| -> reply::WithStatus<Json> {
-            let node_name = filter.node_name.clone().unwrap_or("tezedge".to_string());
-            match dbs.get(&node_name) {
-                Some(db) => match db.fetch_messages(&filter) {
-                    Ok(messages) => reply::with_status(reply::json(&messages), StatusCode::OK),
-                    Err(err) => {
-                        let r = &format!("database error: {}", err);
-                        reply::with_status(reply::json(&r), StatusCode::INTERNAL_SERVER_ERROR)
-                    },
-                },
-                None => {
-                    let r = &format!("no such node: {:?}", node_name);
-                    reply::with_status(reply::json(&r), StatusCode::NOT_FOUND)
-                },
+    warp::path!("v2" / "p2p")
+        .and(warp::query::query())
+        .and_then(move |filter: MessagesFilter| {
+            let (db, pool) = (node_db(&dbs, &filter.node_name), pool.clone());
+            async move {
+                let r = match db {
+                    Ok(db) => pool.run(move || db.fetch_messages(&filter)).await,
+                    Err(r) => r,
+                };
+                Ok::<_, Rejection>(r)
             }
-        },
-    )
+        })
 }
 
 fn p2p_details<Db>(
     dbs: HashMap<String, Arc<Db>>,
+    pool: QueryPool,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
 where
     Db: DatabaseFetch + Sync + Send + 'static,
 {
     warp::path!("v2" / "p2p" / u64)
         .and(warp::query::query())
-        .map(
-            move |id: u64, filter: MessagesFilter| -> reply::WithStatus<Json> {
-                let node_name = filter.node_name.clone().unwrap_or("tezedge".to_string());
-                match dbs.get(&node_name) {
-                    Some(db) => match db.fetch_message(id) {
-                        Ok(message) => reply::with_status(reply::json(&message), StatusCode::OK),
-                        Err(err) => {
-                            let r = &format!("database error: {}", err);
-                            reply::with_status(reply::json(&r), StatusCode::INTERNAL_SERVER_ERROR)
-                        },
-                    },
-                    None => {
-                        let r = &format!("no such node: {:?}", node_name);
-                        reply::with_status(reply::json(&r), StatusCode::NOT_FOUND)
-                    },
-                }
-            },
-        )
+        .and_then(move |id: u64, filter: MessagesFilter| {
+            let (db, pool) = (node_db(&dbs, &filter.node_name), pool.clone());
+            async move {
+                let r = match db {
+                    Ok(db) => pool.run(move || db.fetch_message(id)).await,
+                    Err(r) => r,
+                };
+                Ok::<_, Rejection>(r)
+            }
+        })
 }
 
 fn log_old<Db>(
     dbs: HashMap<String, Arc<Db>>,
+    pool: QueryPool,
 ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone + Sync + Send + 'static
 where
     Db: DatabaseFetch + Sync + Send + 'static,
 {
-    warp::path!("v2" / "log").and(warp::query::query()).map(
-        move |filter: LogsFilter| -> reply::WithStatus<Json> {
-            let node_name = filter.node_name.clone().unwrap_or("tezedge".to_string());
-            match dbs.get(&node_name) {
-                Some(db) => match db.fetch_log(&filter) {
-                    Ok(v) => reply::with_status(reply::json(&v), StatusCode::OK),
-                    Err(err) => {
-                        let r = &format!("database error: {}", err);
-                        reply::with_status(reply::json(&r), StatusCode::INTERNAL_SERVER_ERROR)
-                    },
-                },
-                None => {
-                    let r = &format!("no such node: {:?}", node_name);
-                    reply::with_status(reply::json(&r), StatusCode::NOT_FOUND)
-                },
+    warp::path!("v2" / "log")
+        .and(warp::query::query())
+        .and_then(move |filter: LogsFilter| {
+            let (db, pool) = (node_db(&dbs, &filter.node_name), pool.clone());
+            async move {
+                let r = match db {
+                    Ok(db) => pool.run(move || db.fetch_log(&filter)).await,
+                    Err(r) => r,
+                };
+                Ok::<_, Rejection>(r)
             }
-        },
-    )
+        })
 }
 
 pub fn routes_old<Db>(
     dbs: HashMap<String, Arc<Db>>,
+    pool: QueryPool,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone + Sync + Send + 'static
 where
     Db: DatabaseFetch + Sync + Send + 'static,
@@ -263,9 +607,9 @@ where
 
     warp::get()
         .and(
-            p2p(dbs.clone())
-                .or(p2p_details(dbs.clone()))
-                .or(log_old(dbs))
+            p2p(dbs.clone(), pool.clone())
+                .or(p2p_details(dbs.clone(), pool.clone()))
+                .or(log_old(dbs, pool))
                 .or(version())
                 .or(openapi()),
         )
@@ -6,14 +6,18 @@ use std::{
     sync::{Arc, atomic::AtomicBool},
     net::SocketAddr,
     io, thread,
+    time::Duration,
 };
 use serde::Deserialize;
 use anyhow::Result;
 use thiserror::Error;
 use tokio::{runtime::Runtime, task::JoinHandle};
 use super::{
-    database::{DatabaseNew, DatabaseFetch, Database},
-    server, log_client,
+    database::{DatabaseNew, DatabaseFetch, DatabaseRetention, Database, RetentionPolicy},
+    server::{self, QueryPool},
+    log_client, retention,
+    keylog::KeyLog,
+    tables::chunk::ChunkPayload,
 };
 
 #[derive(Clone, Deserialize)]
@@ -21,6 +25,10 @@ pub struct P2pConfig {
     identity: String,
     pub port: u16,
     store_limit: Option<u64>,
+    store_hours: Option<u64>,
+    #[serde(default)]
+    chunk_payload: ChunkPayload,
+    keylog: Option<String>,
 }
 
 #[derive(Clone, Deserialize)]
@@ -28,6 +36,7 @@ struct LogConfig {
     port: u16,
     disable_search: Option<bool>,
     store_limit: Option<u64>,
+    store_hours: Option<u64>,
 }
 
 #[derive(Clone, Deserialize)]
@@ -35,6 +44,7 @@ pub struct NodeConfig {
     name: String,
     http_v3: Option<u16>,
     db: String,
+    db_size_limit_gib: Option<u64>,
     p2p: Option<P2pConfig>,
     log: Option<LogConfig>,
 }
@@ -42,6 +52,9 @@ pub struct NodeConfig {
 #[derive(Clone, Deserialize)]
 struct Config {
     http_v2: Option<u16>,
+    http_max_queries: Option<usize>,
+    http_timeout_secs: Option<u64>,
+    capture_file: Option<String>,
     nodes: Vec<NodeConfig>,
 }
 
@@ -54,6 +67,7 @@ pub struct Identity {
 pub struct NodeInfo {
     identity: Identity,
     name: String,
+    keylog: Option<Arc<KeyLog>>,
 }
 
 #[derive(Error, Debug)]
@@ -66,11 +80,14 @@ pub enum NodeError {
     ParsePk,
     #[error("failed to parse secret key from hex")]
     ParseSk,
+    #[error("failed to open keylog {}", _0)]
+    OpenKeyLog(io::Error),
 }
 
 struct NodeServer {
     _server: Option<JoinHandle<()>>,
     log_client: Option<thread::JoinHandle<()>>,
+    retention: thread::JoinHandle<()>,
 }
 
 pub struct System<Db> {
@@ -80,48 +97,75 @@ pub struct System<Db> {
     node_servers: HashMap<String, NodeServer>,
     node_dbs: HashMap<String, Arc<Db>>,
     _old_server: Option<JoinHandle<()>>,
+    query_pool: QueryPool,
     tokio_rt: Runtime,
 }
 
 impl NodeServer {
     pub fn open_spawn<Db>(
-        db_path: &str,
-        rpc_port: Option<u16>,
-        log_config: &Option<LogConfig>,
-        p2p_config: &Option<P2pConfig>,
+        config: &NodeConfig,
         rt: &Runtime,
+        pool: QueryPool,
         running: Arc<AtomicBool>,
     ) -> Result<(Self, Arc<Db>)>
     where
-        Db: DatabaseNew + Database + DatabaseFetch + Sync + Send + 'static,
+        Db: DatabaseNew + Database + DatabaseFetch + DatabaseRetention + Sync + Send + 'static,
     {
+        let log_config = &config.log;
+        let p2p_config = &config.p2p;
         let log_search = !log_config
             .as_ref()
             .and_then(|c| c.disable_search)
             .unwrap_or(false);
-        let log_store_limit = log_config
-            .as_ref()
-            .and_then(|c| c.store_limit);
-        let message_store_limit = p2p_config
-            .as_ref()
-            .and_then(|c| c.store_limit);
-        let db = Arc::new(Db::open(db_path, log_search, log_store_limit, message_store_limit)?);
-        let server = if let Some(port) = rpc_port {
+        let log_store_limit = log_config.as_ref().and_then(|c| c.store_limit);
+        let message_store_limit = p2p_config.as_ref().and_then(|c| c.store_limit);
+        let db = Arc::new(Db::open(
+            &config.db,
+            log_search,
+            log_store_limit,
+            message_store_limit,
+            p2p_config
+                .as_ref()
+                .map(|c| c.chunk_payload)
+                .unwrap_or_default(),
+        )?);
+        let policy = RetentionPolicy {
+            message_max_age: p2p_config
+                .as_ref()
+                .and_then(|c| c.store_hours)
+                .map(|h| h * 3600),
+            log_max_age: log_config
+                .as_ref()
+                .and_then(|c| c.store_hours)
+                .map(|h| h * 3600),
+            max_size: config.db_size_limit_gib.map(|s| s << 30),
+        };
+        let server = if let Some(port) = config.http_v3 {
             let addr = ([0, 0, 0, 0], port);
-            Some(rt.spawn(warp::serve(server::routes(db.clone())).run(addr)))
+            // without p2p config there are no connections to export, the port is irrelevant
+            let p2p_port = p2p_config.as_ref().map(|c| c.port).unwrap_or(0);
+            let routes = server::routes(db.clone(), pool, p2p_port);
+            Some(rt.spawn(warp::serve(routes).run(addr)))
         } else {
             None
         };
         let log_client = if let Some(log_config) = log_config {
-            Some(log_client::spawn(log_config.port, db.clone(), running)?)
+            Some(log_client::spawn(
+                log_config.port,
+                db.clone(),
+                running.clone(),
+            )?)
         } else {
             None
         };
+        // run it even without policy, it also collects orphaned connections and chunks
+        let retention = retention::spawn(policy, db.clone(), running)?;
 
         Ok((
             NodeServer {
                 _server: server,
                 log_client,
+                retention,
             },
             db,
         ))
@@ -131,11 +175,12 @@ impl NodeServer {
         if let Some(log_client) = self.log_client {
             log_client.join().unwrap()
         }
+        self.retention.join().unwrap()
     }
 }
 
 impl NodeInfo {
-    pub fn new(identity_path: &str, name: String) -> Result<Self, NodeError> {
+    pub fn new(p2p: &P2pConfig, name: String) -> Result<Self, NodeError> {
         use std::{fs::File, convert::TryInto};
 
         #[derive(Deserialize)]
@@ -148,7 +193,7 @@ impl NodeInfo {
             proof_of_work_stamp: String,
         }
 
-        let file = File::open(identity_path).map_err(NodeError::OpenIdentity)?;
+        let file = File::open(&p2p.identity).map_err(NodeError::OpenIdentity)?;
         let Inner {
             public_key,
             secret_key,
@@ -170,15 +215,31 @@ impl NodeInfo {
             },
         };
 
-        Ok(NodeInfo { identity, name })
+        let keylog = match &p2p.keylog {
+            Some(path) => Some(Arc::new(KeyLog::open(path).map_err(NodeError::OpenKeyLog)?)),
+            None => None,
+        };
+
+        Ok(NodeInfo {
+            identity,
+            name,
+            keylog,
+        })
     }
 
     pub fn identity(&self) -> Identity {
         self.identity.clone()
     }
+
+    pub fn keylog(&self) -> Option<Arc<KeyLog>> {
+        self.keylog.clone()
+    }
 }
 
 impl<Db> System<Db> {
+    const MAX_QUERIES: usize = 8;
+    const QUERY_TIMEOUT_SECS: u64 = 30;
+
     pub fn load_config() -> Result<Self> {
         use std::{fs::File, io::Read};
 
@@ -187,7 +248,17 @@ impl<Db> System<Db> {
             .or_else(|_| File::open("/home/appuser/config.toml"))?;
         let mut settings_toml = String::new();
         settings_file.read_to_string(&mut settings_toml)?;
-        let config = toml::from_str(&settings_toml)?;
+        Self::from_toml(&settings_toml)
+    }
+
+    /// Create the system from the content of the config file,
+    /// useful to run the recorder with custom capture source
+    pub fn from_toml(settings_toml: &str) -> Result<Self> {
+        let config: Config = toml::from_str(settings_toml)?;
+        let query_pool = QueryPool::new(
+            config.http_max_queries.unwrap_or(Self::MAX_QUERIES),
+            Duration::from_secs(config.http_timeout_secs.unwrap_or(Self::QUERY_TIMEOUT_SECS)),
+        );
 
         Ok(System {
             config,
@@ -196,6 +267,7 @@ impl<Db> System<Db> {
             node_servers: HashMap::new(),
             node_dbs: HashMap::new(),
             _old_server: None,
+            query_pool,
             tokio_rt: Runtime::new().unwrap(),
         })
     }
@@ -204,6 +276,11 @@ impl<Db> System<Db> {
         "/tmp/bpf-sniffer.sock"
     }
 
+    /// The file where raw intercepted events should be written
+    pub fn capture_file(&self) -> Option<&str> {
+        self.config.capture_file.as_deref()
+    }
+
     pub fn p2p_configs(&self) -> impl Iterator<Item = &P2pConfig> {
         self.config.nodes.iter().filter_map(|c| c.p2p.as_ref())
     }
@@ -256,13 +333,14 @@ impl<Db> System<Db> {
 
 impl<Db> System<Db>
 where
-    Db: DatabaseNew + Database + DatabaseFetch + Sync + Send + 'static,
+    Db: DatabaseNew + Database + DatabaseFetch + DatabaseRetention + Sync + Send + 'static,
 {
     pub fn run_dbs(&mut self, running: Arc<AtomicBool>) {
         for c in &self.config.nodes {
             let r = running.clone();
             let rt = &self.tokio_rt;
-            match NodeServer::open_spawn(&c.db, c.http_v3, &c.log, &c.p2p, rt, r) {
+            let pool = self.query_pool.clone();
+            match NodeServer::open_spawn(c, rt, pool, r) {
                 Ok((server, db)) => {
                     self.node_servers.insert(c.name.clone(), server);
                     self.node_dbs.insert(c.name.clone(), db);
@@ -275,7 +353,11 @@ where
 
         if let Some(port) = self.config.http_v2 {
             let addr = ([0, 0, 0, 0], port);
-            let s = warp::serve(server::routes_old(self.node_dbs.clone())).run(addr);
+            let s = warp::serve(server::routes_old(
+                self.node_dbs.clone(),
+                self.query_pool.clone(),
+            ))
+            .run(addr);
             self._old_server = Some(self.tokio_rt.spawn(s));
         }
     }
@@ -293,7 +375,7 @@ where
                 .find(|c| c.p2p.as_ref().unwrap().port == port)
                 .unwrap();
             let p2p = c.p2p.as_ref().unwrap();
-            NodeInfo::new(&p2p.identity, c.name.clone())?
+            NodeInfo::new(p2p, c.name.clone())?
         };
         log::info!("attaching to pid: {} at port: {}", pid, port);
         self.port_to_pid.insert(port, pid);
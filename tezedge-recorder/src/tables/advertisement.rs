@@ -0,0 +1,68 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::net::SocketAddr;
+use serde::{Serialize, Deserialize};
+use storage::persistent::{KeyValueSchema, BincodeEncoded, database::RocksDbKeyValueSchema};
+use super::{
+    common::{MessageType, MessageKind},
+    connection, message,
+};
+
+/// The addresses of the peers advertised by the `advertise`, `swap_request` or `swap_ack`,
+/// the key is the id of the message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Value {
+    cn_ts: u64,
+    cn_ts_nanos: u32,
+    pub kind: MessageKind,
+    /// the peer sent the message
+    pub incoming: bool,
+    /// milliseconds since epoch
+    pub timestamp: u64,
+    pub points: Vec<SocketAddr>,
+}
+
+impl Value {
+    /// `None` if the message advertises nothing
+    pub fn new(item: &message::Item) -> Option<Self> {
+        let kind = match &item.ty {
+            MessageType::P2p(kind) => kind.clone(),
+            _ => return None,
+        };
+        if item.refs.points.is_empty() {
+            return None;
+        }
+        let cn_id = item.cn_id();
+        Some(Value {
+            cn_ts: cn_id.ts,
+            cn_ts_nanos: cn_id.ts_nanos,
+            kind,
+            incoming: item.sender.incoming(),
+            timestamp: item.timestamp,
+            points: item.refs.points.clone(),
+        })
+    }
+
+    pub fn cn_id(&self) -> connection::Key {
+        connection::Key {
+            ts: self.cn_ts,
+            ts_nanos: self.cn_ts_nanos,
+        }
+    }
+}
+
+impl BincodeEncoded for Value {}
+
+pub struct Schema;
+
+impl KeyValueSchema for Schema {
+    type Key = u64;
+    type Value = Value;
+}
+
+impl RocksDbKeyValueSchema for Schema {
+    fn name() -> &'static str {
+        "advertisement_storage"
+    }
+}
@@ -4,7 +4,7 @@
 use std::{convert::TryFrom, fmt, str::FromStr, num::ParseIntError};
 use thiserror::Error;
 use serde::{
-    Serialize,
+    Serialize, Deserialize,
     ser::{self, SerializeStruct},
 };
 use rocksdb::{Cache, ColumnFamilyDescriptor};
@@ -25,6 +25,7 @@ pub struct Item {
 }
 
 impl Item {
+    /// The time is unknown until `set_timestamp`, the parser sets it before storing the chunk
     pub fn new(
         cn_id: connection::Key,
         sender: Sender,
@@ -32,19 +33,12 @@ impl Item {
         bytes: Vec<u8>,
         plain: Vec<u8>,
     ) -> Self {
-        use std::time::{SystemTime, UNIX_EPOCH};
-
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
         Item {
             cn_id,
             sender,
             counter,
             net: true,
-            timestamp,
+            timestamp: 0,
             bytes,
             plain,
         }
@@ -54,11 +48,31 @@ impl Item {
         self.net = net;
     }
 
+    /// The `timestamp` is nanoseconds since epoch, the chunk stores seconds
+    pub fn set_timestamp(&mut self, timestamp: u64) {
+        self.timestamp = timestamp / 1_000_000_000;
+    }
+
+    /// Drop the payload which should not be stored
+    pub fn retain(&mut self, payload: ChunkPayload) {
+        match payload {
+            ChunkPayload::Plain => self.bytes.clear(),
+            ChunkPayload::Cipher => self.plain.clear(),
+            ChunkPayload::Both => (),
+        }
+    }
+
     #[rustfmt::skip]
     pub fn split(self) -> (Key, Value) {
         let Item { cn_id, counter, sender, net, timestamp, bytes, plain } = self;
         (Key { cn_id, counter, sender }, Value { net, timestamp, bytes, plain })
     }
+
+    #[rustfmt::skip]
+    pub fn unite(key: Key, value: Value) -> Self {
+        let (Key { cn_id, counter, sender }, Value { net, timestamp, bytes, plain }) = (key, value);
+        Item { cn_id, counter, sender, net, timestamp, bytes, plain }
+    }
 }
 
 impl fmt::Debug for Item {
@@ -74,6 +88,23 @@ impl fmt::Debug for Item {
     }
 }
 
+/// Which payload of the chunk is stored
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkPayload {
+    /// decrypted bytes only
+    Plain,
+    /// bytes as they are on the wire only, messages cannot be decoded
+    Cipher,
+    Both,
+}
+
+impl Default for ChunkPayload {
+    fn default() -> Self {
+        ChunkPayload::Both
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Key {
     pub cn_id: connection::Key,
@@ -192,6 +223,12 @@ pub struct Value {
     pub plain: Vec<u8>,
 }
 
+impl Value {
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
 pub struct ValueTruncated(pub Value);
 
 impl Serialize for Value {
@@ -234,20 +271,12 @@ impl Serialize for ValueTruncated {
     }
 }
 
-impl Encoder for Value {
-    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
-        let mut v = Vec::with_capacity(self.bytes.len() + self.plain.len() + 17);
-        v.extend_from_slice(&self.timestamp.to_le_bytes());
-        v.extend_from_slice(&(self.bytes.len() as u64).to_le_bytes());
-        v.push(if self.net { 1 } else { 0 });
-        v.extend_from_slice(&self.bytes);
-        v.extend_from_slice(&self.plain);
-        Ok(v)
-    }
-}
+impl Value {
+    const COMPRESSION_LEVEL: i32 = 1;
 
-impl Decoder for Value {
-    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+    /// Decode the layout of version 1, uncompressed
+    /// * bytes layout: `[timestamp(8)][bytes_len(8)][net(1)][bytes][plain]`
+    pub fn decode_v1(bytes: &[u8]) -> Result<Self, SchemaError> {
         if bytes.len() < 17 {
             return Err(SchemaError::DecodeError);
         }
@@ -257,7 +286,7 @@ impl Decoder for Value {
             net: bytes[16] != 0,
             timestamp: u64::from_le_bytes(TryFrom::try_from(&bytes[..8]).unwrap()),
             bytes: {
-                if bytes.len() < 16 + len {
+                if bytes.len() < 17 + len {
                     return Err(SchemaError::DecodeError);
                 }
                 bytes[17..(17 + len)].to_vec()
@@ -267,6 +296,47 @@ impl Decoder for Value {
     }
 }
 
+/// * bytes layout: `[timestamp(8)][net(1)][zstd([bytes_len(8)][bytes][plain])]`
+impl Encoder for Value {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        let mut payload = Vec::with_capacity(self.bytes.len() + self.plain.len() + 8);
+        payload.extend_from_slice(&(self.bytes.len() as u64).to_le_bytes());
+        payload.extend_from_slice(&self.bytes);
+        payload.extend_from_slice(&self.plain);
+        let payload = zstd::encode_all(payload.as_slice(), Self::COMPRESSION_LEVEL)
+            .map_err(|_| SchemaError::EncodeError)?;
+
+        let mut v = Vec::with_capacity(payload.len() + 9);
+        v.extend_from_slice(&self.timestamp.to_le_bytes());
+        v.push(if self.net { 1 } else { 0 });
+        v.extend_from_slice(&payload);
+        Ok(v)
+    }
+}
+
+impl Decoder for Value {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if bytes.len() < 9 {
+            return Err(SchemaError::DecodeError);
+        }
+
+        let payload = zstd::decode_all(&bytes[9..]).map_err(|_| SchemaError::DecodeError)?;
+        if payload.len() < 8 {
+            return Err(SchemaError::DecodeError);
+        }
+        let len = u64::from_le_bytes(TryFrom::try_from(&payload[..8]).unwrap()) as usize;
+        if payload.len() < 8 + len {
+            return Err(SchemaError::DecodeError);
+        }
+        Ok(Value {
+            net: bytes[8] != 0,
+            timestamp: u64::from_le_bytes(TryFrom::try_from(&bytes[..8]).unwrap()),
+            bytes: payload[8..(8 + len)].to_vec(),
+            plain: payload[(8 + len)..].to_vec(),
+        })
+    }
+}
+
 pub struct Schema;
 
 impl KeyValueSchema for Schema {
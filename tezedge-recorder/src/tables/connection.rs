@@ -11,7 +11,70 @@ use typenum::Bit;
 use storage::persistent::{
     KeyValueSchema, Encoder, Decoder, SchemaError, database::RocksDbKeyValueSchema,
 };
-use super::common::{Initiator, Sender};
+use super::common::{Initiator, Sender, MessageType, MessageKind, MessageCategory};
+
+/// The protocol violations by one side of the connection, a bit for each kind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Violations(u16);
+
+impl Violations {
+    /// the side sent p2p message before its `ack`, or in place of it
+    pub const BEFORE_ACK: u16 = 0x01;
+    /// the side sent a message after `disconnect` sent by any side
+    pub const AFTER_DISCONNECT: u16 = 0x02;
+    /// the side sent `nack`, the connection must be closed
+    pub const NACK: u16 = 0x04;
+    /// the side sent p2p message after `nack` sent by any side
+    pub const AFTER_NACK: u16 = 0x08;
+    /// the side sent a message larger than the maximal size
+    pub const TOO_BIG: u16 = 0x10;
+    /// the side sent the metadata or the `ack` again after the handshake
+    pub const DUPLICATE_HANDSHAKE: u16 = 0x20;
+    /// the side sent p2p message with unknown tag
+    pub const UNKNOWN_KIND: u16 = 0x40;
+
+    /// The bit, the name of the kind of the comment, and the description
+    const ALL: [(u16, &'static str, &'static str); 7] = [
+        (Self::BEFORE_ACK, "before_ack", "p2p message before ack"),
+        (
+            Self::AFTER_DISCONNECT,
+            "after_disconnect",
+            "message after disconnect",
+        ),
+        (Self::NACK, "nack", "nack"),
+        (Self::AFTER_NACK, "after_nack", "p2p message after nack"),
+        (Self::TOO_BIG, "too_big", "message exceeds the maximal size"),
+        (
+            Self::DUPLICATE_HANDSHAKE,
+            "duplicate_handshake",
+            "handshake message after the handshake",
+        ),
+        (
+            Self::UNKNOWN_KIND,
+            "unknown_kind",
+            "p2p message with unknown tag",
+        ),
+    ];
+
+    pub fn insert(&mut self, bits: u16) {
+        self.0 |= bits;
+    }
+
+    pub fn contains(&self, bits: u16) -> bool {
+        self.0 & bits == bits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    fn kinds(&self) -> impl Iterator<Item = (&'static str, &'static str)> + '_ {
+        Self::ALL
+            .iter()
+            .filter(move |(bit, ..)| self.contains(*bit))
+            .map(|(_, kind, description)| (*kind, *description))
+    }
+}
 
 #[derive(Debug, Clone, Default)]
 pub struct Comments {
@@ -25,9 +88,55 @@ pub struct Comments {
     pub outgoing_uncertain: bool,
     pub outgoing_wrong_pk: bool,
     pub outgoing_cannot_decrypt: Option<u64>,
+    pub incoming_violations: Violations,
+    pub outgoing_violations: Violations,
 }
 
 impl Comments {
+    /// The names of the kinds of the comments, used to filter the connections
+    pub const KINDS: [&'static str; 13] = [
+        "wrong_pow",
+        "too_short",
+        "uncertain",
+        "cannot_decrypt",
+        "suspicious",
+        "wrong_pk",
+        "before_ack",
+        "after_disconnect",
+        "nack",
+        "after_nack",
+        "too_big",
+        "duplicate_handshake",
+        "unknown_kind",
+    ];
+
+    /// The kinds of the comments the connection has, in any direction
+    pub fn kinds(&self) -> Vec<&'static str> {
+        let mut kinds = Vec::new();
+        if self.incoming_wrong_pow.is_some() || self.outgoing_wrong_pow.is_some() {
+            kinds.push("wrong_pow");
+        }
+        if self.incoming_too_short.is_some() || self.outgoing_too_short.is_some() {
+            kinds.push("too_short");
+        }
+        if self.incoming_uncertain || self.outgoing_uncertain {
+            kinds.push("uncertain");
+        }
+        if self.incoming_cannot_decrypt.is_some() || self.outgoing_cannot_decrypt.is_some() {
+            kinds.push("cannot_decrypt");
+        }
+        if self.incoming_suspicious.is_some() {
+            kinds.push("suspicious");
+        }
+        if self.outgoing_wrong_pk {
+            kinds.push("wrong_pk");
+        }
+        let mut violations = self.incoming_violations;
+        violations.insert(self.outgoing_violations.0);
+        kinds.extend(violations.kinds().map(|(kind, _)| kind));
+        kinds
+    }
+
     fn ser(&self) -> ([u8; 18], [u8; 18]) {
         let mut i = [0; 18];
         i[0] = self.incoming_wrong_pow.as_ref().cloned().unwrap_or(0.0) as u8;
@@ -44,6 +153,7 @@ impl Comments {
             .unwrap_or(u64::MAX);
         i[4..12].clone_from_slice(&c.to_le_bytes());
         i[12..16].clone_from_slice(&(self.incoming_suspicious.unwrap_or(0) as u32).to_le_bytes());
+        i[16..18].clone_from_slice(&self.incoming_violations.0.to_le_bytes());
         let mut o = [0; 18];
         o[0] = self.outgoing_wrong_pow.as_ref().cloned().unwrap_or(0.0) as u8;
         o[1] = self
@@ -59,6 +169,7 @@ impl Comments {
             .cloned()
             .unwrap_or(u64::MAX);
         o[4..12].clone_from_slice(&c.to_le_bytes());
+        o[16..18].clone_from_slice(&self.outgoing_violations.0.to_le_bytes());
 
         (i, o)
     }
@@ -67,6 +178,8 @@ impl Comments {
         let i_c = u64::from_le_bytes(TryFrom::try_from(&i[4..12]).unwrap());
         let i_s = u32::from_le_bytes(TryFrom::try_from(&i[12..16]).unwrap()) as u64;
         let o_c = u64::from_le_bytes(TryFrom::try_from(&o[4..12]).unwrap());
+        let i_v = u16::from_le_bytes(TryFrom::try_from(&i[16..18]).unwrap());
+        let o_v = u16::from_le_bytes(TryFrom::try_from(&o[16..18]).unwrap());
         Comments {
             incoming_wrong_pow: if i[0] == 0 { None } else { Some(i[0] as f64) },
             incoming_too_short: if i[1] == u8::MAX {
@@ -86,6 +199,8 @@ impl Comments {
             outgoing_uncertain: o[2] != 0,
             outgoing_wrong_pk: o[3] != 0,
             outgoing_cannot_decrypt: if o_c == u64::MAX { None } else { Some(o_c) },
+            incoming_violations: Violations(i_v),
+            outgoing_violations: Violations(o_v),
         }
     }
 }
@@ -142,11 +257,116 @@ impl Serialize for Comments {
             let msg = format!("outgoing chunk cannot decrypt, position: {}", position);
             s.serialize_element(&msg)?;
         }
+        for (_, description) in self.incoming_violations.kinds() {
+            let msg = format!("incoming {}", description);
+            s.serialize_element(&msg)?;
+        }
+        for (_, description) in self.outgoing_violations.kinds() {
+            let msg = format!("outgoing {}", description);
+            s.serialize_element(&msg)?;
+        }
 
         s.end()
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// the last message is `disconnect`, sent by any side
+    Disconnect,
+    /// the socket is closed without `disconnect` message
+    Closed,
+    /// the file descriptor is taken for other file, or for other connection,
+    /// the close is not intercepted
+    FdReused,
+}
+
+impl CloseReason {
+    fn into_int(self) -> u8 {
+        match self {
+            CloseReason::Disconnect => 1,
+            CloseReason::Closed => 2,
+            CloseReason::FdReused => 3,
+        }
+    }
+
+    fn from_int(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(CloseReason::Disconnect),
+            2 => Some(CloseReason::Closed),
+            3 => Some(CloseReason::FdReused),
+            _ => None,
+        }
+    }
+}
+
+/// How much data the connection transferred and how it ended
+#[derive(Debug, Clone, Default)]
+pub struct Lifecycle {
+    /// nanoseconds since epoch, `None` if the connection is open
+    pub closed_at: Option<u64>,
+    pub close_reason: Option<CloseReason>,
+    pub incoming_bytes: u64,
+    pub outgoing_bytes: u64,
+    pub incoming_chunks: u64,
+    pub outgoing_chunks: u64,
+    /// the type of the last message and whether it is incoming
+    pub last_message: Option<(MessageType, bool)>,
+}
+
+impl Lifecycle {
+    fn ser(&self) -> [u8; 44] {
+        let mut v = [0; 44];
+        v[0..8].clone_from_slice(&self.closed_at.unwrap_or(0).to_le_bytes());
+        v[8] = self.close_reason.map(CloseReason::into_int).unwrap_or(0);
+        if let Some((ty, incoming)) = &self.last_message {
+            v[9] = 1;
+            v[10] = ty.clone().into_int();
+            v[11] = if *incoming { 1 } else { 0 };
+        }
+        v[12..20].clone_from_slice(&self.incoming_bytes.to_le_bytes());
+        v[20..28].clone_from_slice(&self.outgoing_bytes.to_le_bytes());
+        v[28..36].clone_from_slice(&self.incoming_chunks.to_le_bytes());
+        v[36..44].clone_from_slice(&self.outgoing_chunks.to_le_bytes());
+        v
+    }
+
+    fn de(v: &[u8; 44]) -> Self {
+        let u64_at = |i: usize| u64::from_le_bytes(TryFrom::try_from(&v[i..(i + 8)]).unwrap());
+        let closed_at = u64_at(0);
+        Lifecycle {
+            closed_at: if closed_at == 0 {
+                None
+            } else {
+                Some(closed_at)
+            },
+            close_reason: CloseReason::from_int(v[8]),
+            incoming_bytes: u64_at(12),
+            outgoing_bytes: u64_at(20),
+            incoming_chunks: u64_at(28),
+            outgoing_chunks: u64_at(36),
+            last_message: if v[9] != 0 {
+                Some((MessageType::from_int(v[10]), v[11] != 0))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// What the sides sent so far, the next message of the connection is checked against it,
+/// it is not stored
+#[derive(Debug, Clone, Default)]
+pub struct ConformanceState {
+    pub incoming_ack: bool,
+    pub outgoing_ack: bool,
+    /// any side sent `nack`
+    pub nack: bool,
+    /// any side sent `disconnect`
+    pub disconnect: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct Item {
     pub ts: u64,
@@ -155,18 +375,14 @@ pub struct Item {
     pub remote_addr: SocketAddr,
     peer_pk: [u8; 32],
     comments: Comments,
+    lifecycle: Lifecycle,
+    conformance: ConformanceState,
 }
 
 impl Item {
-    pub fn new(initiator: Initiator, remote_addr: SocketAddr) -> Self {
-        use std::time::{SystemTime, UNIX_EPOCH};
-
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-
-        let ts = (timestamp / 1_000_000_000) as u64;
+    /// The `timestamp` is nanoseconds since epoch, it is the id of the connection
+    pub fn new(initiator: Initiator, remote_addr: SocketAddr, timestamp: u64) -> Self {
+        let ts = timestamp / 1_000_000_000;
         let ts_nanos = (timestamp % 1_000_000_000) as u32;
 
         Item {
@@ -176,6 +392,8 @@ impl Item {
             remote_addr,
             peer_pk: [0; 32],
             comments: Comments::default(),
+            lifecycle: Lifecycle::default(),
+            conformance: ConformanceState::default(),
         }
     }
 
@@ -183,10 +401,54 @@ impl Item {
         self.peer_pk = peer_pk;
     }
 
+    pub fn peer_pk(&self) -> [u8; 32] {
+        self.peer_pk
+    }
+
     pub fn add_comment(&mut self) -> &mut Comments {
         &mut self.comments
     }
 
+    pub fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    /// The comments and the state the message is checked against
+    pub fn conformance(&mut self) -> (&mut Comments, &mut ConformanceState) {
+        (&mut self.comments, &mut self.conformance)
+    }
+
+    pub fn count_bytes(&mut self, incoming: bool, length: usize) {
+        if incoming {
+            self.lifecycle.incoming_bytes += length as u64;
+        } else {
+            self.lifecycle.outgoing_bytes += length as u64;
+        }
+    }
+
+    pub fn count_chunk(&mut self, sender: &Sender) {
+        if sender.incoming() {
+            self.lifecycle.incoming_chunks += 1;
+        } else {
+            self.lifecycle.outgoing_chunks += 1;
+        }
+    }
+
+    pub fn set_last_message(&mut self, ty: &MessageType, sender: &Sender) {
+        self.lifecycle.last_message = Some((ty.clone(), sender.incoming()));
+    }
+
+    /// The connection ended with `disconnect` message if it is the last message,
+    /// regardless of how the socket was closed, the `timestamp` is nanoseconds since epoch
+    pub fn close(&mut self, reason: CloseReason, timestamp: u64) {
+        let reason = match &self.lifecycle.last_message {
+            Some((MessageType::P2p(MessageKind::Disconnect), _)) => CloseReason::Disconnect,
+            _ => reason,
+        };
+        self.lifecycle.closed_at = Some(timestamp);
+        self.lifecycle.close_reason = Some(reason);
+    }
+
     pub fn mark_uncertain(&mut self) {
         let cn_value = match serde_json::to_string(&self.value()) {
             Ok(s) => s,
@@ -221,14 +483,15 @@ impl Item {
 
     #[rustfmt::skip]
     pub fn split(self) -> (Key, Value) {
-        let Item { ts, ts_nanos, initiator, remote_addr, peer_pk, comments } = self;
-        (Key { ts, ts_nanos }, Value { initiator, remote_addr, peer_pk, comments })
+        let Item { ts, ts_nanos, initiator, remote_addr, peer_pk, comments, lifecycle } = self;
+        (Key { ts, ts_nanos }, Value { initiator, remote_addr, peer_pk, comments, lifecycle })
     }
 
     #[rustfmt::skip]
     pub fn unite(key: Key, value: Value) -> Self {
-        let (Key { ts, ts_nanos }, Value { initiator, remote_addr, peer_pk, comments }) = (key, value);
-        Item { ts, ts_nanos, initiator, remote_addr, peer_pk, comments }
+        let (Key { ts, ts_nanos }, Value { initiator, remote_addr, peer_pk, comments, lifecycle }) = (key, value);
+        let conformance = ConformanceState::default();
+        Item { ts, ts_nanos, initiator, remote_addr, peer_pk, comments, lifecycle, conformance }
     }
 
     pub fn key(&self) -> Key {
@@ -244,6 +507,7 @@ impl Item {
             remote_addr: self.remote_addr,
             peer_pk: self.peer_pk,
             comments: self.comments.clone(),
+            lifecycle: self.lifecycle.clone(),
         }
     }
 }
@@ -320,19 +584,85 @@ impl Decoder for Key {
     }
 }
 
-// ip 16 bytes, port 2 bytes, initiator 1 byte, padding 1 byte, comments 36 bytes, peer_pk 32 bytes
+// ip 16 bytes, port 2 bytes, initiator 1 byte, padding 1 byte, comments 36 bytes, peer_pk 32 bytes,
+// closed_at 8 bytes, close_reason 1 byte, last message 3 bytes, bytes and chunks counters 32 bytes
 pub struct Value {
     initiator: Initiator,
     remote_addr: SocketAddr,
     peer_pk: [u8; 32],
     comments: Comments,
+    lifecycle: Lifecycle,
+}
+
+impl Value {
+    pub fn initiator(&self) -> &Initiator {
+        &self.initiator
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    pub fn comments(&self) -> &Comments {
+        &self.comments
+    }
+
+    pub fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    pub fn peer_pk(&self) -> [u8; 32] {
+        self.peer_pk
+    }
+
+    /// The hash of the public key of the peer in base58check, or the reason why it is unknown
+    pub fn peer_id(&self) -> Result<String, String> {
+        use crypto::{blake2b, hash::HashType};
+
+        if self.peer_pk == [0; 32] {
+            return Err("unknown".to_string());
+        }
+        let hash = blake2b::digest_128(&self.peer_pk).map_err(|e| e.to_string())?;
+        HashType::CryptoboxPublicKeyHash
+            .hash_to_b58check(&hash)
+            .map_err(|e| e.to_string())
+    }
+
+    /// The connection recorded in the layout of version 1 is not tracked anymore,
+    /// it is closed at `closed_at`, nanoseconds since epoch, the reason is unknown
+    pub fn close_v1(&mut self, closed_at: u64) {
+        self.lifecycle.closed_at = Some(closed_at);
+    }
+
+    /// Decode the layout of version 1, without lifecycle, 88 bytes
+    pub fn decode_v1(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if bytes.len() != 88 {
+            return Err(SchemaError::DecodeError);
+        }
+
+        Ok(Value {
+            initiator: Initiator::new(bytes[18] != 0),
+            remote_addr: {
+                let ip = <[u8; 16]>::try_from(&bytes[0..16]).unwrap();
+                let port = u16::from_le_bytes(TryFrom::try_from(&bytes[16..18]).unwrap());
+                (ip, port).into()
+            },
+            peer_pk: TryFrom::try_from(&bytes[56..88]).unwrap(),
+            comments: {
+                let i = TryFrom::try_from(&bytes[20..38]).unwrap();
+                let o = TryFrom::try_from(&bytes[38..56]).unwrap();
+                Comments::de((i, o))
+            },
+            lifecycle: Lifecycle::default(),
+        })
+    }
 }
 
 impl Encoder for Value {
     fn encode(&self) -> Result<Vec<u8>, SchemaError> {
         use std::net::IpAddr;
 
-        let mut v = Vec::with_capacity(88);
+        let mut v = Vec::with_capacity(132);
 
         let ip = match self.remote_addr.ip() {
             IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
@@ -350,30 +680,21 @@ impl Encoder for Value {
 
         v.extend_from_slice(&self.peer_pk);
 
+        v.extend_from_slice(&self.lifecycle.ser());
+
         Ok(v)
     }
 }
 
 impl Decoder for Value {
     fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
-        if bytes.len() != 88 {
+        if bytes.len() != 132 {
             return Err(SchemaError::DecodeError);
         }
 
-        Ok(Value {
-            initiator: Initiator::new(bytes[18] != 0),
-            remote_addr: {
-                let ip = <[u8; 16]>::try_from(&bytes[0..16]).unwrap();
-                let port = u16::from_le_bytes(TryFrom::try_from(&bytes[16..18]).unwrap());
-                (ip, port).into()
-            },
-            peer_pk: TryFrom::try_from(&bytes[56..88]).unwrap(),
-            comments: {
-                let i = TryFrom::try_from(&bytes[20..38]).unwrap();
-                let o = TryFrom::try_from(&bytes[38..56]).unwrap();
-                Comments::de((i, o))
-            },
-        })
+        let mut value = Value::decode_v1(&bytes[..88])?;
+        value.lifecycle = Lifecycle::de(&TryFrom::try_from(&bytes[88..]).unwrap());
+        Ok(value)
     }
 }
 
@@ -382,27 +703,40 @@ impl Serialize for Value {
     where
         S: ser::Serializer,
     {
-        use crypto::{blake2b, hash::HashType};
-
-        let calc_peer_id = || -> Result<String, String> {
-            if self.peer_pk == [0; 32] {
-                return Err("unknown".to_string());
-            }
-            let hash = blake2b::digest_128(&self.peer_pk).map_err(|e| e.to_string())?;
-            HashType::CryptoboxPublicKeyHash
-                .hash_to_b58check(&hash)
-                .map_err(|e| e.to_string())
-        };
-        let peer_id = match calc_peer_id() {
+        let peer_id = match self.peer_id() {
             Ok(s) => s,
             Err(s) => s,
         };
 
-        let mut s = serializer.serialize_struct("Connection", 4)?;
+        #[derive(Serialize)]
+        struct LastMessage {
+            category: MessageCategory,
+            kind: Option<MessageKind>,
+            incoming: bool,
+        }
+
+        let last_message = self.lifecycle.last_message.clone().map(|(ty, incoming)| {
+            let (category, kind) = ty.split();
+            LastMessage {
+                category,
+                kind,
+                incoming,
+            }
+        });
+
+        let lifecycle = &self.lifecycle;
+        let mut s = serializer.serialize_struct("Connection", 11)?;
         s.serialize_field("initiator", &self.initiator)?;
         s.serialize_field("remote_addr", &self.remote_addr)?;
         s.serialize_field("peer_id", &peer_id)?;
         s.serialize_field("comments", &self.comments)?;
+        s.serialize_field("closed_at", &lifecycle.closed_at)?;
+        s.serialize_field("close_reason", &lifecycle.close_reason)?;
+        s.serialize_field("incoming_bytes", &lifecycle.incoming_bytes)?;
+        s.serialize_field("outgoing_bytes", &lifecycle.outgoing_bytes)?;
+        s.serialize_field("incoming_chunks", &lifecycle.incoming_chunks)?;
+        s.serialize_field("outgoing_chunks", &lifecycle.outgoing_chunks)?;
+        s.serialize_field("last_message", &last_message)?;
         s.end()
     }
 }
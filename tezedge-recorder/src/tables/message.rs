@@ -10,8 +10,10 @@ use tezos_messages::p2p::{
         metadata::MetadataMessage,
         ack::AckMessage,
         peer::{PeerMessage, PeerMessageResponse},
+        block_header::BlockHeader,
+        operation::Operation,
     },
-    binary_message::BinaryRead,
+    binary_message::{BinaryRead, BinaryWrite},
 };
 use super::{
     common::{Initiator, Sender, MessageCategory, MessageKind, MessageType},
@@ -28,14 +30,216 @@ pub struct Item {
     pub sender: Sender,
     pub ty: MessageType,
     chunks: Range<u64>,
+    /// total length of the decrypted chunks
+    pub size: u64,
+    /// truncated json representation, computed at ingest time
+    pub preview: Option<String>,
+    /// the blocks and the operations, computed at ingest time
+    pub refs: Refs,
+}
+
+/// The blocks and the operations the message refers to
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Refs {
+    /// the hashes of the blocks
+    pub blocks: Vec<[u8; 32]>,
+    /// the levels of the block headers the message carries
+    pub levels: Vec<u32>,
+    /// the hashes of the operations
+    pub operations: Vec<[u8; 32]>,
+    /// the addresses of the peers the message advertises,
+    /// they are not stored with the message, but in the advertisement table
+    #[serde(skip)]
+    pub points: Vec<SocketAddr>,
+}
+
+impl Refs {
+    /// The `current_head` is sent often, and each of its operations is indexed,
+    /// the operations of the mempool beyond this number are not referred to
+    pub const MAX_MEMPOOL_OPERATIONS: usize = 0x100;
+
+    /// The `current_head`, the `block_header` and the `operations_for_blocks` refer to the block,
+    /// the `get_*` messages refer to the requested blocks and operations,
+    /// the `current_head` also refers to the first `MAX_MEMPOOL_OPERATIONS` of its mempool,
+    /// the `advertise`, `swap_request` and `swap_ack` carry the addresses of the peers
+    pub fn new(message: &PeerMessage) -> Self {
+        let mut refs = Refs::default();
+        match message {
+            PeerMessage::CurrentHead(m) => {
+                refs.header(m.current_block_header());
+                let mempool = m.current_mempool();
+                let hashes = mempool.known_valid().iter().chain(mempool.pending());
+                for hash in hashes.take(Self::MAX_MEMPOOL_OPERATIONS) {
+                    refs.operations.extend(hash_bytes(hash));
+                }
+            },
+            PeerMessage::CurrentBranch(m) => refs.header(m.current_branch().current_head()),
+            PeerMessage::BlockHeader(m) => refs.header(m.block_header()),
+            PeerMessage::GetBlockHeaders(m) => {
+                for hash in m.get_block_headers() {
+                    refs.blocks.extend(hash_bytes(hash));
+                }
+            },
+            PeerMessage::GetOperationsForBlocks(m) => {
+                for operations_for_block in m.get_operations_for_blocks() {
+                    refs.blocks.extend(hash_bytes(operations_for_block.hash()));
+                }
+            },
+            PeerMessage::OperationsForBlocks(m) => {
+                refs.blocks
+                    .extend(hash_bytes(m.operations_for_block().hash()));
+                for operation in m.operations() {
+                    refs.operation(operation);
+                }
+            },
+            PeerMessage::Operation(m) => refs.operation(m.operation()),
+            PeerMessage::GetOperations(m) => {
+                for hash in m.get_operations() {
+                    refs.operations.extend(hash_bytes(hash));
+                }
+            },
+            PeerMessage::Advertise(m) => {
+                refs.points.extend(
+                    m.id()
+                        .iter()
+                        .filter_map(|point| point.parse::<SocketAddr>().ok()),
+                );
+            },
+            PeerMessage::SwapRequest(m) | PeerMessage::SwapAck(m) => {
+                refs.points.extend(m.point().parse::<SocketAddr>().ok());
+            },
+            _ => (),
+        }
+        refs
+    }
+
+    fn header(&mut self, header: &BlockHeader) {
+        if let Ok(bytes) = header.as_bytes() {
+            self.blocks.extend(digest(&bytes));
+        }
+        if header.level() >= 0 {
+            self.levels.push(header.level() as u32);
+        }
+    }
+
+    fn operation(&mut self, operation: &Operation) {
+        if let Ok(bytes) = operation.as_bytes() {
+            self.operations.extend(digest(&bytes));
+        }
+    }
+}
+
+// the hash of the block header and the hash of the operation is blake2b of its bytes
+fn digest(bytes: &[u8]) -> Option<[u8; 32]> {
+    let hash = crypto::blake2b::digest_256(bytes).ok()?;
+    <[u8; 32]>::try_from(hash.as_slice()).ok()
+}
+
+fn hash_bytes<H>(hash: &H) -> Option<[u8; 32]>
+where
+    H: AsRef<Vec<u8>>,
+{
+    <[u8; 32]>::try_from(hash.as_ref().as_slice()).ok()
+}
+
+/// The layout of version 2, without references to the blocks and the operations
+#[derive(Serialize, Deserialize)]
+pub struct ItemV2 {
+    cn_ts: u64,
+    cn_ts_nanos: u32,
+    timestamp: u64,
+    remote_addr: SocketAddr,
+    initiator: Initiator,
+    sender: Sender,
+    ty: MessageType,
+    chunks: Range<u64>,
+    size: u64,
+    preview: Option<String>,
+}
+
+impl BincodeEncoded for ItemV2 {}
+
+impl From<ItemV2> for Item {
+    fn from(v: ItemV2) -> Self {
+        Item {
+            cn_ts: v.cn_ts,
+            cn_ts_nanos: v.cn_ts_nanos,
+            timestamp: v.timestamp,
+            remote_addr: v.remote_addr,
+            initiator: v.initiator,
+            sender: v.sender,
+            ty: v.ty,
+            chunks: v.chunks,
+            size: v.size,
+            preview: v.preview,
+            refs: Refs::default(),
+        }
+    }
+}
+
+/// The layout of version 1, without size and preview
+#[derive(Serialize, Deserialize)]
+pub struct ItemV1 {
+    cn_ts: u64,
+    cn_ts_nanos: u32,
+    timestamp: u64,
+    remote_addr: SocketAddr,
+    initiator: Initiator,
+    sender: Sender,
+    ty: MessageType,
+    chunks: Range<u64>,
+}
+
+impl BincodeEncoded for ItemV1 {}
+
+impl From<ItemV1> for ItemV2 {
+    fn from(v: ItemV1) -> Self {
+        ItemV2 {
+            cn_ts: v.cn_ts,
+            cn_ts_nanos: v.cn_ts_nanos,
+            timestamp: v.timestamp,
+            remote_addr: v.remote_addr,
+            initiator: v.initiator,
+            sender: v.sender,
+            ty: v.ty,
+            chunks: v.chunks,
+            size: 0,
+            preview: None,
+        }
+    }
 }
 
 impl Item {
-    pub fn chunks(&self) -> impl Iterator<Item = chunk::Key> + '_ {
-        let cn_id = connection::Key {
+    pub fn chunk_count(&self) -> u64 {
+        self.chunks.end - self.chunks.start
+    }
+
+    /// Compute size, preview and refs from the decrypted bytes of the message
+    pub fn summarize(&mut self, plain: &[u8]) {
+        self.size = plain.len() as u64;
+        let message = TezosMessage::decode(&self.ty, plain).ok();
+        self.preview = message
+            .as_ref()
+            .and_then(|m| m.json_string().ok())
+            .map(|mut s| {
+                utf8_truncate(&mut s, 100);
+                s
+            });
+        self.refs = match &message {
+            Some(TezosMessage::PeerMessage(m)) => Refs::new(m),
+            _ => Refs::default(),
+        };
+    }
+
+    pub fn cn_id(&self) -> connection::Key {
+        connection::Key {
             ts: self.cn_ts,
             ts_nanos: self.cn_ts_nanos,
-        };
+        }
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = chunk::Key> + '_ {
+        let cn_id = self.cn_id();
         let sender = self.sender.clone();
         self.chunks.clone().map(move |counter| chunk::Key {
             cn_id: cn_id.clone(),
@@ -55,11 +259,14 @@ pub struct MessageFrontend {
     pub category: MessageCategory,
     pub kind: Option<MessageKind>,
     message_preview: Option<String>,
+    pub size: u64,
+    chunk_count: u64,
 }
 
 impl MessageFrontend {
-    pub fn new(item: Item, id: u64, message_preview: Option<String>) -> Self {
+    pub fn new(item: Item, id: u64) -> Self {
         let (category, kind) = item.ty.split();
+        let chunk_count = item.chunk_count();
         MessageFrontend {
             id,
             timestamp: (item.timestamp as u128) * 1_000_000,
@@ -68,7 +275,9 @@ impl MessageFrontend {
             incoming: item.sender.incoming(),
             category,
             kind,
-            message_preview,
+            message_preview: item.preview,
+            size: item.size,
+            chunk_count,
         }
     }
 }
@@ -82,6 +291,23 @@ pub enum TezosMessage {
 }
 
 impl TezosMessage {
+    pub fn decode(ty: &MessageType, bytes: &[u8]) -> Result<Self, String> {
+        match ty {
+            MessageType::Connection => ConnectionMessage::from_bytes(bytes)
+                .map_err(|e| e.to_string())
+                .map(TezosMessage::ConnectionMessage),
+            MessageType::Meta => MetadataMessage::from_bytes(bytes)
+                .map_err(|e| e.to_string())
+                .map(TezosMessage::MetadataMessage),
+            MessageType::Ack => AckMessage::from_bytes(bytes)
+                .map_err(|e| e.to_string())
+                .map(TezosMessage::AckMessage),
+            MessageType::P2p(_) => PeerMessageResponse::from_bytes(bytes)
+                .map_err(|e| e.to_string())
+                .map(|n| TezosMessage::PeerMessage(n.message().clone())),
+        }
+    }
+
     pub fn json_string(&self) -> Result<String, serde_json::Error> {
         match self {
             TezosMessage::ConnectionMessage(m) => serde_json::to_string(m),
@@ -151,21 +377,7 @@ impl MessageDetails {
         for c in chunks {
             bytes.extend_from_slice(&c.plain);
         }
-        let message = match ty {
-            MessageType::Connection => ConnectionMessage::from_bytes(&bytes)
-                .map_err(|e| e.to_string())
-                .map(TezosMessage::ConnectionMessage),
-            MessageType::Meta => MetadataMessage::from_bytes(&bytes)
-                .map_err(|e| e.to_string())
-                .map(TezosMessage::MetadataMessage),
-            MessageType::Ack => AckMessage::from_bytes(&bytes)
-                .map_err(|e| e.to_string())
-                .map(TezosMessage::AckMessage),
-            MessageType::P2p(_) => PeerMessageResponse::from_bytes(&bytes)
-                .map_err(|e| e.to_string())
-                .map(|n| TezosMessage::PeerMessage(n.message().clone())),
-        };
-        let (message, error) = match message {
+        let (message, error) = match TezosMessage::decode(ty, &bytes) {
             Ok(m) => (Some(m), None),
             Err(e) => (None, Some(e)),
         };
@@ -249,27 +461,41 @@ impl MessageBuilder {
 }
 
 impl MessageBuilderFull {
-    pub fn build(self, sender: &Sender, connection: &connection::Item) -> Item {
-        use std::time::{SystemTime, UNIX_EPOCH};
-
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
-
-        Item {
+    /// The `plain` is the decrypted bytes of all chunks of the message,
+    /// the `timestamp` is nanoseconds since epoch, the message stores milliseconds
+    pub fn build(
+        self,
+        sender: &Sender,
+        connection: &connection::Item,
+        plain: &[u8],
+        timestamp: u64,
+    ) -> Item {
+        let mut item = Item {
             cn_ts: connection.ts,
             cn_ts_nanos: connection.ts_nanos,
-            timestamp,
+            timestamp: timestamp / 1_000_000,
             remote_addr: connection.remote_addr,
             initiator: connection.initiator.clone(),
             sender: sender.clone(),
             ty: self.0.ty,
             chunks: self.0.chunks,
-        }
+            size: 0,
+            preview: None,
+            refs: Refs::default(),
+        };
+        item.summarize(plain);
+        item
     }
 }
 
+fn utf8_truncate(input: &mut String, max_size: usize) {
+    let mut m = max_size;
+    while !input.is_char_boundary(m) {
+        m -= 1;
+    }
+    input.truncate(m);
+}
+
 impl BincodeEncoded for Item {}
 
 pub struct Schema;
@@ -284,3 +510,17 @@ impl RocksDbKeyValueSchema for Schema {
         "message_storage"
     }
 }
+
+/// The same column family in the layout of version 2, used by the migration
+pub struct SchemaV2;
+
+impl KeyValueSchema for SchemaV2 {
+    type Key = u64;
+    type Value = ItemV2;
+}
+
+impl RocksDbKeyValueSchema for SchemaV2 {
+    fn name() -> &'static str {
+        Schema::name()
+    }
+}
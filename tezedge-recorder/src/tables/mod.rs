@@ -7,6 +7,10 @@ pub mod connection;
 pub mod chunk;
 pub mod message;
 pub mod node_log;
+pub mod peer;
+pub mod request;
+pub mod advertisement;
+pub mod schema_version;
 
 mod secondary_indexes;
 pub use self::secondary_indexes::*;
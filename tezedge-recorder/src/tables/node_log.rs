@@ -16,6 +16,9 @@ pub struct ItemWithId {
     pub section: String,
     #[serde(alias = "msg")]
     pub message: String,
+    /// the host name of the syslog record, empty if the record has no host name
+    #[serde(default)]
+    pub hostname: String,
 }
 
 impl ItemWithId {
@@ -26,6 +29,7 @@ impl ItemWithId {
             timestamp: item.timestamp,
             section: item.section,
             message: item.message,
+            hostname: item.hostname,
         }
     }
 }
@@ -37,6 +41,30 @@ pub struct Item {
     pub timestamp: u128,
     pub section: String,
     pub message: String,
+    pub hostname: String,
+}
+
+/// The layout of version 1, without host name
+#[derive(Serialize, Deserialize)]
+pub struct ItemV1 {
+    level: LogLevel,
+    timestamp: u128,
+    section: String,
+    message: String,
+}
+
+impl BincodeEncoded for ItemV1 {}
+
+impl From<ItemV1> for Item {
+    fn from(v: ItemV1) -> Self {
+        Item {
+            level: v.level,
+            timestamp: v.timestamp,
+            section: v.section,
+            message: v.message,
+            hostname: String::new(),
+        }
+    }
 }
 
 #[repr(u8)]
@@ -141,6 +169,11 @@ where
                     .unwrap()
                     .as_nanos()
             });
+        let hostname = msg
+            .hostname
+            .as_ref()
+            .map(|h| h.as_ref().to_string())
+            .unwrap_or_default();
         let line = msg.msg.as_ref();
 
         let pos = line.find('.').unwrap_or_default();
@@ -152,6 +185,7 @@ where
                     level: LogLevel::from_str(level).unwrap_or(LogLevel::Fatal),
                     message: message.to_string(),
                     section: "".to_string(),
+                    hostname,
                 }
             } else {
                 Item {
@@ -159,6 +193,7 @@ where
                     level: LogLevel::Fatal,
                     section: "".to_string(),
                     message: line.to_string(),
+                    hostname,
                 }
             }
         } else {
@@ -168,6 +203,7 @@ where
                     level: LogLevel::from_str(level).unwrap_or(LogLevel::Fatal),
                     message: message.to_string(),
                     section: "".to_string(),
+                    hostname,
                 }
             } else {
                 Item {
@@ -175,6 +211,7 @@ where
                     level: LogLevel::Fatal,
                     section: "".to_string(),
                     message: line.to_string(),
+                    hostname,
                 }
             }
         }
@@ -0,0 +1,357 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+use thiserror::Error;
+use serde::{
+    Serialize,
+    ser::{self, SerializeStruct},
+};
+use storage::persistent::{
+    KeyValueSchema, Encoder, Decoder, SchemaError, database::RocksDbKeyValueSchema,
+};
+use super::{
+    connection,
+    common::{MessageType, MessageKind, MessageCategory},
+};
+
+/// The hash of the public key of the peer, its peer id
+/// * bytes layout: `[hash(16)]`
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key(pub [u8; 16]);
+
+impl Key {
+    /// `None` if the public key is unknown
+    pub fn from_pk(pk: &[u8; 32]) -> Option<Self> {
+        use crypto::blake2b;
+
+        if *pk == [0; 32] {
+            return None;
+        }
+        let hash = blake2b::digest_128(pk).ok()?;
+        <[u8; 16]>::try_from(hash.as_slice()).ok().map(Key)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("invalid peer id {}", _0)]
+pub struct KeyFromStrError(String);
+
+impl FromStr for Key {
+    type Err = KeyFromStrError;
+
+    // format: base58check
+    // example: idtJunqYgUmFDSsDXuqHSDt9HmQGQX
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use crypto::hash::HashType;
+
+        HashType::CryptoboxPublicKeyHash
+            .b58check_to_hash(s)
+            .ok()
+            .and_then(|hash| <[u8; 16]>::try_from(hash.as_slice()).ok())
+            .map(Key)
+            .ok_or_else(|| KeyFromStrError(s.to_string()))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use crypto::hash::HashType;
+
+        match HashType::CryptoboxPublicKeyHash.hash_to_b58check(&self.0) {
+            Ok(s) => write!(f, "{}", s),
+            Err(_) => write!(f, "{}", hex::encode(&self.0)),
+        }
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl Encoder for Key {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        Ok(self.0.to_vec())
+    }
+}
+
+impl Decoder for Key {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        <[u8; 16]>::try_from(bytes)
+            .map(Key)
+            .map_err(|_| SchemaError::DecodeError)
+    }
+}
+
+/// The summary of all connections with the peer, it outlives the connections
+/// and the messages removed by the retention
+#[derive(Clone, Default)]
+pub struct Value {
+    /// nanoseconds since epoch
+    first_seen: u64,
+    last_seen: u64,
+    /// the most recent remote addresses, at most `MAX_ADDRESSES`
+    addresses: Vec<SocketAddr>,
+    connections: u64,
+    incoming_bytes: u64,
+    outgoing_bytes: u64,
+    /// the number of connections where the peer sent the connection message with wrong pow
+    wrong_pow: u64,
+    /// the number of connections where the data of the peer cannot be decrypted
+    cannot_decrypt: u64,
+    /// the number of messages by type, incoming and outgoing
+    messages: BTreeMap<u8, (u64, u64)>,
+}
+
+impl Value {
+    pub const MAX_ADDRESSES: usize = 0x40;
+
+    pub fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    fn seen(&mut self, time: u64) {
+        if self.first_seen == 0 || time < self.first_seen {
+            self.first_seen = time;
+        }
+        if time > self.last_seen {
+            self.last_seen = time;
+        }
+    }
+
+    /// Count the change of the connection from the `old` state to the `new` state,
+    /// the `old` is `None` if the connection is new
+    pub fn count_connection(
+        &mut self,
+        key: &connection::Key,
+        old: Option<&connection::Value>,
+        new: &connection::Value,
+    ) {
+        self.seen(key.ts * 1_000_000_000 + key.ts_nanos as u64);
+        if let Some(closed_at) = new.lifecycle().closed_at {
+            self.seen(closed_at);
+        }
+
+        // store the ipv4 address mapped to ipv6, the same way as the decoder returns it
+        let addr = match new.remote_addr() {
+            SocketAddr::V4(addr) => SocketAddr::new(addr.ip().to_ipv6_mapped().into(), addr.port()),
+            addr => addr,
+        };
+        if let Some(position) = self.addresses.iter().position(|a| *a == addr) {
+            self.addresses.remove(position);
+        } else if self.addresses.len() >= Self::MAX_ADDRESSES {
+            self.addresses.remove(0);
+        }
+        self.addresses.push(addr);
+
+        let (old_lifecycle, old_comments) = match old {
+            Some(old) => (old.lifecycle().clone(), old.comments().clone()),
+            None => {
+                self.connections += 1;
+                Default::default()
+            },
+        };
+        let lifecycle = new.lifecycle();
+        self.incoming_bytes += lifecycle
+            .incoming_bytes
+            .saturating_sub(old_lifecycle.incoming_bytes);
+        self.outgoing_bytes += lifecycle
+            .outgoing_bytes
+            .saturating_sub(old_lifecycle.outgoing_bytes);
+        let comments = new.comments();
+        if comments.incoming_wrong_pow.is_some() && old_comments.incoming_wrong_pow.is_none() {
+            self.wrong_pow += 1;
+        }
+        if comments.incoming_cannot_decrypt.is_some()
+            && old_comments.incoming_cannot_decrypt.is_none()
+        {
+            self.cannot_decrypt += 1;
+        }
+    }
+
+    /// Count the message, the `timestamp` is in milliseconds
+    pub fn count_message(&mut self, ty: &MessageType, incoming: bool, timestamp: u64) {
+        self.seen(timestamp * 1_000_000);
+        let count = self.messages.entry(ty.clone().into_int()).or_default();
+        if incoming {
+            count.0 += 1;
+        } else {
+            count.1 += 1;
+        }
+    }
+
+    /// Add the messages counted in the `other` summary
+    pub fn merge_messages(&mut self, other: &Self) {
+        if other.first_seen != 0 {
+            self.seen(other.first_seen);
+            self.seen(other.last_seen);
+        }
+        for (ty, (incoming, outgoing)) in &other.messages {
+            let count = self.messages.entry(*ty).or_default();
+            count.0 += incoming;
+            count.1 += outgoing;
+        }
+    }
+}
+
+/// * bytes layout: `[first_seen(8)][last_seen(8)][connections(8)][incoming_bytes(8)]`
+/// `[outgoing_bytes(8)][wrong_pow(8)][cannot_decrypt(8)]`
+/// `[addresses_len(2)][[addr(16)][port(2)] * addresses_len]`
+/// `[messages_len(2)][[type(1)][incoming(8)][outgoing(8)] * messages_len]`
+impl Encoder for Value {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        let mut v = Vec::with_capacity(60 + self.addresses.len() * 18 + self.messages.len() * 17);
+        v.extend_from_slice(&self.first_seen.to_be_bytes());
+        v.extend_from_slice(&self.last_seen.to_be_bytes());
+        v.extend_from_slice(&self.connections.to_be_bytes());
+        v.extend_from_slice(&self.incoming_bytes.to_be_bytes());
+        v.extend_from_slice(&self.outgoing_bytes.to_be_bytes());
+        v.extend_from_slice(&self.wrong_pow.to_be_bytes());
+        v.extend_from_slice(&self.cannot_decrypt.to_be_bytes());
+        v.extend_from_slice(&(self.addresses.len() as u16).to_be_bytes());
+        for addr in &self.addresses {
+            let ip = match addr.ip() {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+                IpAddr::V6(ip) => ip.octets(),
+            };
+            v.extend_from_slice(&ip);
+            v.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        v.extend_from_slice(&(self.messages.len() as u16).to_be_bytes());
+        for (ty, (incoming, outgoing)) in &self.messages {
+            v.push(*ty);
+            v.extend_from_slice(&incoming.to_be_bytes());
+            v.extend_from_slice(&outgoing.to_be_bytes());
+        }
+        Ok(v)
+    }
+}
+
+impl Decoder for Value {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], SchemaError> {
+            if bytes.len() < len {
+                return Err(SchemaError::DecodeError);
+            }
+            let (head, tail) = bytes.split_at(len);
+            *bytes = tail;
+            Ok(head)
+        }
+        fn u64(bytes: &mut &[u8]) -> Result<u64, SchemaError> {
+            Ok(u64::from_be_bytes(
+                TryFrom::try_from(take(bytes, 8)?).unwrap(),
+            ))
+        }
+        fn u16(bytes: &mut &[u8]) -> Result<u16, SchemaError> {
+            Ok(u16::from_be_bytes(
+                TryFrom::try_from(take(bytes, 2)?).unwrap(),
+            ))
+        }
+
+        let mut bytes = bytes;
+        let mut value = Value {
+            first_seen: u64(&mut bytes)?,
+            last_seen: u64(&mut bytes)?,
+            connections: u64(&mut bytes)?,
+            incoming_bytes: u64(&mut bytes)?,
+            outgoing_bytes: u64(&mut bytes)?,
+            wrong_pow: u64(&mut bytes)?,
+            cannot_decrypt: u64(&mut bytes)?,
+            ..Default::default()
+        };
+        for _ in 0..u16(&mut bytes)? {
+            let ip = <[u8; 16]>::try_from(take(&mut bytes, 16)?).unwrap();
+            let port = u16(&mut bytes)?;
+            value.addresses.push((ip, port).into());
+        }
+        for _ in 0..u16(&mut bytes)? {
+            let ty = take(&mut bytes, 1)?[0];
+            let incoming = u64(&mut bytes)?;
+            let outgoing = u64(&mut bytes)?;
+            value.messages.insert(ty, (incoming, outgoing));
+        }
+        if !bytes.is_empty() {
+            return Err(SchemaError::DecodeError);
+        }
+        Ok(value)
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        #[derive(Serialize)]
+        struct MessageCount {
+            category: MessageCategory,
+            kind: Option<MessageKind>,
+            incoming: u64,
+            outgoing: u64,
+        }
+
+        // the ipv4 address is stored mapped to ipv6
+        let addresses = self
+            .addresses
+            .iter()
+            .map(|addr| match addr.ip() {
+                IpAddr::V6(v6) => match v6.to_ipv4() {
+                    Some(v4) if v4.to_ipv6_mapped() == v6 => {
+                        SocketAddr::new(v4.into(), addr.port())
+                    },
+                    _ => *addr,
+                },
+                IpAddr::V4(_) => *addr,
+            })
+            .collect::<Vec<_>>();
+        let messages = self
+            .messages
+            .iter()
+            .map(|(ty, (incoming, outgoing))| {
+                let (category, kind) = MessageType::from_int(*ty).split();
+                MessageCount {
+                    category,
+                    kind,
+                    incoming: *incoming,
+                    outgoing: *outgoing,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut s = serializer.serialize_struct("Peer", 9)?;
+        s.serialize_field("first_seen", &self.first_seen)?;
+        s.serialize_field("last_seen", &self.last_seen)?;
+        s.serialize_field("addresses", &addresses)?;
+        s.serialize_field("connections", &self.connections)?;
+        s.serialize_field("incoming_bytes", &self.incoming_bytes)?;
+        s.serialize_field("outgoing_bytes", &self.outgoing_bytes)?;
+        s.serialize_field("wrong_pow", &self.wrong_pow)?;
+        s.serialize_field("cannot_decrypt", &self.cannot_decrypt)?;
+        s.serialize_field("message_counts", &messages)?;
+        s.end()
+    }
+}
+
+pub struct Schema;
+
+impl KeyValueSchema for Schema {
+    type Key = Key;
+    type Value = Value;
+}
+
+impl RocksDbKeyValueSchema for Schema {
+    fn name() -> &'static str {
+        "peer_storage"
+    }
+}
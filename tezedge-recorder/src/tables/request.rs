@@ -0,0 +1,128 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryFrom;
+use serde::{Serialize, Deserialize};
+use storage::persistent::{
+    KeyValueSchema, Encoder, Decoder, SchemaError, BincodeEncoded, database::RocksDbKeyValueSchema,
+};
+use super::{common::MessageKind, connection};
+
+/// The request message and the requested item, the request of several blocks
+/// or operations has a part for each of them
+/// * bytes layout: `[index(8)][part(4)]`
+#[derive(Debug, Clone)]
+pub struct Key {
+    pub index: u64,
+    pub part: u32,
+}
+
+impl Encoder for Key {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        let mut v = Vec::with_capacity(12);
+        v.extend_from_slice(&self.index.to_be_bytes());
+        v.extend_from_slice(&self.part.to_be_bytes());
+        Ok(v)
+    }
+}
+
+impl Decoder for Key {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if bytes.len() != 12 {
+            return Err(SchemaError::DecodeError);
+        }
+
+        Ok(Key {
+            index: u64::from_be_bytes(TryFrom::try_from(&bytes[..8]).unwrap()),
+            part: u32::from_be_bytes(TryFrom::try_from(&bytes[8..]).unwrap()),
+        })
+    }
+}
+
+/// The request and its response, if any
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Value {
+    cn_ts: u64,
+    cn_ts_nanos: u32,
+    pub kind: MessageKind,
+    /// the peer sent the request
+    pub incoming: bool,
+    /// milliseconds since epoch
+    pub timestamp: u64,
+    /// the id of the response message
+    pub response: Option<u64>,
+    /// milliseconds from the request until the response
+    pub rtt: Option<u64>,
+    /// the connection was closed before the response
+    pub unanswered: bool,
+}
+
+impl Value {
+    pub fn new(cn_id: &connection::Key, kind: MessageKind, incoming: bool, timestamp: u64) -> Self {
+        Value {
+            cn_ts: cn_id.ts,
+            cn_ts_nanos: cn_id.ts_nanos,
+            kind,
+            incoming,
+            timestamp,
+            response: None,
+            rtt: None,
+            unanswered: false,
+        }
+    }
+
+    pub fn cn_id(&self) -> connection::Key {
+        connection::Key {
+            ts: self.cn_ts,
+            ts_nanos: self.cn_ts_nanos,
+        }
+    }
+}
+
+impl BincodeEncoded for Value {}
+
+#[derive(Serialize)]
+pub struct RequestFrontend {
+    /// the id of the request message
+    pub id: u64,
+    pub part: u32,
+    pub connection_id: connection::Key,
+    pub peer: String,
+    pub kind: MessageKind,
+    pub incoming: bool,
+    /// nanoseconds since epoch
+    pub timestamp: u64,
+    pub response: Option<u64>,
+    pub rtt: Option<u64>,
+    pub unanswered: bool,
+}
+
+impl RequestFrontend {
+    pub fn new(key: Key, value: Value, peer: String) -> Self {
+        RequestFrontend {
+            id: key.index,
+            part: key.part,
+            connection_id: value.cn_id(),
+            peer,
+            kind: value.kind,
+            incoming: value.incoming,
+            timestamp: value.timestamp * 1_000_000,
+            response: value.response,
+            rtt: value.rtt,
+            unanswered: value.unanswered,
+        }
+    }
+}
+
+pub struct Schema;
+
+impl KeyValueSchema for Schema {
+    type Key = Key;
+    type Value = Value;
+}
+
+impl RocksDbKeyValueSchema for Schema {
+    fn name() -> &'static str {
+        "request_storage"
+    }
+}
@@ -0,0 +1,37 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use storage::persistent::{
+    KeyValueSchema, Encoder, Decoder, SchemaError, database::RocksDbKeyValueSchema,
+};
+
+/// The name of the column family whose layout version is stored
+/// * bytes layout: `[name(utf8)]`
+pub struct Key(pub String);
+
+impl Encoder for Key {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        Ok(self.0.as_bytes().to_vec())
+    }
+}
+
+impl Decoder for Key {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        String::from_utf8(bytes.to_vec())
+            .map(Key)
+            .map_err(|_| SchemaError::DecodeError)
+    }
+}
+
+pub struct Schema;
+
+impl KeyValueSchema for Schema {
+    type Key = Key;
+    type Value = u64;
+}
+
+impl RocksDbKeyValueSchema for Schema {
+    fn name() -> &'static str {
+        "schema_version"
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryFrom;
+use storage::persistent::{
+    KeyValueSchema, Encoder, Decoder, SchemaError, database::RocksDbKeyValueSchema,
+};
+use rocksdb::{ColumnFamilyDescriptor, Cache};
+use super::connection;
+
+/// The messages of the connection
+/// * bytes layout: `[cn_ts(8)][cn_ts_nanos(4)][index(8)]`
+pub struct Item {
+    pub cn_id: connection::Key,
+    pub index: u64,
+}
+
+impl Encoder for Item {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        let mut v = Vec::with_capacity(20);
+
+        v.extend_from_slice(&self.cn_id.ts.to_be_bytes());
+        v.extend_from_slice(&self.cn_id.ts_nanos.to_be_bytes());
+        v.extend_from_slice(&self.index.to_be_bytes());
+
+        Ok(v)
+    }
+}
+
+impl Decoder for Item {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if bytes.len() != 20 {
+            return Err(SchemaError::DecodeError);
+        }
+
+        Ok(Item {
+            cn_id: connection::Key {
+                ts: u64::from_be_bytes(TryFrom::try_from(&bytes[..8]).unwrap()),
+                ts_nanos: u32::from_be_bytes(TryFrom::try_from(&bytes[8..12]).unwrap()),
+            },
+            index: u64::from_be_bytes(TryFrom::try_from(&bytes[12..]).unwrap()),
+        })
+    }
+}
+
+pub struct Schema;
+
+impl KeyValueSchema for Schema {
+    type Key = Item;
+    type Value = ();
+}
+
+impl RocksDbKeyValueSchema for Schema {
+    fn descriptor(_cache: &Cache) -> ColumnFamilyDescriptor {
+        use rocksdb::{Options, SliceTransform};
+
+        let mut cf_opts = Options::default();
+        cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(12));
+        cf_opts.set_memtable_prefix_bloom_ratio(0.2);
+        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    }
+
+    fn name() -> &'static str {
+        "message_connection_secondary_index"
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryFrom;
+use storage::persistent::{
+    KeyValueSchema, Encoder, Decoder, SchemaError, database::RocksDbKeyValueSchema,
+};
+use rocksdb::{ColumnFamilyDescriptor, Cache};
+
+/// The message refers to the block or the operation with the hash
+/// * bytes layout: `[hash(32)][index(8)]`
+pub struct HashItem {
+    pub hash: [u8; 32],
+    pub index: u64,
+}
+
+impl Encoder for HashItem {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        let mut v = Vec::with_capacity(40);
+
+        v.extend_from_slice(&self.hash);
+        v.extend_from_slice(&self.index.to_be_bytes());
+
+        Ok(v)
+    }
+}
+
+impl Decoder for HashItem {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if bytes.len() != 40 {
+            return Err(SchemaError::DecodeError);
+        }
+
+        Ok(HashItem {
+            hash: <[u8; 32]>::try_from(&bytes[..32]).unwrap(),
+            index: u64::from_be_bytes(<[u8; 8]>::try_from(&bytes[32..]).unwrap()),
+        })
+    }
+}
+
+/// The messages are queried by the range of levels, this is the maximal length of the range
+pub const MAX_LEVEL_SPAN: u32 = 0x400;
+
+/// The message carries the block header of the level
+/// * bytes layout: `[level(4)][index(8)]`
+pub struct LevelItem {
+    pub level: u32,
+    pub index: u64,
+}
+
+impl Encoder for LevelItem {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        let mut v = Vec::with_capacity(12);
+
+        v.extend_from_slice(&self.level.to_be_bytes());
+        v.extend_from_slice(&self.index.to_be_bytes());
+
+        Ok(v)
+    }
+}
+
+impl Decoder for LevelItem {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if bytes.len() != 12 {
+            return Err(SchemaError::DecodeError);
+        }
+
+        Ok(LevelItem {
+            level: u32::from_be_bytes(<[u8; 4]>::try_from(&bytes[..4]).unwrap()),
+            index: u64::from_be_bytes(<[u8; 8]>::try_from(&bytes[4..]).unwrap()),
+        })
+    }
+}
+
+fn hash_descriptor(name: &'static str) -> ColumnFamilyDescriptor {
+    use rocksdb::{Options, SliceTransform};
+
+    let mut cf_opts = Options::default();
+    cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(32));
+    cf_opts.set_memtable_prefix_bloom_ratio(0.2);
+    ColumnFamilyDescriptor::new(name, cf_opts)
+}
+
+pub struct BlockSchema;
+
+impl KeyValueSchema for BlockSchema {
+    type Key = HashItem;
+    type Value = ();
+}
+
+impl RocksDbKeyValueSchema for BlockSchema {
+    fn descriptor(_cache: &Cache) -> ColumnFamilyDescriptor {
+        hash_descriptor(Self::name())
+    }
+
+    fn name() -> &'static str {
+        "message_block_secondary_index"
+    }
+}
+
+pub struct OperationSchema;
+
+impl KeyValueSchema for OperationSchema {
+    type Key = HashItem;
+    type Value = ();
+}
+
+impl RocksDbKeyValueSchema for OperationSchema {
+    fn descriptor(_cache: &Cache) -> ColumnFamilyDescriptor {
+        hash_descriptor(Self::name())
+    }
+
+    fn name() -> &'static str {
+        "message_operation_secondary_index"
+    }
+}
+
+pub struct LevelSchema;
+
+impl KeyValueSchema for LevelSchema {
+    type Key = LevelItem;
+    type Value = ();
+}
+
+impl RocksDbKeyValueSchema for LevelSchema {
+    fn name() -> &'static str {
+        "message_level_secondary_index"
+    }
+}
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: MIT
 
 use super::{
+    connection, peer,
     common::{MessageType, Sender, Initiator},
     node_log::LogLevel,
 };
@@ -10,5 +11,8 @@ pub mod message_ty;
 pub mod message_sender;
 pub mod message_initiator;
 pub mod message_addr;
+pub mod message_cn;
+pub mod peer_cn;
+pub mod message_ref;
 pub mod timestamp;
 pub mod log_level;
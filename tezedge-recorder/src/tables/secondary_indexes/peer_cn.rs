@@ -0,0 +1,66 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryFrom;
+use storage::persistent::{
+    KeyValueSchema, Encoder, Decoder, SchemaError, database::RocksDbKeyValueSchema,
+};
+use rocksdb::{ColumnFamilyDescriptor, Cache};
+use super::{connection, peer};
+
+/// The connections of the peer
+/// * bytes layout: `[peer_id(16)][cn_ts(8)][cn_ts_nanos(4)]`
+pub struct Item {
+    pub peer: peer::Key,
+    pub cn_id: connection::Key,
+}
+
+impl Encoder for Item {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        let mut v = Vec::with_capacity(28);
+
+        v.extend_from_slice(&self.peer.0);
+        v.extend_from_slice(&self.cn_id.ts.to_be_bytes());
+        v.extend_from_slice(&self.cn_id.ts_nanos.to_be_bytes());
+
+        Ok(v)
+    }
+}
+
+impl Decoder for Item {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if bytes.len() != 28 {
+            return Err(SchemaError::DecodeError);
+        }
+
+        Ok(Item {
+            peer: peer::Key(TryFrom::try_from(&bytes[..16]).unwrap()),
+            cn_id: connection::Key {
+                ts: u64::from_be_bytes(TryFrom::try_from(&bytes[16..24]).unwrap()),
+                ts_nanos: u32::from_be_bytes(TryFrom::try_from(&bytes[24..]).unwrap()),
+            },
+        })
+    }
+}
+
+pub struct Schema;
+
+impl KeyValueSchema for Schema {
+    type Key = Item;
+    type Value = ();
+}
+
+impl RocksDbKeyValueSchema for Schema {
+    fn descriptor(_cache: &Cache) -> ColumnFamilyDescriptor {
+        use rocksdb::{Options, SliceTransform};
+
+        let mut cf_opts = Options::default();
+        cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(16));
+        cf_opts.set_memtable_prefix_bloom_ratio(0.2);
+        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    }
+
+    fn name() -> &'static str {
+        "peer_connection_secondary_index"
+    }
+}
@@ -6,6 +6,10 @@ use storage::persistent::{
     KeyValueSchema, Encoder, Decoder, SchemaError, database::RocksDbKeyValueSchema,
 };
 
+/// The messages are summarized in the time window, this is the maximal length
+/// of the window in milliseconds
+pub const MAX_TIME_SPAN: u64 = 3_600_000;
+
 /// * bytes layout: `[timestamp(8)][index(8)]`
 pub struct Item {
     pub timestamp: u64,
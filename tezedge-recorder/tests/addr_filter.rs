@@ -0,0 +1,91 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tezedge_recorder::database::AddrFilter;
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
+
+// the address is stored as ipv6, the ipv4 address is mapped
+fn mapped(s: &str) -> SocketAddr {
+    let addr = addr(s);
+    match addr.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(ip.to_ipv6_mapped().into(), addr.port()),
+        IpAddr::V6(_) => addr,
+    }
+}
+
+#[test]
+fn socket() {
+    let filter = "10.0.0.2:9732".parse::<AddrFilter>().unwrap();
+    assert!(filter.matches(&addr("10.0.0.2:9732")));
+    assert!(filter.matches(&mapped("10.0.0.2:9732")));
+    assert!(!filter.matches(&addr("10.0.0.2:9733")));
+    assert!(!filter.matches(&addr("10.0.0.3:9732")));
+}
+
+#[test]
+fn ip() {
+    let filter = "10.0.0.2".parse::<AddrFilter>().unwrap();
+    assert!(filter.matches(&addr("10.0.0.2:9732")));
+    assert!(filter.matches(&mapped("10.0.0.2:19732")));
+    assert!(!filter.matches(&addr("10.0.0.3:9732")));
+
+    let filter = "::ffff:10.0.0.2".parse::<AddrFilter>().unwrap();
+    assert!(filter.matches(&addr("10.0.0.2:9732")));
+
+    let filter = "2001:db8::1".parse::<AddrFilter>().unwrap();
+    assert!(filter.matches(&addr("[2001:db8::1]:9732")));
+    assert!(!filter.matches(&addr("[2001:db8::2]:9732")));
+}
+
+#[test]
+fn subnet() {
+    let filter = "10.0.0.0/8".parse::<AddrFilter>().unwrap();
+    assert!(filter.matches(&addr("10.255.0.1:9732")));
+    assert!(filter.matches(&mapped("10.1.2.3:9732")));
+    assert!(!filter.matches(&addr("11.0.0.1:9732")));
+    assert!(!filter.matches(&addr("[2001:db8::1]:9732")));
+
+    let filter = "192.168.1.0/24".parse::<AddrFilter>().unwrap();
+    assert!(filter.matches(&addr("192.168.1.200:9732")));
+    assert!(!filter.matches(&addr("192.168.2.1:9732")));
+
+    let filter = "2001:db8::/32".parse::<AddrFilter>().unwrap();
+    assert!(filter.matches(&addr("[2001:db8:ffff::1]:9732")));
+    assert!(!filter.matches(&addr("[2001:db9::1]:9732")));
+    assert!(!filter.matches(&addr("10.0.0.1:9732")));
+}
+
+#[test]
+fn subnet_bounds() {
+    // zero length matches any address of the family
+    let filter = "0.0.0.0/0".parse::<AddrFilter>().unwrap();
+    assert!(filter.matches(&SocketAddr::new(Ipv4Addr::new(1, 2, 3, 4).into(), 1)));
+    assert!(!filter.matches(&addr("[::1]:1")));
+
+    // full length matches the single address
+    let filter = "10.0.0.2/32".parse::<AddrFilter>().unwrap();
+    assert!(filter.matches(&addr("10.0.0.2:1")));
+    assert!(!filter.matches(&addr("10.0.0.3:1")));
+
+    let filter = "::1/128".parse::<AddrFilter>().unwrap();
+    assert!(filter.matches(&addr("[::1]:1")));
+    assert!(!filter.matches(&addr("[::2]:1")));
+}
+
+#[test]
+fn invalid() {
+    for s in &[
+        "",
+        "10.0.0",
+        "10.0.0.0/33",
+        "2001:db8::/129",
+        "10.0.0.0/x",
+        "host:9732",
+    ] {
+        assert!(s.parse::<AddrFilter>().is_err(), "{} must be rejected", s);
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::net::SocketAddr;
+use tezedge_recorder::{
+    crypto::{blake2b, hash::HashType},
+    common::{Initiator, MessageType, MessageKind, Sender},
+    database::ConnectionChunks,
+    export::anonymize::Anonymizer,
+    tables::{
+        chunk, connection,
+        message::MessageBuilder,
+        node_log::{ItemWithId, LogLevel},
+    },
+};
+
+const SECRET: [u8; 32] = [7; 32];
+const REMOTE_PK: [u8; 32] = [0x11; 32];
+const LOCAL_PK: [u8; 32] = [0x22; 32];
+const REMOTE_ADDR: &str = "10.0.0.2:19732";
+
+fn peer_id(pk: &[u8; 32]) -> String {
+    let hash = blake2b::digest_128(pk).unwrap();
+    HashType::CryptoboxPublicKeyHash
+        .hash_to_b58check(&hash)
+        .unwrap()
+}
+
+/// `[length(2)][port(2)][public_key(32)][proof_of_work(24)][nonce(24)][version]`
+fn connection_message(port: u16, pk: &[u8; 32]) -> Vec<u8> {
+    let mut plain = Vec::new();
+    plain.extend_from_slice(&port.to_be_bytes());
+    plain.extend_from_slice(pk);
+    plain.extend_from_slice(&[0; 48]);
+    let chain_name = b"TEZOS_MAINNET";
+    plain.extend_from_slice(&(chain_name.len() as u32).to_be_bytes());
+    plain.extend_from_slice(chain_name);
+    plain.extend_from_slice(&[0, 0, 0, 1]);
+    let mut bytes = (plain.len() as u16).to_be_bytes().to_vec();
+    bytes.extend_from_slice(&plain);
+    bytes
+}
+
+/// The `advertise` message, each point is `[length(4)][text]`
+fn advertise(points: &[&str]) -> Vec<u8> {
+    let mut body = 0x03u16.to_be_bytes().to_vec();
+    for point in points {
+        body.extend_from_slice(&(point.len() as u32).to_be_bytes());
+        body.extend_from_slice(point.as_bytes());
+    }
+    let mut plain = (body.len() as u32).to_be_bytes().to_vec();
+    plain.extend_from_slice(&body);
+    plain
+}
+
+fn connection_chunks() -> ConnectionChunks {
+    let mut connection = connection::Item::new(
+        Initiator::new(true),
+        REMOTE_ADDR.parse().unwrap(),
+        1617005682_953928051,
+    );
+    connection.set_peer_pk(REMOTE_PK);
+
+    let chunk = |incoming, counter, bytes: Vec<u8>, plain: Vec<u8>, ty| {
+        let item = chunk::Item::new(
+            connection.key(),
+            Sender::new(incoming),
+            counter,
+            bytes,
+            plain,
+        );
+        let (key, value) = item.split();
+        (key, value, Some(ty))
+    };
+    let remote_cm = connection_message(19732, &REMOTE_PK);
+    let local_cm = connection_message(9732, &LOCAL_PK);
+    let points = advertise(&[REMOTE_ADDR, "192.168.100.7:9732"]);
+    let chunks = vec![
+        chunk(
+            true,
+            0,
+            remote_cm.clone(),
+            remote_cm[2..].to_vec(),
+            MessageType::Connection,
+        ),
+        chunk(
+            false,
+            0,
+            local_cm.clone(),
+            local_cm[2..].to_vec(),
+            MessageType::Connection,
+        ),
+        chunk(
+            true,
+            3,
+            vec![0; points.len() + 18],
+            points,
+            MessageType::P2p(MessageKind::Advertise),
+        ),
+    ];
+    let message = MessageBuilder::connection_message().build(
+        &Sender::Remote,
+        &connection,
+        &remote_cm[2..],
+        1617005683_000000000,
+    );
+
+    ConnectionChunks {
+        connection,
+        chunks,
+        messages: vec![(0, message)],
+    }
+}
+
+fn log(id: u64, hostname: &str, message: String) -> ItemWithId {
+    ItemWithId {
+        id,
+        level: LogLevel::Info,
+        timestamp: 1617005683_000000000,
+        section: "p2p".to_string(),
+        message,
+        hostname: hostname.to_string(),
+    }
+}
+
+#[test]
+fn connection() {
+    let mut c = connection_chunks();
+    Anonymizer::new(SECRET).connection_chunks(&mut c);
+
+    let original = REMOTE_ADDR.parse::<SocketAddr>().unwrap();
+    let remote_addr = c.connection.remote_addr;
+    assert_ne!(remote_addr.ip(), original.ip());
+    assert_ne!(remote_addr.port(), original.port());
+    // the textual length is the same
+    assert_eq!(remote_addr.to_string().len(), REMOTE_ADDR.len());
+
+    let peer_pk = c.connection.peer_pk();
+    assert_ne!(peer_pk, REMOTE_PK);
+
+    // the connection message of the peer carries its pseudonym and the pseudonym of its port
+    let (_, remote_cm, _) = &c.chunks[0];
+    assert_eq!(remote_cm.bytes[2..], remote_cm.plain[..]);
+    assert_eq!(remote_cm.plain[2..34], peer_pk);
+    let port = u16::from_be_bytes([remote_cm.plain[0], remote_cm.plain[1]]);
+    assert_eq!(port, remote_addr.port());
+
+    let (_, local_cm, _) = &c.chunks[1];
+    assert_eq!(local_cm.bytes[2..], local_cm.plain[..]);
+    assert_ne!(local_cm.plain[2..34], LOCAL_PK);
+    assert_ne!(local_cm.plain[2..34], peer_pk);
+
+    // the advertised point is replaced in place by the same pseudonym
+    let (_, points, _) = &c.chunks[2];
+    let expected = advertise(&[&remote_addr.to_string(), "192.168.100.7:9732"]);
+    assert_eq!(points.plain.len(), expected.len());
+    assert_eq!(
+        points.plain[..(4 + 2 + 4 + REMOTE_ADDR.len())],
+        expected[..(4 + 2 + 4 + REMOTE_ADDR.len())]
+    );
+    let text = String::from_utf8_lossy(&points.plain);
+    assert!(!text.contains(REMOTE_ADDR));
+    assert!(!text.contains("192.168.100.7"));
+
+    let (_, message) = &c.messages[0];
+    assert_eq!(message.remote_addr, remote_addr);
+    assert!(message.preview.is_some());
+}
+
+#[test]
+fn deterministic() {
+    let mut a = connection_chunks();
+    Anonymizer::new(SECRET).connection_chunks(&mut a);
+    let mut b = connection_chunks();
+    Anonymizer::new(SECRET).connection_chunks(&mut b);
+    assert_eq!(a.connection.remote_addr, b.connection.remote_addr);
+    assert_eq!(a.connection.peer_pk(), b.connection.peer_pk());
+
+    let mut c = connection_chunks();
+    Anonymizer::new([8; 32]).connection_chunks(&mut c);
+    assert_ne!(a.connection.peer_pk(), c.connection.peer_pk());
+}
+
+#[test]
+fn logs() {
+    let mut anonymizer = Anonymizer::new(SECRET);
+    let mut c = connection_chunks();
+    anonymizer.connection_chunks(&mut c);
+
+    let remote_peer_id = peer_id(&REMOTE_PK);
+    let mut logs = vec![
+        log(
+            0,
+            "",
+            format!(
+                "connected to {} peer_id: {}, on node-7",
+                REMOTE_ADDR, remote_peer_id
+            ),
+        ),
+        log(
+            1,
+            "node-7",
+            format!(
+                "public key {}, address 172.16.0.1, again {}.",
+                hex::encode(REMOTE_PK),
+                REMOTE_ADDR
+            ),
+        ),
+    ];
+    let original = logs.iter().map(|l| l.message.clone()).collect::<Vec<_>>();
+    anonymizer.logs(&mut logs);
+
+    let remote_addr = c.connection.remote_addr.to_string();
+    let pseudonym_id = peer_id(&c.connection.peer_pk());
+
+    // the host name of the second record is collected before the first record is replaced
+    assert_eq!(
+        logs[0].message,
+        format!(
+            "connected to {} peer_id: {}, on host0",
+            remote_addr, pseudonym_id
+        )
+    );
+    assert_eq!(logs[1].hostname, "host0");
+    assert!(logs[1]
+        .message
+        .contains(&hex::encode(c.connection.peer_pk())));
+    assert!(logs[1].message.contains(&format!("again {}.", remote_addr)));
+    assert!(!logs[1].message.contains("172.16.0.1"));
+    assert_eq!(logs[1].message.len(), original[1].len());
+    assert_eq!(logs[0].section, "p2p");
+}
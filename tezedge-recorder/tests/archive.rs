@@ -0,0 +1,144 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::net::SocketAddr;
+use tezedge_recorder::{
+    common::{Initiator, MessageKind, Sender},
+    database::{
+        rocks::Db, ConnectionsFilter, Database, DatabaseFetch, DatabaseNew, DiscoveryFilter,
+        ExportFilter, MessagesFilter,
+    },
+    export::archive,
+    tables::{
+        chunk::{self, ChunkPayload},
+        connection,
+        message::MessageBuilder,
+    },
+};
+
+const ADVERTISE: u16 = 0x03;
+const BOOTSTRAP: u16 = 0x02;
+
+/// `[length(4)][tag(2)][body]`
+fn p2p(tag: u16, body: &[u8]) -> Vec<u8> {
+    let mut plain = ((body.len() + 2) as u32).to_be_bytes().to_vec();
+    plain.extend_from_slice(&tag.to_be_bytes());
+    plain.extend_from_slice(body);
+    plain
+}
+
+/// The list of the addresses, each is a string with its length
+fn advertise(points: &[&str]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for point in points {
+        body.extend_from_slice(&(point.len() as u32).to_be_bytes());
+        body.extend_from_slice(point.as_bytes());
+    }
+    p2p(ADVERTISE, &body)
+}
+
+/// Store the message of the peer consisting of the single chunk
+fn store(db: &Db, cn: &connection::Item, counter: u64, plain: Vec<u8>, timestamp: u64) {
+    let mut bytes = (plain.len() as u16).to_be_bytes().to_vec();
+    bytes.extend_from_slice(&plain);
+    let chunk = chunk::Item::new(cn.key(), Sender::Remote, counter, bytes, plain.clone());
+    db.store_chunk(chunk);
+    let mut header = [0; 6];
+    header.clone_from_slice(&plain[..6]);
+    let message = MessageBuilder::peer_message(header, counter)
+        .link_chunk(plain.len())
+        .ok()
+        .unwrap()
+        .build(&Sender::Remote, cn, &plain, timestamp);
+    db.store_message(message);
+}
+
+fn open(path: &std::path::Path) -> Db {
+    Db::open(path, false, None, None, ChunkPayload::Both).unwrap()
+}
+
+fn advertised(db: &Db) -> Vec<SocketAddr> {
+    let filter = DiscoveryFilter {
+        limit: None,
+        from: None,
+        to: None,
+        peer_id: None,
+    };
+    let graph = db.fetch_discovery(&filter).unwrap();
+    graph.addresses.iter().map(|a| a.address).collect()
+}
+
+#[test]
+fn round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = open(&dir.path().join("source"));
+    let cn = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        1617005682_953928051,
+    );
+    source.store_connection(cn.clone());
+    let points = ["10.0.0.5:9732", "10.0.0.6:19732"];
+    store(&source, &cn, 3, p2p(BOOTSTRAP, &[]), 1617005683_000000000);
+    store(&source, &cn, 4, advertise(&points), 1617005684_000000000);
+    let expected = points
+        .iter()
+        .map(|p| p.parse::<SocketAddr>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(advertised(&source), expected);
+
+    let filter = ExportFilter {
+        cn: None,
+        from: None,
+        to: None,
+        limit: None,
+    };
+    let mut data = Vec::new();
+    let manifest = archive::write(&mut data, &source, &filter, None).unwrap();
+    assert_eq!(manifest.connections, 1);
+    assert_eq!(manifest.chunks, 2);
+    assert_eq!(manifest.messages, 2);
+
+    let target = open(&dir.path().join("target"));
+    assert!(archive::read(&b"not an archive"[..], &target).is_err());
+    let manifest = archive::read(data.as_slice(), &target).unwrap();
+    assert_eq!(manifest.messages, 2);
+
+    let connections = target
+        .fetch_connections(&ConnectionsFilter::default())
+        .unwrap();
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].0.to_string(), cn.key().to_string());
+
+    let filter = MessagesFilter {
+        direction: Some("forward".to_string()),
+        ..MessagesFilter::default()
+    };
+    let kinds = target
+        .fetch_messages(&filter)
+        .unwrap()
+        .into_iter()
+        .map(|m| m.kind)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![Some(MessageKind::Bootstrap), Some(MessageKind::Advertise)],
+    );
+    let key = chunk::Key {
+        cn_id: cn.key(),
+        counter: 4,
+        sender: Sender::Remote,
+    };
+    assert_eq!(
+        target.fetch_chunk(&key).unwrap().unwrap().plain,
+        advertise(&points),
+    );
+
+    // the advertised addresses are not in the archive, but decoded again
+    assert_eq!(advertised(&target), expected);
+
+    // the connection is already there, nothing is loaded
+    assert!(archive::read(data.as_slice(), &target).is_err());
+    let messages = target.fetch_messages(&MessagesFilter::default()).unwrap();
+    assert_eq!(messages.len(), 2);
+}
@@ -0,0 +1,100 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use tezedge_recorder::{
+    common::{Initiator, Sender},
+    database::{rocks::Db, BlocksFilter, Database, DatabaseFetch, DatabaseNew},
+    tables::{
+        chunk::ChunkPayload,
+        connection,
+        message::{MessageBuilder, Refs},
+    },
+};
+
+const CURRENT_HEAD: u16 = 0x14;
+const HOUR: u64 = 3_600_000;
+const START: u64 = 1617005683_000;
+
+/// The `current_head` `[length(4)][tag(2)]` at `timestamp` in milliseconds
+fn announce(db: &Db, cn: &connection::Item, timestamp: u64, hash: [u8; 32], level: u32) {
+    let mut plain = [0, 0, 0, 2, 0, 0];
+    plain[4..].clone_from_slice(&CURRENT_HEAD.to_be_bytes());
+    let mut message = MessageBuilder::peer_message(plain, 0)
+        .link_chunk(plain.len())
+        .ok()
+        .unwrap()
+        .build(&Sender::Remote, cn, &plain, timestamp * 1_000_000);
+    message.refs = Refs {
+        blocks: vec![hash],
+        levels: vec![level],
+        ..Refs::default()
+    };
+    db.store_message(message);
+}
+
+fn levels(db: &Db, from: Option<u64>, to: Option<u64>) -> (usize, Option<u32>, Option<u32>) {
+    let filter = BlocksFilter {
+        from,
+        to,
+        ..BlocksFilter::default()
+    };
+    let summary = db.fetch_blocks_summary(&filter).unwrap();
+    (summary.levels, summary.first_level, summary.last_level)
+}
+
+#[test]
+fn window() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path(), false, None, None, ChunkPayload::Both).unwrap();
+    let peer = |ip: &str, ts: u64| {
+        let cn = connection::Item::new(Initiator::new(true), ip.parse().unwrap(), ts);
+        db.store_connection(cn.clone());
+        cn
+    };
+    let first = peer("10.0.0.2:9732", 1617005682_000000000);
+    let second = peer("10.0.0.3:9732", 1617005682_500000000);
+
+    announce(&db, &first, START, [0xa; 32], 10);
+    announce(&db, &second, START + 500, [0xa; 32], 10);
+    announce(&db, &first, START + 1000, [0xb; 32], 11);
+    announce(&db, &first, START + 2 * HOUR, [0xc; 32], 12);
+
+    // the hour before the newest message
+    assert_eq!(levels(&db, None, None), (1, Some(12), Some(12)));
+
+    // the missing end is an hour away from the other one
+    assert_eq!(levels(&db, Some(START), None), (2, Some(10), Some(11)));
+    assert_eq!(
+        levels(&db, None, Some(START + 2 * HOUR + 1)),
+        (1, Some(12), Some(12)),
+    );
+    assert_eq!(
+        levels(&db, Some(START + 1), Some(START + 1001)),
+        (2, Some(10), Some(11)),
+    );
+
+    // the range is at most an hour
+    let filter = BlocksFilter {
+        from: Some(START),
+        to: Some(START + HOUR + 1),
+        ..BlocksFilter::default()
+    };
+    assert!(db.fetch_blocks_summary(&filter).is_err());
+
+    let filter = BlocksFilter {
+        limit: Some(1),
+        from: Some(START),
+        to: None,
+    };
+    let summary = db.fetch_blocks_summary(&filter).unwrap();
+    assert_eq!((summary.levels, summary.blocks), (1, 1));
+
+    let report = db.fetch_block_report(10).unwrap();
+    assert_eq!(report.blocks.len(), 1);
+    let delays = report.blocks[0]
+        .announcements
+        .iter()
+        .map(|a| a.delay)
+        .collect::<Vec<_>>();
+    assert_eq!(delays, [0, 500]);
+}
@@ -0,0 +1,74 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{fs::OpenOptions, io::Write, path::Path, sync::atomic::AtomicBool};
+use bpf_recorder::{EventId, SnifferEvent, SocketId};
+use tezedge_recorder::capture::{CaptureFile, CaptureSource, CaptureWriter};
+
+fn data(fd: u32, data: &[u8]) -> SnifferEvent {
+    SnifferEvent::Data {
+        id: EventId::new(SocketId { pid: 1, fd }, 0, 0),
+        data: data.to_vec(),
+        net: true,
+        incoming: true,
+    }
+}
+
+fn write(path: &Path, event: &SnifferEvent) {
+    let mut writer = CaptureWriter::create(path).unwrap();
+    writer.write(event, 1617005682_953928051).unwrap();
+    writer.flush().unwrap();
+}
+
+fn read(path: &Path) -> Vec<Vec<u8>> {
+    let mut file = CaptureFile::open(path).unwrap();
+    let running = AtomicBool::new(true);
+    let mut data = Vec::new();
+    while let Ok(events) = file.read_events(&running) {
+        for event in events {
+            match event {
+                SnifferEvent::Data { data: bytes, .. } => data.push(bytes),
+                _ => panic!("unexpected event"),
+            }
+        }
+    }
+    data
+}
+
+#[test]
+fn append() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capture");
+    write(&path, &data(5, &[1, 2, 3]));
+    write(&path, &data(5, &[4, 5]));
+    assert_eq!(read(&path), vec![vec![1, 2, 3], vec![4, 5]]);
+}
+
+#[test]
+fn after_crash() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capture");
+    write(&path, &data(5, &[1, 2, 3]));
+
+    // the recorder was killed in the middle of the record
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&100u32.to_le_bytes()).unwrap();
+    file.write_all(&[0; 10]).unwrap();
+    drop(file);
+
+    // the incomplete record does not swallow the next one
+    write(&path, &data(5, &[4, 5]));
+    assert_eq!(read(&path), vec![vec![1, 2, 3], vec![4, 5]]);
+}
+
+#[test]
+fn incomplete_magic() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capture");
+    std::fs::write(&path, b"TZR").unwrap();
+    write(&path, &data(5, &[1]));
+    assert_eq!(read(&path), vec![vec![1]]);
+
+    std::fs::write(&path, b"not a capture").unwrap();
+    assert!(CaptureWriter::create(&path).is_err());
+}
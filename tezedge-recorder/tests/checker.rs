@@ -0,0 +1,107 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::path::Path;
+use storage::persistent::Encoder;
+use tezedge_recorder::{
+    common::{Initiator, Sender},
+    database::{rocks::Db, Database, DatabaseFetch, DatabaseNew, MessagesFilter, RequestsFilter},
+    tables::{
+        chunk::{self, ChunkPayload},
+        connection,
+        message::MessageBuilder,
+    },
+};
+
+fn open(path: &Path) -> Db {
+    Db::open(path, false, None, None, ChunkPayload::Both).unwrap()
+}
+
+fn cn() -> connection::Item {
+    connection::Item::new(
+        Initiator::new(false),
+        "10.0.0.2:9732".parse().unwrap(),
+        1617005682_953928051,
+    )
+}
+
+/// Write the connection as the recorder did before versioning was introduced,
+/// the layout of version 1 is the layout of version 2 without lifecycle
+fn write_v1(path: &Path) {
+    let mut opts = rocksdb::Options::default();
+    opts.create_if_missing(true);
+    opts.create_missing_column_families(true);
+    let db = rocksdb::DB::open_cf(&opts, path.join("rocksdb"), &["connection_storage"]).unwrap();
+    let connections = db.cf_handle("connection_storage").unwrap();
+    let (key, value) = cn().split();
+    let mut value = value.encode().unwrap();
+    value.truncate(88);
+    db.put_cf(connections, key.encode().unwrap(), value)
+        .unwrap();
+}
+
+/// The p2p message without body and its chunk
+fn store_message(db: &Db, cn: &connection::Item, kind: u16, incoming: bool, timestamp: u64) {
+    let mut plain = 2u32.to_be_bytes().to_vec();
+    plain.extend_from_slice(&kind.to_be_bytes());
+    let mut bytes = (plain.len() as u16).to_be_bytes().to_vec();
+    bytes.extend_from_slice(&plain);
+    let chunk = chunk::Item::new(cn.key(), Sender::new(incoming), 3, bytes, plain.clone());
+    db.store_chunk(chunk);
+    let item = MessageBuilder::peer_message([0, 0, 0, 2, (kind >> 8) as u8, kind as u8], 3)
+        .link_chunk(plain.len())
+        .ok()
+        .unwrap()
+        .build(&Sender::new(incoming), cn, &plain, timestamp);
+    db.store_message(item);
+}
+
+const GET_CURRENT_BRANCH: u16 = 0x10;
+const CURRENT_BRANCH: u16 = 0x11;
+
+#[test]
+fn outdated_layout() {
+    let dir = tempfile::tempdir().unwrap();
+    write_v1(dir.path());
+
+    // the check does not fail, it reports what needs migration
+    let report = Db::open_read_only(dir.path()).unwrap().check().unwrap();
+    assert_eq!(report.needs_migration.get("connection_storage"), Some(&1));
+    // absent
+    assert_eq!(report.needs_migration.get("message_storage"), Some(&0));
+    assert!(!report.is_consistent());
+
+    // migrated on open
+    drop(open(dir.path()));
+    let report = Db::open_read_only(dir.path()).unwrap().check().unwrap();
+    assert!(report.needs_migration.is_empty());
+    assert!(report.is_consistent());
+}
+
+#[test]
+fn rebuild() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path());
+    let cn = cn();
+    db.store_connection(cn.clone());
+    store_message(&db, &cn, GET_CURRENT_BRANCH, false, 1617005683_000000000);
+    store_message(&db, &cn, CURRENT_BRANCH, true, 1617005683_250000000);
+
+    db.rebuild_indexes().unwrap();
+    let report = db.check().unwrap();
+    assert!(report.is_consistent());
+    assert_eq!(report.messages, 2);
+
+    let filter = MessagesFilter {
+        cn: Some(cn.key().to_string()),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(db.fetch_messages(&filter).unwrap().len(), 2);
+
+    // the derived table is built again
+    let requests = db.fetch_requests(&RequestsFilter::default()).unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].id, 0);
+    assert_eq!(requests[0].response, Some(1));
+    assert_eq!(requests[0].rtt, Some(250));
+}
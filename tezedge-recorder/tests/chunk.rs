@@ -0,0 +1,105 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use storage::persistent::{Encoder, Decoder};
+use tezedge_recorder::{
+    common::Sender,
+    tables::{
+        chunk::{self, ChunkPayload},
+        connection,
+    },
+};
+
+fn item(bytes: Vec<u8>, plain: Vec<u8>) -> chunk::Item {
+    let cn_id = connection::Key {
+        ts: 1617005682,
+        ts_nanos: 953928051,
+    };
+    let mut item = chunk::Item::new(cn_id, Sender::Remote, 15, bytes, plain);
+    item.set_timestamp(1617005690_123456789);
+    item
+}
+
+#[test]
+fn round_trip() {
+    let bytes = (0..0x1000).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+    let plain = (0..0x0fee).map(|i| (i % 13) as u8).collect::<Vec<_>>();
+    let (key, value) = item(bytes.clone(), plain.clone()).split();
+
+    let encoded = value.encode().unwrap();
+    // the payload is repetitive, zstd must shrink it
+    assert!(encoded.len() < bytes.len() + plain.len());
+
+    let decoded = chunk::Value::decode(&encoded).unwrap();
+    assert_eq!(decoded.bytes, bytes);
+    assert_eq!(decoded.plain, plain);
+    assert_eq!(decoded.timestamp(), 1617005690);
+
+    let decoded_key = chunk::Key::decode(&key.encode().unwrap()).unwrap();
+    assert_eq!(decoded_key.to_string(), "1617005682.953928051-remote-15");
+    assert_eq!(
+        decoded_key
+            .to_string()
+            .parse::<chunk::Key>()
+            .unwrap()
+            .counter,
+        15
+    );
+}
+
+#[test]
+fn round_trip_empty() {
+    let (_, value) = item(vec![], vec![]).split();
+    let decoded = chunk::Value::decode(&value.encode().unwrap()).unwrap();
+    assert!(decoded.bytes.is_empty());
+    assert!(decoded.plain.is_empty());
+}
+
+#[test]
+fn decode_corrupted() {
+    let (_, value) = item(vec![1, 2, 3], vec![4, 5]).split();
+    let mut encoded = value.encode().unwrap();
+    assert!(chunk::Value::decode(&encoded[..8]).is_err());
+    let length = encoded.len();
+    encoded.truncate(length - 4);
+    assert!(chunk::Value::decode(&encoded).is_err());
+}
+
+#[test]
+fn decode_v1() {
+    // `[timestamp(8)][bytes_len(8)][net(1)][bytes][plain]`
+    let mut v1 = Vec::new();
+    v1.extend_from_slice(&1617005690u64.to_le_bytes());
+    v1.extend_from_slice(&3u64.to_le_bytes());
+    v1.push(1);
+    v1.extend_from_slice(&[1, 2, 3]);
+    v1.extend_from_slice(&[4, 5]);
+
+    let value = chunk::Value::decode_v1(&v1).unwrap();
+    assert_eq!(value.timestamp(), 1617005690);
+    assert_eq!(value.bytes, [1, 2, 3]);
+    assert_eq!(value.plain, [4, 5]);
+
+    // the length of the bytes exceeds the value
+    v1[8] = 10;
+    assert!(chunk::Value::decode_v1(&v1).is_err());
+}
+
+#[test]
+fn retain() {
+    let check = |payload, bytes: &[u8], plain: &[u8]| {
+        let mut item = item(vec![1, 2, 3], vec![4, 5]);
+        item.retain(payload);
+        assert_eq!(item.bytes, bytes);
+        assert_eq!(item.plain, plain);
+
+        let (_, value) = item.split();
+        let value = chunk::Value::decode(&value.encode().unwrap()).unwrap();
+        assert_eq!(value.bytes, bytes);
+        assert_eq!(value.plain, plain);
+    };
+
+    check(ChunkPayload::Both, &[1, 2, 3], &[4, 5]);
+    check(ChunkPayload::Plain, &[], &[4, 5]);
+    check(ChunkPayload::Cipher, &[1, 2, 3], &[]);
+}
@@ -0,0 +1,191 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use tezedge_recorder::{
+    analytics::conformance::{self, MAX_MESSAGE_SIZE},
+    common::{Initiator, Sender},
+    tables::{
+        connection::{self, Violations},
+        message::{self, MessageBuilder},
+    },
+};
+
+fn cn() -> connection::Item {
+    connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        1617005682_953928051,
+    )
+}
+
+fn ack(cn: &connection::Item, incoming: bool, plain: &[u8]) -> message::Item {
+    MessageBuilder::acknowledge_message().build(
+        &Sender::new(incoming),
+        cn,
+        plain,
+        1617005683_000000000,
+    )
+}
+
+/// The p2p message without body
+fn p2p(cn: &connection::Item, incoming: bool, tag: u16) -> (message::Item, Vec<u8>) {
+    let mut plain = 2u32.to_be_bytes().to_vec();
+    plain.extend_from_slice(&tag.to_be_bytes());
+    let mut header = [0; 6];
+    header.clone_from_slice(&plain);
+    let item = MessageBuilder::peer_message(header, 3)
+        .link_chunk(plain.len())
+        .ok()
+        .unwrap()
+        .build(&Sender::new(incoming), cn, &plain, 1617005684_000000000);
+    (item, plain)
+}
+
+fn check(cn: &mut connection::Item, message: &message::Item, plain: &[u8]) {
+    let (comments, state) = cn.conformance();
+    conformance::check_message(comments, state, message, Some(plain));
+}
+
+fn violations(cn: &mut connection::Item, incoming: bool) -> Violations {
+    let (comments, _) = cn.conformance();
+    if incoming {
+        comments.incoming_violations
+    } else {
+        comments.outgoing_violations
+    }
+}
+
+const DISCONNECT: u16 = 0x01;
+const BOOTSTRAP: u16 = 0x02;
+
+#[test]
+fn acked() {
+    let mut cn = cn();
+    for &incoming in &[true, false] {
+        let message = ack(&cn, incoming, &[0x00]);
+        check(&mut cn, &message, &[0x00]);
+        let (message, plain) = p2p(&cn, incoming, BOOTSTRAP);
+        check(&mut cn, &message, &plain);
+    }
+    assert!(violations(&mut cn, true).is_empty());
+    assert!(violations(&mut cn, false).is_empty());
+}
+
+#[test]
+fn before_ack() {
+    let mut cn = cn();
+
+    // the remote side skips the `ack`, its p2p message is in place of it
+    let (_, plain) = p2p(&cn, true, BOOTSTRAP);
+    let message = ack(&cn, true, &plain);
+    check(&mut cn, &message, &plain);
+    assert!(violations(&mut cn, true).contains(Violations::BEFORE_ACK));
+
+    // the local side sends the p2p message before its `ack`
+    let (message, plain) = p2p(&cn, false, BOOTSTRAP);
+    check(&mut cn, &message, &plain);
+    assert!(violations(&mut cn, false).contains(Violations::BEFORE_ACK));
+}
+
+#[test]
+fn garbage_in_place_of_ack() {
+    let mut cn = cn();
+    let message = ack(&cn, true, &[0x42]);
+    check(&mut cn, &message, &[0x42]);
+    // not a p2p message, cannot tell
+    assert!(violations(&mut cn, true).is_empty());
+}
+
+#[test]
+fn ack_not_decrypted() {
+    let mut cn = cn();
+    let message = ack(&cn, true, &[]);
+    {
+        let (comments, state) = cn.conformance();
+        conformance::check_message(comments, state, &message, None);
+    }
+    let (message, plain) = p2p(&cn, true, BOOTSTRAP);
+    check(&mut cn, &message, &plain);
+    assert!(violations(&mut cn, true).is_empty());
+}
+
+#[test]
+fn nack() {
+    let mut cn = cn();
+    let message = ack(&cn, false, &[0xff]);
+    check(&mut cn, &message, &[0xff]);
+    assert!(violations(&mut cn, false).contains(Violations::NACK));
+
+    // the other side continues regardless
+    let message = ack(&cn, true, &[0x00]);
+    check(&mut cn, &message, &[0x00]);
+    let (message, plain) = p2p(&cn, true, BOOTSTRAP);
+    check(&mut cn, &message, &plain);
+    let incoming = violations(&mut cn, true);
+    assert!(incoming.contains(Violations::AFTER_NACK));
+    assert!(!incoming.contains(Violations::NACK));
+    assert!(!incoming.contains(Violations::BEFORE_ACK));
+}
+
+#[test]
+fn after_disconnect() {
+    let mut cn = cn();
+    for &incoming in &[true, false] {
+        let message = ack(&cn, incoming, &[0x00]);
+        check(&mut cn, &message, &[0x00]);
+    }
+
+    let (message, plain) = p2p(&cn, false, DISCONNECT);
+    check(&mut cn, &message, &plain);
+    // the `disconnect` itself is fine
+    assert!(violations(&mut cn, false).is_empty());
+
+    // any message of any side after it is a violation
+    for &incoming in &[true, true, false] {
+        let (message, plain) = p2p(&cn, incoming, BOOTSTRAP);
+        check(&mut cn, &message, &plain);
+    }
+    assert!(violations(&mut cn, true).contains(Violations::AFTER_DISCONNECT));
+    assert!(violations(&mut cn, false).contains(Violations::AFTER_DISCONNECT));
+}
+
+#[test]
+fn unknown_kind() {
+    let mut cn = cn();
+    let message = ack(&cn, true, &[0x00]);
+    check(&mut cn, &message, &[0x00]);
+    let (message, plain) = p2p(&cn, true, 0x99);
+    check(&mut cn, &message, &plain);
+    // the only violation
+    let mut expected = Violations::default();
+    expected.insert(Violations::UNKNOWN_KIND);
+    assert_eq!(violations(&mut cn, true), expected);
+}
+
+#[test]
+fn too_big() {
+    let mut cn = cn();
+    let message = ack(&cn, true, &[0x00]);
+    check(&mut cn, &message, &[0x00]);
+
+    let (mut message, plain) = p2p(&cn, true, BOOTSTRAP);
+    message.size = MAX_MESSAGE_SIZE;
+    check(&mut cn, &message, &plain);
+    assert!(violations(&mut cn, true).is_empty());
+
+    message.size = MAX_MESSAGE_SIZE + 1;
+    check(&mut cn, &message, &plain);
+    assert!(violations(&mut cn, true).contains(Violations::TOO_BIG));
+}
+
+#[test]
+fn stray_chunk() {
+    let mut cn = cn();
+    {
+        let (comments, _) = cn.conformance();
+        conformance::check_stray_chunk(comments, true, &[]);
+        conformance::check_stray_chunk(comments, false, &[0x00]);
+    }
+    assert!(violations(&mut cn, true).is_empty());
+    assert!(violations(&mut cn, false).contains(Violations::DUPLICATE_HANDSHAKE));
+}
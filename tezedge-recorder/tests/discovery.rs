@@ -0,0 +1,105 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::net::SocketAddr;
+use tezedge_recorder::{
+    common::{Initiator, Sender},
+    database::{rocks::Db, Database, DatabaseFetch, DatabaseNew, DiscoveryFilter},
+    tables::{chunk::ChunkPayload, connection, message::MessageBuilder},
+};
+
+const ADVERTISE: u16 = 0x03;
+
+/// The `advertise` `[length(4)][tag(2)]` at `timestamp` in milliseconds
+fn advertise(db: &Db, cn: &connection::Item, timestamp: u64, points: &[&str]) {
+    let mut plain = [0, 0, 0, 2, 0, 0];
+    plain[4..].clone_from_slice(&ADVERTISE.to_be_bytes());
+    let mut message = MessageBuilder::peer_message(plain, 0)
+        .link_chunk(plain.len())
+        .ok()
+        .unwrap()
+        .build(&Sender::Remote, cn, &plain, timestamp * 1_000_000);
+    message.refs.points = points.iter().map(|p| p.parse().unwrap()).collect();
+    db.store_message(message);
+}
+
+/// The node connects to the address at `timestamp` in nanoseconds
+fn dial(db: &Db, addr: &str, timestamp: u64) {
+    let cn = connection::Item::new(Initiator::new(false), addr.parse().unwrap(), timestamp);
+    db.store_connection(cn);
+}
+
+/// The advertised addresses and whether the node connected to them
+fn connected(db: &Db, limit: Option<u64>, to: Option<u64>) -> Vec<(SocketAddr, bool)> {
+    let filter = DiscoveryFilter {
+        limit,
+        to,
+        ..DiscoveryFilter::default()
+    };
+    db.fetch_discovery(&filter)
+        .unwrap()
+        .addresses
+        .into_iter()
+        .map(|a| (a.address, a.connected.is_some()))
+        .collect()
+}
+
+#[test]
+fn dials() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path(), false, None, None, ChunkPayload::Both).unwrap();
+    let cn = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        1617005682_000000000,
+    );
+    db.store_connection(cn.clone());
+
+    advertise(
+        &db,
+        &cn,
+        1617005683_000,
+        &["10.0.0.5:9732", "10.0.0.6:9732"],
+    );
+    dial(&db, "10.0.0.5:9732", 1617005683_500000000);
+    advertise(&db, &cn, 1617005690_000, &["10.0.0.7:9732"]);
+    dial(&db, "10.0.0.6:9732", 1617005700_000000000);
+
+    let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+
+    // the connections are looked for until the last advertisement
+    assert_eq!(
+        connected(&db, None, None),
+        [
+            (addr("10.0.0.5:9732"), true),
+            (addr("10.0.0.6:9732"), false),
+            (addr("10.0.0.7:9732"), false),
+        ],
+    );
+
+    // or until the end of the range
+    assert_eq!(
+        connected(&db, None, Some(1617005701_000)),
+        [
+            (addr("10.0.0.5:9732"), true),
+            (addr("10.0.0.6:9732"), true),
+            (addr("10.0.0.7:9732"), false),
+        ],
+    );
+    assert_eq!(
+        connected(&db, None, Some(1617005689_000)),
+        [
+            (addr("10.0.0.5:9732"), true),
+            (addr("10.0.0.6:9732"), false),
+        ],
+    );
+
+    // the limit cuts off the advertisements, and the connections after them
+    assert_eq!(
+        connected(&db, Some(1), None),
+        [
+            (addr("10.0.0.5:9732"), false),
+            (addr("10.0.0.6:9732"), false),
+        ],
+    );
+}
@@ -0,0 +1,93 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::path::Path;
+use tezedge_recorder::{
+    common::{Initiator, Sender},
+    database::{
+        rocks::Db, ConnectionsFilter, Database, DatabaseFetch, DatabaseNew, DatabaseRetention,
+        RetentionPolicy,
+    },
+    tables::{
+        chunk::{self, ChunkPayload},
+        connection::{self, CloseReason},
+    },
+};
+
+fn open(path: &Path) -> Db {
+    Db::open(path, false, None, None, ChunkPayload::Both).unwrap()
+}
+
+/// The connection with a chunk which never became a part of any message
+fn store(db: &Db, timestamp: u64, closed: bool) -> connection::Key {
+    let mut cn = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        timestamp,
+    );
+    let key = cn.key();
+    let chunk = chunk::Item::new(key.clone(), Sender::Remote, 0, vec![0, 1, 0], vec![0]);
+    if closed {
+        cn.close(CloseReason::Closed, timestamp + 1_000_000_000);
+    }
+    db.store_connection(cn);
+    db.store_chunk(chunk);
+    key
+}
+
+fn connections(db: &Db) -> Vec<String> {
+    db.fetch_connections(&ConnectionsFilter::default())
+        .unwrap()
+        .into_iter()
+        .map(|(key, _)| key.to_string())
+        .collect()
+}
+
+fn has_chunk(db: &Db, cn_id: &connection::Key) -> bool {
+    let key = chunk::Key {
+        cn_id: cn_id.clone(),
+        counter: 0,
+        sender: Sender::Remote,
+    };
+    db.fetch_chunk(&key).unwrap().is_some()
+}
+
+#[test]
+fn without_messages() {
+    let dir = tempfile::tempdir().unwrap();
+
+    // the recorder was killed, the connection is never closed
+    let db = open(dir.path());
+    let stale = store(&db, 1617005600_000000000, false);
+    drop(db);
+
+    let db = open(dir.path());
+    let closed = store(&db, 1617005682_000000000, true);
+    let live = store(&db, 1617005690_000000000, false);
+
+    let policy = RetentionPolicy {
+        message_max_age: Some(3600),
+        ..RetentionPolicy::default()
+    };
+    db.apply_retention(&policy);
+
+    // the connection which is still recorded stays, despite it is out of the window
+    assert_eq!(connections(&db), vec![live.to_string()]);
+    assert!(!has_chunk(&db, &stale));
+    assert!(!has_chunk(&db, &closed));
+    assert!(has_chunk(&db, &live));
+}
+
+#[test]
+fn without_window() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path());
+    let old = store(&db, 1617005682_000000000, true);
+    let newest = store(&db, 1617005690_000000000, true);
+
+    // nothing tells the window, the newest connection stays
+    db.apply_retention(&RetentionPolicy::default());
+    assert_eq!(connections(&db), vec![newest.to_string()]);
+    assert!(!has_chunk(&db, &old));
+    assert!(has_chunk(&db, &newest));
+}
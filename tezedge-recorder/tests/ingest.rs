@@ -0,0 +1,251 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    convert::TryInto,
+    fs,
+    net::SocketAddr,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    sync::{atomic::AtomicBool, atomic::Ordering, Arc},
+};
+use bpf_recorder::{EventId, SnifferEvent, SocketId};
+use tezedge_recorder::{
+    crypto::{
+        crypto_box::{CryptoKey, PrecomputedKey, PublicKey, SecretKey},
+        nonce::{generate_nonces, Nonce, NoncePair},
+    },
+    System,
+    capture::ChannelCapture,
+    common::{MessageCategory, MessageKind, MessageType},
+    database::{
+        rocks::Db, ConnectionsFilter, DatabaseFetch, DatabaseNew, ExportFilter, MessagesFilter,
+    },
+    main_loop,
+    tables::chunk::ChunkPayload,
+};
+
+const PORT: u16 = 9732;
+const PID: u32 = 1;
+const REMOTE_ADDR: &str = "10.0.0.2:9732";
+
+struct Identity {
+    public_key: [u8; 32],
+    secret_key: [u8; 32],
+    proof_of_work_stamp: Vec<u8>,
+}
+
+fn identity(json: &str) -> Identity {
+    let value = serde_json::from_str::<serde_json::Value>(json).unwrap();
+    let field = |name: &str| hex::decode(value[name].as_str().unwrap()).unwrap();
+    Identity {
+        public_key: field("public_key").as_slice().try_into().unwrap(),
+        secret_key: field("secret_key").as_slice().try_into().unwrap(),
+        proof_of_work_stamp: field("proof_of_work_stamp"),
+    }
+}
+
+/// `[length(2)][content]`
+fn chunk(content: &[u8]) -> Vec<u8> {
+    let mut bytes = (content.len() as u16).to_be_bytes().to_vec();
+    bytes.extend_from_slice(content);
+    bytes
+}
+
+fn connection_message(identity: &Identity, nonce: [u8; 24]) -> Vec<u8> {
+    let mut content = PORT.to_be_bytes().to_vec();
+    content.extend_from_slice(&identity.public_key);
+    content.extend_from_slice(&identity.proof_of_work_stamp);
+    content.extend_from_slice(&nonce);
+    let chain_name = b"TEZOS_MAINNET";
+    content.extend_from_slice(&(chain_name.len() as u32).to_be_bytes());
+    content.extend_from_slice(chain_name);
+    content.extend_from_slice(&[0, 0, 0, 1]);
+    chunk(&content)
+}
+
+fn encrypt(key: &PrecomputedKey, nonce: &mut Nonce, plain: &[u8]) -> Vec<u8> {
+    let content = key.encrypt(plain, nonce).unwrap();
+    *nonce = nonce.increment();
+    chunk(&content)
+}
+
+fn event_id(fd: u32) -> EventId {
+    EventId::new(SocketId { pid: PID, fd }, 0, 0)
+}
+
+fn data(incoming: bool, data: Vec<u8>) -> SnifferEvent {
+    SnifferEvent::Data {
+        id: event_id(5),
+        data,
+        net: true,
+        incoming,
+    }
+}
+
+/// The connection messages of the remote and the local side, and the secrets of the session
+fn handshake(local: &Identity, remote: &Identity) -> (Vec<u8>, Vec<u8>, PrecomputedKey, NoncePair) {
+    let remote_cm = connection_message(remote, [1; 24]);
+    let local_cm = connection_message(local, [2; 24]);
+
+    let pk = PublicKey::from_bytes(&remote.public_key).unwrap();
+    let sk = SecretKey::from_bytes(&local.secret_key).unwrap();
+    let key = PrecomputedKey::precompute(&pk, &sk);
+    let nonces = generate_nonces(&local_cm, &remote_cm, true).unwrap();
+    (remote_cm, local_cm, key, nonces)
+}
+
+/// The handshake, the metadata and the `ack` of both sides, and the `bootstrap` of the peer
+fn events(local: &Identity, remote: &Identity) -> Vec<SnifferEvent> {
+    let (remote_cm, local_cm, key, nonces) = handshake(local, remote);
+    let NoncePair {
+        local: mut local_nonce,
+        remote: mut remote_nonce,
+    } = nonces;
+
+    vec![
+        SnifferEvent::Bind {
+            id: event_id(3),
+            address: SocketAddr::from(([0, 0, 0, 0], PORT)),
+        },
+        SnifferEvent::Accept {
+            id: event_id(5),
+            listen_on_fd: 3,
+            address: REMOTE_ADDR.parse().unwrap(),
+        },
+        data(true, remote_cm),
+        data(false, local_cm),
+        data(true, encrypt(&key, &mut remote_nonce, &[0, 0])),
+        data(false, encrypt(&key, &mut local_nonce, &[0, 0])),
+        data(true, encrypt(&key, &mut remote_nonce, &[0x00])),
+        data(false, encrypt(&key, &mut local_nonce, &[0x00])),
+        data(true, encrypt(&key, &mut remote_nonce, &[0, 0, 0, 2, 0, 2])),
+        SnifferEvent::Close { id: event_id(5) },
+    ]
+}
+
+/// Run the recorder on the events, `p2p` is the rest of the `p2p` section of the config
+fn record(db_path: &Path, p2p: &str, local: &Identity, remote: &Identity) {
+    let config = format!(
+        "[[nodes]]\nname = \"tezedge\"\ndb = {:?}\n\n[nodes.p2p]\nidentity = {:?}\nport = {}\n{}",
+        db_path,
+        concat!(env!("CARGO_MANIFEST_DIR"), "/identity_i.json"),
+        PORT,
+        p2p,
+    );
+
+    let running = Arc::new(AtomicBool::new(true));
+    let mut system = System::<Db>::from_toml(&config).unwrap();
+    system.run_dbs(running.clone());
+
+    let (source, tx) = ChannelCapture::new();
+    for event in events(local, remote) {
+        tx.send(event).unwrap();
+    }
+    // the source is exhausted after the events
+    drop(tx);
+    main_loop::run_with_source(source, &mut system, running.clone()).unwrap();
+    running.store(false, Ordering::Relaxed);
+    system.join();
+}
+
+#[test]
+fn channel_to_db() {
+    let local = identity(include_str!("../identity_i.json"));
+    let remote = identity(include_str!("../identity_r.json"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("db");
+    record(&db_path, "", &local, &remote);
+
+    let db = Db::open(&db_path, false, None, None, ChunkPayload::Both).unwrap();
+
+    let connections = db.fetch_connections(&ConnectionsFilter::default()).unwrap();
+    assert_eq!(connections.len(), 1);
+    let (cn_key, cn) = &connections[0];
+    assert!(cn.initiator().incoming());
+    assert_eq!(cn.remote_addr(), REMOTE_ADDR.parse::<SocketAddr>().unwrap());
+    assert_eq!(cn.peer_pk(), remote.public_key);
+    assert!(cn.lifecycle().closed_at.is_some());
+
+    let by_addr = |remote_addr: &str| {
+        let filter = ConnectionsFilter {
+            remote_addr: Some(remote_addr.to_string()),
+            ..ConnectionsFilter::default()
+        };
+        db.fetch_connections(&filter).unwrap().len()
+    };
+    assert_eq!(by_addr("10.0.0.0/8"), 1);
+    assert_eq!(by_addr("192.168.0.0/16"), 0);
+
+    let mut messages = db.fetch_messages(&MessagesFilter::default()).unwrap();
+    messages.sort_by_key(|m| m.id);
+    let categories = messages
+        .iter()
+        .map(|m| (m.category.clone(), m.incoming))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        categories,
+        vec![
+            (MessageCategory::Connection, true),
+            (MessageCategory::Connection, false),
+            (MessageCategory::Meta, true),
+            (MessageCategory::Meta, false),
+            (MessageCategory::Ack, true),
+            (MessageCategory::Ack, false),
+            (MessageCategory::P2p, true),
+        ],
+    );
+    assert_eq!(messages[6].kind, Some(MessageKind::Bootstrap));
+
+    let filter = ExportFilter {
+        cn: Some(cn_key.to_string()),
+        from: None,
+        to: None,
+        limit: None,
+    };
+    let exported = db.fetch_connection_chunks(&filter).unwrap();
+    assert_eq!(exported.len(), 1);
+    let chunks = &exported[0].chunks;
+    assert_eq!(chunks.len(), 7);
+    let (key, value, ty) = chunks
+        .iter()
+        .find(|(key, _, _)| key.sender.incoming() && key.counter == 3)
+        .unwrap();
+    assert_eq!(key.cn_id.to_string(), cn_key.to_string());
+    assert_eq!(value.plain, [0, 0, 0, 2, 0, 2]);
+    assert!(matches!(ty, Some(MessageType::P2p(MessageKind::Bootstrap))));
+}
+
+#[test]
+fn keylog() {
+    let local = identity(include_str!("../identity_i.json"));
+    let remote = identity(include_str!("../identity_r.json"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("db");
+    let keylog_path = dir.path().join("keylog");
+    let p2p = format!("keylog = {:?}\n", keylog_path);
+    record(&db_path, &p2p, &local, &remote);
+
+    // only the owner can read the secrets
+    let mode = fs::metadata(&keylog_path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let db = Db::open(&db_path, false, None, None, ChunkPayload::Both).unwrap();
+    let connections = db.fetch_connections(&ConnectionsFilter::default()).unwrap();
+    assert_eq!(connections.len(), 1);
+    let (cn_key, _) = &connections[0];
+
+    let (_, _, key, nonces) = handshake(&local, &remote);
+    let expected = format!(
+        "TEZOS_SESSION {} {} remote {} {} {}",
+        cn_key,
+        REMOTE_ADDR,
+        hex::encode(nonces.local.get_bytes()),
+        hex::encode(nonces.remote.get_bytes()),
+        hex::encode(key.as_ref()),
+    );
+    let keylog = fs::read_to_string(&keylog_path).unwrap();
+    assert_eq!(keylog.lines().collect::<Vec<_>>(), vec![expected]);
+}
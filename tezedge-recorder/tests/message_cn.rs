@@ -0,0 +1,76 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use tezedge_recorder::{
+    common::{Initiator, Sender},
+    database::{rocks::Db, Database, DatabaseFetch, DatabaseNew, MessagesFilter},
+    tables::{chunk::ChunkPayload, connection, message::MessageBuilder},
+};
+
+fn ids(db: &Db, filter: &MessagesFilter) -> Vec<u64> {
+    db.fetch_messages(filter)
+        .unwrap()
+        .into_iter()
+        .map(|m| m.id)
+        .collect()
+}
+
+#[test]
+fn paging() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path(), false, None, None, ChunkPayload::Both).unwrap();
+
+    // the peer reconnects from the same address, the messages are interleaved
+    let first = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        1617005682_000000000,
+    );
+    let second = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        1617005683_000000000,
+    );
+    db.store_connection(first.clone());
+    db.store_connection(second.clone());
+    for i in 0..10 {
+        let cn = if i % 2 == 0 { &first } else { &second };
+        let sender = Sender::new(i % 4 < 2);
+        let timestamp = 1617005684_000000000 + i * 1_000_000;
+        let message = MessageBuilder::connection_message().build(&sender, cn, &[0; 32], timestamp);
+        db.store_message(message);
+    }
+
+    let filter = |cursor: Option<u64>, forward: bool| MessagesFilter {
+        cn: Some(second.key().to_string()),
+        cursor,
+        limit: Some(2),
+        direction: Some(if forward { "forward" } else { "backward" }.to_string()),
+        ..MessagesFilter::default()
+    };
+
+    // from the newest, the cursor is inclusive
+    assert_eq!(ids(&db, &filter(None, false)), [9, 7]);
+    assert_eq!(ids(&db, &filter(Some(6), false)), [5, 3]);
+    assert_eq!(ids(&db, &filter(Some(2), false)), [1]);
+
+    // from the oldest
+    assert_eq!(ids(&db, &filter(None, true)), [1, 3]);
+    assert_eq!(ids(&db, &filter(Some(4), true)), [5, 7]);
+    assert_eq!(ids(&db, &filter(Some(8), true)), [9]);
+
+    // intersects with the other filters
+    let filter = MessagesFilter {
+        cn: Some(first.key().to_string()),
+        incoming: Some(true),
+        direction: Some("forward".to_string()),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(ids(&db, &filter), [0, 4, 8]);
+
+    let filter = MessagesFilter {
+        cn: Some("not a connection".to_string()),
+        ..MessagesFilter::default()
+    };
+    assert!(db.fetch_messages(&filter).is_err());
+}
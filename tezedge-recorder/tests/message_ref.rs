@@ -0,0 +1,143 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use crypto::hash::HashType;
+use tezedge_recorder::{
+    common::{Initiator, Sender},
+    database::{rocks::Db, Database, DatabaseFetch, DatabaseNew, MessagesFilter},
+    tables::{
+        chunk::ChunkPayload,
+        connection,
+        message::{MessageBuilder, Refs},
+    },
+};
+
+const BLOCK_A: [u8; 32] = [0xa; 32];
+const BLOCK_B: [u8; 32] = [0xb; 32];
+const OPERATION: [u8; 32] = [0xc; 32];
+
+fn store(db: &Db, cn: &connection::Item, index: u64, sender: Sender, refs: Refs) {
+    let timestamp = 1617005683_000000000 + index * 1_000_000;
+    let mut message = MessageBuilder::connection_message().build(&sender, cn, &[0; 32], timestamp);
+    message.refs = refs;
+    db.store_message(message);
+}
+
+fn ids(db: &Db, filter: MessagesFilter) -> Vec<u64> {
+    db.fetch_messages(&filter)
+        .unwrap()
+        .into_iter()
+        .map(|m| m.id)
+        .collect()
+}
+
+#[test]
+fn refs() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path(), false, None, None, ChunkPayload::Both).unwrap();
+    let cn = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        1617005682_953928051,
+    );
+    db.store_connection(cn.clone());
+
+    // 0: the header of the block A at the level 10 and the operation in the mempool
+    let refs = Refs {
+        blocks: vec![BLOCK_A],
+        levels: vec![10],
+        operations: vec![OPERATION],
+        points: vec![],
+    };
+    store(&db, &cn, 0, Sender::Remote, refs);
+    // 1: the request of the block B
+    let refs = Refs {
+        blocks: vec![BLOCK_B],
+        ..Refs::default()
+    };
+    store(&db, &cn, 1, Sender::Local, refs);
+    // 2: the header of the block B at the level 11
+    let refs = Refs {
+        blocks: vec![BLOCK_B],
+        levels: vec![11],
+        ..Refs::default()
+    };
+    store(&db, &cn, 2, Sender::Remote, refs);
+    // 3: the operation is relayed
+    let refs = Refs {
+        operations: vec![OPERATION],
+        ..Refs::default()
+    };
+    store(&db, &cn, 3, Sender::Local, refs);
+    // 4: refers to nothing
+    store(&db, &cn, 4, Sender::Remote, Refs::default());
+
+    let block = |hash: [u8; 32]| Some(HashType::BlockHash.hash_to_b58check(&hash).unwrap());
+    let operation = Some(
+        HashType::OperationHash
+            .hash_to_b58check(&OPERATION)
+            .unwrap(),
+    );
+
+    let filter = MessagesFilter {
+        block_hash: block(BLOCK_B),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(ids(&db, filter), [2, 1]);
+
+    // intersects with the other filters
+    let filter = MessagesFilter {
+        block_hash: block(BLOCK_B),
+        incoming: Some(true),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(ids(&db, filter), [2]);
+
+    let filter = MessagesFilter {
+        operation_hash: operation.clone(),
+        direction: Some("forward".to_string()),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(ids(&db, filter), [0, 3]);
+
+    // the cursor is inclusive
+    let filter = MessagesFilter {
+        operation_hash: operation,
+        cursor: Some(2),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(ids(&db, filter), [0]);
+
+    let filter = MessagesFilter {
+        level_from: Some(10),
+        level_to: Some(11),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(ids(&db, filter), [2, 0]);
+
+    let filter = MessagesFilter {
+        level_from: Some(11),
+        level_to: Some(1000),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(ids(&db, filter), [2]);
+
+    // the range is required and bounded
+    let filter = MessagesFilter {
+        level_from: Some(10),
+        ..MessagesFilter::default()
+    };
+    assert!(db.fetch_messages(&filter).is_err());
+    let filter = MessagesFilter {
+        level_from: Some(0),
+        level_to: Some(1024),
+        ..MessagesFilter::default()
+    };
+    assert!(db.fetch_messages(&filter).is_err());
+
+    let filter = MessagesFilter {
+        block_hash: Some("not a hash".to_string()),
+        ..MessagesFilter::default()
+    };
+    assert!(db.fetch_messages(&filter).is_err());
+}
@@ -0,0 +1,76 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use tezedge_recorder::{
+    common::{Initiator, Sender},
+    database::{rocks::Db, Database, DatabaseFetch, DatabaseNew, MessagesFilter},
+    tables::{chunk::ChunkPayload, connection, message::MessageBuilder},
+};
+
+fn sizes(db: &Db, filter: MessagesFilter) -> Vec<u64> {
+    db.fetch_messages(&filter)
+        .unwrap()
+        .into_iter()
+        .map(|m| m.size)
+        .collect()
+}
+
+#[test]
+fn filter_and_sort() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path(), false, None, None, ChunkPayload::Both).unwrap();
+    let cn = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        1617005682_953928051,
+    );
+    db.store_connection(cn.clone());
+    for (i, &size) in [10, 50, 20, 40, 30].iter().enumerate() {
+        let timestamp = 1617005683_000000000 + i as u64 * 1_000_000;
+        let message = MessageBuilder::connection_message().build(
+            &Sender::Remote,
+            &cn,
+            &vec![0; size],
+            timestamp,
+        );
+        db.store_message(message);
+    }
+
+    // in order of recording, the newest first
+    let filter = MessagesFilter {
+        limit: Some(2),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(sizes(&db, filter), [30, 40]);
+
+    // the largest of all messages, not of the newest ones
+    let filter = MessagesFilter {
+        limit: Some(2),
+        sort: Some("size".to_string()),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(sizes(&db, filter), [50, 40]);
+
+    // the largest of the messages in the range
+    let filter = MessagesFilter {
+        limit: Some(2),
+        size_max: Some(35),
+        sort: Some("size".to_string()),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(sizes(&db, filter), [30, 20]);
+
+    let filter = MessagesFilter {
+        size_min: Some(20),
+        size_max: Some(40),
+        direction: Some("forward".to_string()),
+        ..MessagesFilter::default()
+    };
+    assert_eq!(sizes(&db, filter), [20, 40, 30]);
+
+    let filter = MessagesFilter {
+        sort: Some("time".to_string()),
+        ..MessagesFilter::default()
+    };
+    assert!(db.fetch_messages(&filter).is_err());
+}
@@ -0,0 +1,146 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::path::Path;
+use storage::persistent::Encoder;
+use tezedge_recorder::{
+    common::{Initiator, Sender},
+    database::{rocks::Db, DatabaseNew, DatabaseFetch, ConnectionsFilter},
+    tables::{
+        chunk::{self, ChunkPayload},
+        connection,
+    },
+};
+
+fn open(path: &Path) -> Db {
+    Db::open(path, false, None, None, ChunkPayload::Both).unwrap()
+}
+
+/// `[timestamp(8)][bytes_len(8)][net(1)][bytes][plain]`
+fn chunk_v1(timestamp: u64, bytes: &[u8], plain: &[u8]) -> Vec<u8> {
+    let mut v = Vec::new();
+    v.extend_from_slice(&timestamp.to_le_bytes());
+    v.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    v.push(1);
+    v.extend_from_slice(bytes);
+    v.extend_from_slice(plain);
+    v
+}
+
+/// The layout of version 1 is the layout of version 2 without lifecycle
+fn connection_v1(item: connection::Item) -> (Vec<u8>, Vec<u8>) {
+    let (key, value) = item.split();
+    let mut value = value.encode().unwrap();
+    value.truncate(88);
+    (key.encode().unwrap(), value)
+}
+
+/// Write the database as the recorder did before versioning was introduced
+fn write_v1(path: &Path) -> (connection::Key, connection::Key) {
+    let mut opts = rocksdb::Options::default();
+    opts.create_if_missing(true);
+    opts.create_missing_column_families(true);
+    let db = rocksdb::DB::open_cf(
+        &opts,
+        path.join("rocksdb"),
+        &["connection_storage", "chunk_storage"],
+    )
+    .unwrap();
+    let connections = db.cf_handle("connection_storage").unwrap();
+    let chunks = db.cf_handle("chunk_storage").unwrap();
+
+    // the connection with chunks
+    let with_chunks = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        1617005682_953928051,
+    );
+    let with_chunks_key = with_chunks.key();
+    let (key, value) = connection_v1(with_chunks);
+    db.put_cf(connections, key, value).unwrap();
+    let chunk_key = |counter, incoming| {
+        chunk::Key {
+            cn_id: with_chunks_key.clone(),
+            counter,
+            sender: Sender::new(incoming),
+        }
+        .encode()
+        .unwrap()
+    };
+    let value = chunk_v1(1617005683, &[0, 3, 1, 2, 3], &[1, 2, 3]);
+    db.put_cf(chunks, chunk_key(0, true), value).unwrap();
+    let value = chunk_v1(1617005690, &[0, 2, 4, 5], &[4, 5]);
+    db.put_cf(chunks, chunk_key(0, false), value).unwrap();
+
+    // the connection without chunks, the next one in order of keys
+    let without_chunks = connection::Item::new(
+        Initiator::new(false),
+        "10.0.0.3:9732".parse().unwrap(),
+        1617005700_000000001,
+    );
+    let without_chunks_key = without_chunks.key();
+    let (key, value) = connection_v1(without_chunks);
+    db.put_cf(connections, key, value).unwrap();
+
+    (with_chunks_key, without_chunks_key)
+}
+
+fn check(db: &Db, with_chunks: &connection::Key, without_chunks: &connection::Key) {
+    let connections = db.fetch_connections(&ConnectionsFilter::default()).unwrap();
+    assert_eq!(connections.len(), 2);
+
+    let (key, value) = &connections[0];
+    assert_eq!(key.to_string(), with_chunks.to_string());
+    assert!(value.initiator().incoming());
+    assert_eq!(value.remote_addr().port(), 9732);
+    // closed at the time of the last chunk, the reason is unknown
+    assert_eq!(value.lifecycle().closed_at, Some(1617005690_000000000));
+    assert!(value.lifecycle().close_reason.is_none());
+
+    let (key, value) = &connections[1];
+    assert_eq!(key.to_string(), without_chunks.to_string());
+    assert!(!value.initiator().incoming());
+    // no chunks, closed at the time it was opened
+    assert_eq!(value.lifecycle().closed_at, Some(1617005700_000000001));
+
+    let key = chunk::Key {
+        cn_id: with_chunks.clone(),
+        counter: 0,
+        sender: Sender::Remote,
+    };
+    let value = db.fetch_chunk(&key).unwrap().unwrap();
+    assert_eq!(value.timestamp(), 1617005683);
+    assert_eq!(value.bytes, [0, 3, 1, 2, 3]);
+    assert_eq!(value.plain, [1, 2, 3]);
+}
+
+#[test]
+fn from_v1() {
+    let dir = tempfile::tempdir().unwrap();
+    let (with_chunks, without_chunks) = write_v1(dir.path());
+
+    let db = open(dir.path());
+    check(&db, &with_chunks, &without_chunks);
+    drop(db);
+
+    // the version is stored, the migrations are not applied again
+    let db = open(dir.path());
+    check(&db, &with_chunks, &without_chunks);
+}
+
+#[test]
+fn fresh() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path());
+    assert!(db
+        .fetch_connections(&ConnectionsFilter::default())
+        .unwrap()
+        .is_empty());
+    drop(db);
+
+    let db = open(dir.path());
+    assert!(db
+        .fetch_connections(&ConnectionsFilter::default())
+        .unwrap()
+        .is_empty());
+}
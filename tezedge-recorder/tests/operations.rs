@@ -0,0 +1,97 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use crypto::hash::HashType;
+use tezedge_recorder::{
+    common::{Initiator, Sender},
+    database::{rocks::Db, Database, DatabaseFetch, DatabaseNew, OperationsFilter},
+    tables::{
+        chunk::ChunkPayload,
+        connection,
+        message::{MessageBuilder, Refs},
+    },
+};
+
+const OPERATION: u16 = 0x31;
+const HOUR: u64 = 3_600_000;
+const START: u64 = 1617005683_000;
+
+/// The operation message `[length(4)][tag(2)]` at `timestamp` in milliseconds
+fn store(db: &Db, cn: &connection::Item, sender: Sender, timestamp: u64, hash: [u8; 32]) {
+    let mut plain = [0, 0, 0, 2, 0, 0];
+    plain[4..].clone_from_slice(&OPERATION.to_be_bytes());
+    let mut message = MessageBuilder::peer_message(plain, 0)
+        .link_chunk(plain.len())
+        .ok()
+        .unwrap()
+        .build(&sender, cn, &plain, timestamp * 1_000_000);
+    message.refs = Refs {
+        operations: vec![hash],
+        ..Refs::default()
+    };
+    db.store_message(message);
+}
+
+fn hash_str(hash: [u8; 32]) -> String {
+    HashType::OperationHash.hash_to_b58check(&hash).unwrap()
+}
+
+fn hashes(db: &Db, from: Option<u64>, to: Option<u64>) -> Vec<String> {
+    let filter = OperationsFilter {
+        from,
+        to,
+        ..OperationsFilter::default()
+    };
+    db.fetch_operations(&filter)
+        .unwrap()
+        .into_iter()
+        .map(|r| r.hash)
+        .collect()
+}
+
+#[test]
+fn window() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path(), false, None, None, ChunkPayload::Both).unwrap();
+    let cn = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        1617005682_953928051,
+    );
+    db.store_connection(cn.clone());
+
+    let (a, b) = ([0xa; 32], [0xb; 32]);
+    store(&db, &cn, Sender::Remote, START, a);
+    store(&db, &cn, Sender::Local, START + 1000, a);
+    store(&db, &cn, Sender::Remote, START + 2 * HOUR, b);
+
+    // the hour before the newest message
+    assert_eq!(hashes(&db, None, None), [hash_str(b)]);
+
+    // the missing end is an hour away from the other one
+    assert_eq!(hashes(&db, Some(START), None), [hash_str(a)]);
+    assert_eq!(hashes(&db, None, Some(START + 2 * HOUR + 1)), [hash_str(b)]);
+    assert_eq!(
+        hashes(&db, Some(START + 1), Some(START + HOUR)),
+        [hash_str(a)]
+    );
+
+    // the range is at most an hour
+    let filter = OperationsFilter {
+        from: Some(START),
+        to: Some(START + HOUR + 1),
+        ..OperationsFilter::default()
+    };
+    assert!(db.fetch_operations(&filter).is_err());
+
+    let filter = OperationsFilter {
+        operation_hash: Some(hash_str(a)),
+        ..OperationsFilter::default()
+    };
+    let reports = db.fetch_operations(&filter).unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].messages, 2);
+    assert_eq!(reports[0].relayed_by, 1);
+    assert_eq!(reports[0].first_seen, Some(START * 1_000_000));
+    assert_eq!(reports[0].advertise_delay, Some(1000));
+}
@@ -0,0 +1,215 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use tezedge_recorder::{
+    analytics::pairing::Pairing,
+    common::{Initiator, MessageKind, Sender},
+    database::{rocks::Db, Database, DatabaseFetch, DatabaseNew, RequestsFilter},
+    tables::{
+        chunk::ChunkPayload,
+        connection,
+        message::{self, MessageBuilder},
+    },
+};
+
+fn cn(timestamp: u64) -> connection::Item {
+    connection::Item::new(
+        Initiator::new(false),
+        "10.0.0.2:9732".parse().unwrap(),
+        timestamp,
+    )
+}
+
+/// The p2p message without body, the `timestamp` is in milliseconds
+fn message(
+    cn: &connection::Item,
+    kind: u16,
+    incoming: bool,
+    timestamp: u64,
+    blocks: &[[u8; 32]],
+) -> message::Item {
+    let mut plain = 2u32.to_be_bytes().to_vec();
+    plain.extend_from_slice(&kind.to_be_bytes());
+    let mut item = MessageBuilder::peer_message([0, 0, 0, 2, (kind >> 8) as u8, kind as u8], 3)
+        .link_chunk(plain.len())
+        .ok()
+        .unwrap()
+        .build(&Sender::new(incoming), cn, &plain, timestamp * 1_000_000);
+    item.refs.blocks = blocks.to_vec();
+    item
+}
+
+const GET_CURRENT_BRANCH: u16 = 0x10;
+const CURRENT_BRANCH: u16 = 0x11;
+const GET_BLOCK_HEADERS: u16 = 0x20;
+const BLOCK_HEADER: u16 = 0x21;
+const BOOTSTRAP: u16 = 0x02;
+
+#[test]
+fn request_response() {
+    let cn = cn(1617005682_000000000);
+    let mut pairing = Pairing::default();
+
+    // the local node requests, the peer responds
+    let records = pairing.message(10, &message(&cn, GET_CURRENT_BRANCH, false, 1000, &[]));
+    assert_eq!(records.len(), 1);
+    let (key, value) = &records[0];
+    assert_eq!((key.index, key.part), (10, 0));
+    assert_eq!(value.kind, MessageKind::GetCurrentBranch);
+    assert!(!value.incoming);
+    assert!(value.response.is_none());
+
+    // not a response
+    assert!(pairing
+        .message(11, &message(&cn, BOOTSTRAP, true, 1010, &[]))
+        .is_empty());
+    // the response in the same direction as the request does not answer it
+    assert!(pairing
+        .message(12, &message(&cn, CURRENT_BRANCH, false, 1020, &[]))
+        .is_empty());
+
+    let records = pairing.message(13, &message(&cn, CURRENT_BRANCH, true, 1250, &[]));
+    assert_eq!(records.len(), 1);
+    let (key, value) = &records[0];
+    assert_eq!(key.index, 10);
+    assert_eq!(value.response, Some(13));
+    assert_eq!(value.rtt, Some(250));
+    assert!(!value.unanswered);
+
+    // nothing is pending anymore
+    assert!(pairing.close(&cn.key()).is_empty());
+}
+
+#[test]
+fn by_hash() {
+    let cn = cn(1617005682_000000000);
+    let mut pairing = Pairing::default();
+
+    let (a, b) = ([1; 32], [2; 32]);
+    let records = pairing.message(0, &message(&cn, GET_BLOCK_HEADERS, true, 1000, &[a, b]));
+    // a record for each requested block
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].0.part, 0);
+    assert_eq!(records[1].0.part, 1);
+
+    // the second block arrives first
+    let records = pairing.message(1, &message(&cn, BLOCK_HEADER, false, 1100, &[b]));
+    assert_eq!(records.len(), 1);
+    assert_eq!((records[0].0.index, records[0].0.part), (0, 1));
+    assert_eq!(records[0].1.rtt, Some(100));
+
+    // the block is not requested
+    assert!(pairing
+        .message(2, &message(&cn, BLOCK_HEADER, false, 1150, &[[3; 32]]))
+        .is_empty());
+
+    let records = pairing.message(3, &message(&cn, BLOCK_HEADER, false, 1200, &[a]));
+    assert_eq!(records.len(), 1);
+    assert_eq!((records[0].0.index, records[0].0.part), (0, 0));
+    assert_eq!(records[0].1.response, Some(3));
+}
+
+#[test]
+fn close() {
+    let first = cn(1617005682_000000000);
+    let second = cn(1617005683_000000000);
+    let mut pairing = Pairing::default();
+
+    pairing.message(0, &message(&first, GET_CURRENT_BRANCH, false, 1000, &[]));
+    pairing.message(1, &message(&second, GET_CURRENT_BRANCH, false, 1000, &[]));
+    pairing.message(2, &message(&first, GET_CURRENT_BRANCH, true, 1000, &[]));
+
+    // the response answers the request of its own connection only
+    let records = pairing.message(3, &message(&second, CURRENT_BRANCH, true, 1100, &[]));
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].0.index, 1);
+
+    let records = pairing.close(&first.key());
+    assert_eq!(records.len(), 2);
+    assert!(records.iter().all(|(_, value)| value.unanswered));
+    assert_eq!(records[0].0.index, 0);
+    assert_eq!(records[1].0.index, 2);
+
+    // closed twice
+    assert!(pairing.close(&first.key()).is_empty());
+    assert!(pairing.close(&second.key()).is_empty());
+}
+
+#[test]
+fn too_many_pending() {
+    let cn = cn(1617005682_000000000);
+    let mut pairing = Pairing::default();
+
+    for index in 0..(Pairing::MAX_PENDING as u64) {
+        let records = pairing.message(index, &message(&cn, GET_CURRENT_BRANCH, false, 1000, &[]));
+        assert_eq!(records.len(), 1);
+    }
+    // the oldest request is dropped as unanswered
+    let index = Pairing::MAX_PENDING as u64;
+    let records = pairing.message(index, &message(&cn, GET_CURRENT_BRANCH, false, 1000, &[]));
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[1].0.index, 0);
+    assert!(records[1].1.unanswered);
+
+    assert_eq!(pairing.close(&cn.key()).len(), Pairing::MAX_PENDING);
+}
+
+#[test]
+fn restore() {
+    let cn = cn(1617005682_000000000);
+    let request = message(&cn, GET_BLOCK_HEADERS, false, 1000, &[[1; 32], [2; 32]]);
+
+    let mut pairing = Pairing::default();
+    let records = pairing.message(5, &request);
+    assert_eq!(records.len(), 2);
+
+    // the recorder is restarted, the pending requests are put back
+    let mut pairing = Pairing::default();
+    for (key, value) in records {
+        pairing.restore(key, value, &request);
+    }
+    let records = pairing.message(6, &message(&cn, BLOCK_HEADER, true, 1300, &[[2; 32]]));
+    assert_eq!(records.len(), 1);
+    assert_eq!((records[0].0.index, records[0].0.part), (5, 1));
+    assert_eq!(records[0].1.rtt, Some(300));
+
+    let records = pairing.close(&cn.key());
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].0.part, 0);
+}
+
+#[test]
+fn stored_with_message() {
+    let dir = tempfile::tempdir().unwrap();
+    let cn = cn(1617005682_000000000);
+    let requests = |db: &Db| {
+        db.fetch_requests(&RequestsFilter::default())
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.id, r.response))
+            .collect::<Vec<_>>()
+    };
+
+    let db = Db::open(dir.path().join("a"), false, None, None, ChunkPayload::Both).unwrap();
+    db.store_connection(cn.clone());
+    db.store_message(message(&cn, GET_CURRENT_BRANCH, false, 1000, &[]));
+    assert_eq!(requests(&db), [(0, None)]);
+    db.store_message(message(&cn, CURRENT_BRANCH, true, 1100, &[]));
+    assert_eq!(requests(&db), [(0, Some(1))]);
+
+    // the response evicts the request message, its record does not appear again
+    let db = Db::open(
+        dir.path().join("b"),
+        false,
+        None,
+        Some(2),
+        ChunkPayload::Both,
+    )
+    .unwrap();
+    db.store_connection(cn.clone());
+    db.store_message(message(&cn, GET_CURRENT_BRANCH, false, 1000, &[]));
+    db.store_message(message(&cn, BOOTSTRAP, true, 1010, &[]));
+    assert_eq!(requests(&db), [(0, None)]);
+    db.store_message(message(&cn, CURRENT_BRANCH, true, 1100, &[]));
+    assert!(requests(&db).is_empty());
+}
@@ -0,0 +1,254 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    net::{SocketAddr, SocketAddrV4},
+    path::Path,
+    sync::atomic::AtomicBool,
+};
+use bpf_recorder::SnifferEvent;
+use tezedge_recorder::capture::{CaptureSource, PcapCapture};
+
+const SYN: u8 = 0x02;
+const FIN: u8 = 0x01;
+const ACK: u8 = 0x10;
+const PSH: u8 = 0x08;
+
+/// Raw ipv4 packet with tcp segment, the checksums are not verified by the reader
+fn packet(src: SocketAddrV4, dst: SocketAddrV4, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let mut tcp = Vec::new();
+    tcp.extend_from_slice(&src.port().to_be_bytes());
+    tcp.extend_from_slice(&dst.port().to_be_bytes());
+    tcp.extend_from_slice(&seq.to_be_bytes());
+    tcp.extend_from_slice(&0u32.to_be_bytes());
+    tcp.push(5 << 4);
+    tcp.push(flags);
+    tcp.extend_from_slice(&[0xff, 0xff, 0, 0, 0, 0]);
+    tcp.extend_from_slice(payload);
+
+    let mut ip = vec![0x45, 0];
+    ip.extend_from_slice(&((tcp.len() + 20) as u16).to_be_bytes());
+    ip.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+    ip.extend_from_slice(&src.ip().octets());
+    ip.extend_from_slice(&dst.ip().octets());
+    ip.extend_from_slice(&tcp);
+    ip
+}
+
+/// Classic pcap, little endian, microseconds, raw ip link type
+fn write_pcap(path: &Path, packets: &[Vec<u8>]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(&0xa1b2c3d4u32.to_le_bytes())?;
+    file.write_all(&2u16.to_le_bytes())?;
+    file.write_all(&4u16.to_le_bytes())?;
+    file.write_all(&[0; 8])?;
+    file.write_all(&0xffffu32.to_le_bytes())?;
+    file.write_all(&101u32.to_le_bytes())?;
+    for (i, packet) in packets.iter().enumerate() {
+        file.write_all(&1617005682u32.to_le_bytes())?;
+        file.write_all(&(i as u32 * 1000).to_le_bytes())?;
+        file.write_all(&(packet.len() as u32).to_le_bytes())?;
+        file.write_all(&(packet.len() as u32).to_le_bytes())?;
+        file.write_all(packet)?;
+    }
+    file.flush()
+}
+
+fn read_all<S>(mut source: S) -> Vec<SnifferEvent>
+where
+    S: CaptureSource,
+{
+    let running = AtomicBool::new(true);
+    let mut events = vec![];
+    loop {
+        match source.read_events(&running) {
+            Ok(batch) => events.extend(batch),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(error) => panic!("{}", error),
+        }
+    }
+    events
+}
+
+#[test]
+fn reassembly() {
+    let local = "10.0.0.1:9732".parse::<SocketAddrV4>().unwrap();
+    let remote = "10.0.0.2:40000".parse::<SocketAddrV4>().unwrap();
+    let other = "10.0.0.3:40001".parse::<SocketAddrV4>().unwrap();
+    let stream = b"hello, reassembled tcp stream";
+    let isn = 1000u32;
+    let segment = |offset: usize, length: usize| {
+        let seq = isn + 1 + offset as u32;
+        let payload = &stream[offset..(offset + length)];
+        packet(remote, local, seq, PSH | ACK, payload)
+    };
+
+    let packets = vec![
+        packet(remote, local, isn, SYN, &[]),
+        packet(local, remote, 5000, SYN | ACK, &[]),
+        // retransmitted syn does not open another connection
+        packet(remote, local, isn, SYN, &[]),
+        segment(0, 6),
+        // ahead of the gap, kept until the gap is filled
+        segment(12, 6),
+        // retransmission, partially overlaps with the delivered data
+        segment(3, 7),
+        packet(local, remote, 5001, PSH | ACK, b"ok"),
+        // fills the gap
+        segment(10, 2),
+        // duplicate of the delivered data
+        segment(12, 6),
+        segment(18, stream.len() - 18),
+        // the port is not watched
+        packet(other, "10.0.0.1:8080".parse().unwrap(), 1, SYN, &[]),
+        packet(remote, local, isn + 1 + stream.len() as u32, FIN | ACK, &[]),
+        packet(local, remote, 5003, FIN | ACK, &[]),
+    ];
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capture.pcap");
+    write_pcap(&path, &packets).unwrap();
+
+    let mut source = PcapCapture::open(&path, vec![]).unwrap();
+    source.watch_port(9732).unwrap();
+    let events = read_all(source);
+
+    let mut incoming_data = vec![];
+    let mut outgoing_data = vec![];
+    let mut accepted = 0;
+    let mut closed = 0;
+    let mut socket_id = None;
+    for event in &events {
+        match event {
+            SnifferEvent::Bind { address, .. } => assert_eq!(address.port(), 9732),
+            SnifferEvent::Accept { id, address, .. } => {
+                assert_eq!(*address, SocketAddr::V4(remote));
+                socket_id = Some(id.socket_id);
+                accepted += 1;
+            },
+            SnifferEvent::Data {
+                id, data, incoming, ..
+            } => {
+                assert_eq!(Some(id.socket_id), socket_id);
+                if *incoming {
+                    incoming_data.extend_from_slice(data);
+                } else {
+                    outgoing_data.extend_from_slice(data);
+                }
+            },
+            SnifferEvent::Close { id } => {
+                assert_eq!(Some(id.socket_id), socket_id);
+                closed += 1;
+            },
+            SnifferEvent::Connect { .. } => panic!("the connection is incoming"),
+            _ => (),
+        }
+    }
+    assert_eq!(accepted, 1);
+    assert_eq!(closed, 1);
+    assert_eq!(incoming_data, stream.to_vec());
+    assert_eq!(outgoing_data, b"ok".to_vec());
+    assert!(matches!(events.last(), Some(SnifferEvent::Close { .. })));
+}
+
+#[test]
+fn not_captured_beginning() {
+    let local = "10.0.0.1:9732".parse::<SocketAddrV4>().unwrap();
+    let remote = "10.0.0.2:40000".parse::<SocketAddrV4>().unwrap();
+
+    // the connection is opened before the capture started, it cannot be decrypted
+    let packets = vec![
+        packet(remote, local, 1001, PSH | ACK, b"hello"),
+        packet(local, remote, 5001, PSH | ACK, b"ok"),
+    ];
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capture.pcap");
+    write_pcap(&path, &packets).unwrap();
+
+    let mut source = PcapCapture::open(&path, vec![]).unwrap();
+    source.watch_port(9732).unwrap();
+    let events = read_all(source);
+    assert_eq!(events.len(), 1);
+    assert!(matches!(&events[0], SnifferEvent::Bind { .. }));
+}
+
+#[test]
+fn not_pcap() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capture.pcap");
+    File::create(&path)
+        .unwrap()
+        .write_all(b"not a capture")
+        .unwrap();
+    let error = PcapCapture::open(&path, vec![]).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn local_ip() {
+    let local = "10.0.0.1:9732".parse::<SocketAddrV4>().unwrap();
+    let remote = "10.0.0.2:40000".parse::<SocketAddrV4>().unwrap();
+    let packets = vec![
+        // the port is not watched, the connection is not the node's
+        packet(remote, "10.0.0.1:8080".parse().unwrap(), 1, SYN, &[]),
+        // the port is watched, but the host is not the node's
+        packet(remote, "10.0.0.3:9732".parse().unwrap(), 1, SYN, &[]),
+        packet(remote, local, 1, SYN, &[]),
+        // initiated by the node's host, the remote port does not matter
+        packet(
+            "10.0.0.1:50000".parse().unwrap(),
+            "10.0.0.4:8732".parse().unwrap(),
+            1,
+            SYN,
+            &[],
+        ),
+    ];
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capture.pcap");
+    write_pcap(&path, &packets).unwrap();
+
+    let local_ips = vec!["10.0.0.1".parse().unwrap()];
+    let mut source = PcapCapture::open(&path, local_ips).unwrap();
+    source.watch_port(9732).unwrap();
+    let connections = read_all(source)
+        .into_iter()
+        .filter_map(|event| match event {
+            SnifferEvent::Accept { address, .. } => Some((address, true)),
+            SnifferEvent::Connect { address, .. } => Some((address, false)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        connections,
+        vec![
+            (SocketAddr::V4(remote), true),
+            ("10.0.0.4:8732".parse().unwrap(), false),
+        ],
+    );
+}
+
+#[test]
+fn too_long_packet() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capture.pcap");
+    write_pcap(&path, &[]).unwrap();
+    // the length of the packet is garbage, the packet itself is absent
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(&1617005682u32.to_le_bytes()).unwrap();
+    file.write_all(&0u32.to_le_bytes()).unwrap();
+    file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+    file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+    drop(file);
+
+    let mut source = PcapCapture::open(&path, vec![]).unwrap();
+    let running = AtomicBool::new(true);
+    let error = source.read_events(&running).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
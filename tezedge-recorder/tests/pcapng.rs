@@ -0,0 +1,189 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::atomic::AtomicBool,
+};
+use bpf_recorder::{SnifferEvent, SocketId};
+use tezedge_recorder::{
+    capture::{CaptureSource, PcapCapture},
+    common::{Initiator, MessageType, MessageKind, Sender},
+    database::ConnectionChunks,
+    export::pcapng,
+    tables::{chunk, connection},
+};
+
+const PORT: u16 = 9732;
+
+fn connection_chunks(
+    incoming: bool,
+    remote_addr: &str,
+    timestamp: u64,
+    chunks: &[(bool, Vec<u8>)],
+) -> ConnectionChunks {
+    let connection = connection::Item::new(
+        Initiator::new(incoming),
+        remote_addr.parse().unwrap(),
+        timestamp,
+    );
+    let mut counters = [0, 0];
+    let chunks = chunks
+        .iter()
+        .enumerate()
+        .map(|(i, (incoming, bytes))| {
+            let counter = counters[*incoming as usize];
+            counters[*incoming as usize] += 1;
+            let plain = bytes[2..].to_vec();
+            let sender = Sender::new(*incoming);
+            let mut item =
+                chunk::Item::new(connection.key(), sender, counter, bytes.clone(), plain);
+            // a second between the chunks
+            item.set_timestamp(timestamp + (i as u64 + 1) * 1_000_000_000);
+            let (key, value) = item.split();
+            let ty = match counter {
+                0 => MessageType::Connection,
+                _ => MessageType::P2p(MessageKind::Bootstrap),
+            };
+            (key, value, Some(ty))
+        })
+        .collect();
+    ConnectionChunks {
+        connection,
+        chunks,
+        messages: vec![],
+    }
+}
+
+fn chunk_bytes(length: usize, fill: u8) -> Vec<u8> {
+    let mut bytes = ((length - 2) as u16).to_be_bytes().to_vec();
+    bytes.resize(length, fill);
+    bytes
+}
+
+#[test]
+fn read_back() {
+    let incoming = connection_chunks(
+        true,
+        "10.0.0.2:40000",
+        1617005682_953928051,
+        &[
+            (true, chunk_bytes(100, 1)),
+            (false, chunk_bytes(100, 2)),
+            (true, chunk_bytes(20, 3)),
+            (false, chunk_bytes(30, 4)),
+        ],
+    );
+    let outgoing = connection_chunks(
+        false,
+        "[2001:db8::2]:40000",
+        1617005700_000000000,
+        &[
+            (false, chunk_bytes(100, 5)),
+            (true, chunk_bytes(100, 6)),
+            // larger than the segment, split into several
+            (false, chunk_bytes(0xf000, 7)),
+        ],
+    );
+    let connections = vec![incoming, outgoing];
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("export.pcapng");
+    pcapng::write(fs::File::create(&path).unwrap(), &connections, PORT).unwrap();
+
+    let content = fs::read(&path).unwrap();
+    assert_eq!(content[..4], 0x0a0d0d0au32.to_le_bytes());
+    assert_eq!(content.len() % 4, 0);
+    let first_key = connections[0].chunks[0].0.to_string();
+    let comment = format!("chunk: {}, message: Connection", first_key);
+    assert!(content
+        .windows(comment.len())
+        .any(|w| w == comment.as_bytes()));
+
+    // the node's side is the unspecified address
+    let local_ips = vec![
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    ];
+    let mut source = PcapCapture::open(&path, local_ips).unwrap();
+    source.watch_port(PORT).unwrap();
+
+    let running = AtomicBool::new(true);
+    let mut opened = HashMap::<SocketId, (SocketAddr, bool)>::new();
+    let mut data = HashMap::<(SocketId, bool), Vec<u8>>::new();
+    let mut first_data = HashMap::<SocketId, u64>::new();
+    let mut closed = vec![];
+    loop {
+        let events = match source.read_events(&running) {
+            Ok(events) => events,
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(error) => panic!("{}", error),
+        };
+        for event in events {
+            match event {
+                SnifferEvent::Accept { id, address, .. } => {
+                    opened.insert(id.socket_id, (address, true));
+                },
+                SnifferEvent::Connect { id, address } => {
+                    opened.insert(id.socket_id, (address, false));
+                },
+                SnifferEvent::Data {
+                    id,
+                    data: d,
+                    incoming,
+                    ..
+                } => {
+                    first_data.entry(id.socket_id).or_insert(id.ts_finish());
+                    data.entry((id.socket_id, incoming))
+                        .or_default()
+                        .extend_from_slice(&d);
+                },
+                SnifferEvent::Close { id } => closed.push(id.socket_id),
+                _ => (),
+            }
+        }
+    }
+    assert_eq!(opened.len(), 2);
+    assert_eq!(closed.len(), 2);
+
+    for c in &connections {
+        let remote = c.connection.remote_addr;
+        let incoming = c.connection.initiator.incoming();
+        let (&socket_id, _) = opened
+            .iter()
+            .find(|(_, (address, i))| *address == remote && *i == incoming)
+            .expect("the connection is in the capture");
+        assert!(closed.contains(&socket_id));
+
+        for sender in [false, true].iter().cloned() {
+            let expected = c
+                .chunks
+                .iter()
+                .filter(|(key, ..)| key.sender.incoming() == sender)
+                .flat_map(|(_, value, _)| value.bytes.clone())
+                .collect::<Vec<_>>();
+            assert_eq!(data[&(socket_id, sender)], expected);
+        }
+
+        // the packet has the time of the chunk
+        let first_chunk = c.chunks[0].1.timestamp() * 1_000_000_000;
+        assert_eq!(first_data[&socket_id], first_chunk);
+    }
+}
+
+#[test]
+fn empty() {
+    let mut content = vec![];
+    pcapng::write(&mut content, &[], PORT).unwrap();
+    assert_eq!(content[..4], 0x0a0d0d0au32.to_le_bytes());
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("export.pcapng");
+    fs::write(&path, &content).unwrap();
+    let mut source = PcapCapture::open(&path, vec![]).unwrap();
+    let running = AtomicBool::new(true);
+    let error = source.read_events(&running).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,111 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::path::Path;
+use tezedge_recorder::{
+    common::{Initiator, Sender},
+    database::{rocks::Db, ConnectionsFilter, Database, DatabaseFetch, DatabaseNew, PeersFilter},
+    tables::{chunk::ChunkPayload, connection, message::MessageBuilder, peer},
+};
+
+const PEER_PK: [u8; 32] = [1; 32];
+
+fn open(path: &Path) -> Db {
+    Db::open(path, false, None, None, ChunkPayload::Both).unwrap()
+}
+
+/// The bootstrap message `[length(4)][tag(2)]`
+fn store_bootstrap(db: &Db, cn: &connection::Item, counter: u64, sender: Sender) {
+    let plain = [0, 0, 0, 2, 0, 2];
+    let mut header = [0; 6];
+    header.clone_from_slice(&plain);
+    let message = MessageBuilder::peer_message(header, counter)
+        .link_chunk(plain.len())
+        .ok()
+        .unwrap()
+        .build(
+            &sender,
+            cn,
+            &plain,
+            1617005683_000000000 + counter * 1_000_000,
+        );
+    db.store_message(message);
+}
+
+fn peers(db: &Db, peer_id: Option<String>) -> Vec<serde_json::Value> {
+    let filter = PeersFilter {
+        peer_id,
+        ..PeersFilter::default()
+    };
+    db.fetch_peers(&filter)
+        .unwrap()
+        .into_iter()
+        .map(|(_, value)| serde_json::to_value(&value).unwrap())
+        .collect()
+}
+
+fn message_counts(peer: &serde_json::Value) -> (u64, u64) {
+    peer["message_counts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .fold((0, 0), |(incoming, outgoing), count| {
+            (
+                incoming + count["incoming"].as_u64().unwrap(),
+                outgoing + count["outgoing"].as_u64().unwrap(),
+            )
+        })
+}
+
+#[test]
+fn counted_before_shutdown() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut cn = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        1617005682_953928051,
+    );
+    cn.set_peer_pk(PEER_PK);
+    let peer_id = peer::Key::from_pk(&PEER_PK).unwrap().to_string();
+
+    let db = open(dir.path());
+    db.store_connection(cn.clone());
+    store_bootstrap(&db, &cn, 3, Sender::Remote);
+    store_bootstrap(&db, &cn, 4, Sender::Local);
+    store_bootstrap(&db, &cn, 5, Sender::Remote);
+
+    // the connection is not updated since its messages
+    drop(db);
+    let db = open(dir.path());
+
+    let found = peers(&db, Some(peer_id.clone()));
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0]["connections"], 1);
+    assert_eq!(found[0]["addresses"][0], "10.0.0.2:9732");
+    assert_eq!(message_counts(&found[0]), (2, 1));
+
+    // the messages of the connection are counted once
+    db.update_connection(cn.clone());
+    drop(db);
+    let db = open(dir.path());
+    let found = peers(&db, Some(peer_id.clone()));
+    assert_eq!(message_counts(&found[0]), (2, 1));
+
+    // the connection of the unknown peer is not counted
+    let other = connection::Item::new(
+        Initiator::new(false),
+        "10.0.0.3:9732".parse().unwrap(),
+        1617005690_000000000,
+    );
+    db.store_connection(other.clone());
+    store_bootstrap(&db, &other, 3, Sender::Remote);
+    assert_eq!(peers(&db, None).len(), 1);
+
+    let filter = ConnectionsFilter {
+        peer_id: Some(peer_id),
+        ..ConnectionsFilter::default()
+    };
+    let connections = db.fetch_connections(&filter).unwrap();
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].0.to_string(), cn.key().to_string());
+}
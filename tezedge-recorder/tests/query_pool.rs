@@ -0,0 +1,75 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+use warp::{http::StatusCode, Reply};
+use tezedge_recorder::QueryPool;
+
+async fn run(pool: &QueryPool, query_time: Duration) -> StatusCode {
+    let query = move || {
+        thread::sleep(query_time);
+        Ok::<_, String>(42)
+    };
+    pool.run(query).await.into_response().status()
+}
+
+#[tokio::test]
+async fn done_in_time() {
+    let pool = QueryPool::new(1, Duration::from_secs(1));
+    assert_eq!(run(&pool, Duration::from_millis(10)).await, StatusCode::OK);
+
+    let reply = pool.run(|| Err::<u32, _>("broken")).await;
+    assert_eq!(
+        reply.into_response().status(),
+        StatusCode::INTERNAL_SERVER_ERROR,
+    );
+}
+
+#[tokio::test]
+async fn timeout() {
+    let pool = QueryPool::new(1, Duration::from_millis(100));
+    assert_eq!(
+        run(&pool, Duration::from_millis(300)).await,
+        StatusCode::GATEWAY_TIMEOUT,
+    );
+    // the slow query keeps its slot until it is done
+    assert_eq!(
+        run(&pool, Duration::from_millis(0)).await,
+        StatusCode::GATEWAY_TIMEOUT,
+    );
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(run(&pool, Duration::from_millis(0)).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn cancelled_while_waiting() {
+    let pool = QueryPool::new(1, Duration::from_millis(100));
+    let busy = {
+        let pool = pool.clone();
+        tokio::spawn(async move { run(&pool, Duration::from_millis(300)).await })
+    };
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let started = Arc::new(AtomicBool::new(false));
+    let query = {
+        let started = started.clone();
+        move || {
+            started.store(true, Ordering::Relaxed);
+            Ok::<_, String>(())
+        }
+    };
+    let reply = pool.run(query).await;
+    assert_eq!(reply.into_response().status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(busy.await.unwrap(), StatusCode::GATEWAY_TIMEOUT);
+
+    // the slot is free, but the timed out query never runs
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(!started.load(Ordering::Relaxed));
+}
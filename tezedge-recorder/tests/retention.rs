@@ -0,0 +1,105 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use tezedge_recorder::{
+    common::{Initiator, Sender},
+    database::{
+        rocks::Db, Database, DatabaseFetch, DatabaseNew, DatabaseRetention, MessagesFilter,
+        RetentionPolicy,
+    },
+    tables::{chunk::ChunkPayload, connection, message::MessageBuilder},
+};
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+/// Store the message at `timestamp`, nanoseconds since epoch
+fn store(db: &Db, cn: &connection::Item, timestamp: u64) {
+    let message =
+        MessageBuilder::connection_message().build(&Sender::Remote, cn, &[0; 32], timestamp);
+    db.store_message(message);
+}
+
+fn timestamps(db: &Db) -> Vec<u64> {
+    let filter = MessagesFilter {
+        limit: Some(1000),
+        direction: Some("forward".to_string()),
+        ..MessagesFilter::default()
+    };
+    db.fetch_messages(&filter)
+        .unwrap()
+        .into_iter()
+        .map(|m| {
+            serde_json::to_value(&m).unwrap()["timestamp"]
+                .as_u64()
+                .unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn by_age() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path(), false, None, None, ChunkPayload::Both).unwrap();
+    let cn = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        now() - 7200_000_000_000,
+    );
+    db.store_connection(cn.clone());
+
+    let recent = now();
+    store(&db, &cn, recent - 7200_000_000_000);
+    store(&db, &cn, recent - 3000_000_000_000);
+    store(&db, &cn, recent);
+    assert_eq!(timestamps(&db).len(), 3);
+
+    // nothing to remove without the policy
+    db.apply_retention(&RetentionPolicy::default());
+    assert_eq!(timestamps(&db).len(), 3);
+
+    let policy = RetentionPolicy {
+        message_max_age: Some(3600),
+        ..RetentionPolicy::default()
+    };
+    db.apply_retention(&policy);
+    let left = timestamps(&db);
+    assert_eq!(left.len(), 2);
+    assert!(left
+        .iter()
+        .all(|&t| t / 1_000_000 >= (recent - 3600_000_000_000) / 1_000_000));
+}
+
+#[test]
+fn by_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::open(dir.path(), false, None, None, ChunkPayload::Both).unwrap();
+    let cn = connection::Item::new(
+        Initiator::new(true),
+        "10.0.0.2:9732".parse().unwrap(),
+        now(),
+    );
+    db.store_connection(cn.clone());
+    let start = now();
+    for i in 0..100 {
+        store(&db, &cn, start + i * 1_000_000);
+    }
+
+    // the limit cannot be reached, the oldest messages are removed anyway
+    let policy = RetentionPolicy {
+        max_size: Some(1),
+        ..RetentionPolicy::default()
+    };
+    db.apply_retention(&policy);
+    let left = timestamps(&db);
+    assert!(left.len() <= 90);
+    // the newest message is the last to be removed
+    if let Some(&last) = left.last() {
+        assert_eq!(last / 1_000_000, (start + 99 * 1_000_000) / 1_000_000);
+    }
+}